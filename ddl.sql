@@ -192,6 +192,343 @@ CREATE TABLE lnv2_complete_lightning_payment_succeeded(
 	PRIMARY KEY (log_id, gateway_epoch)
 );
 
+CREATE TABLE federation_config_snapshots(
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	lightning_base_msat BIGINT NOT NULL,
+	lightning_ppm BIGINT NOT NULL,
+	transaction_base_msat BIGINT NOT NULL,
+	transaction_ppm BIGINT NOT NULL,
+	tos_url TEXT,
+	modules TEXT NOT NULL,
+	PRIMARY KEY (federation_id, ts)
+);
+
+CREATE TABLE federation_config_changes(
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	field TEXT NOT NULL,
+	old_value TEXT NOT NULL,
+	new_value TEXT NOT NULL,
+	PRIMARY KEY (federation_id, ts, field)
+);
+
+CREATE TABLE gateway_epochs(
+	gateway_epoch INT PRIMARY KEY,
+	created_at TIMESTAMP NOT NULL,
+	reason TEXT
+);
+
+CREATE TABLE gateway_ledger(
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	module TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	operation_id TEXT,
+	direction TEXT NOT NULL,
+	amount_msat BIGINT NOT NULL,
+	fee_msat BIGINT NOT NULL,
+	market_fee_rate DOUBLE PRECISION,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE lnv1_contract_cancelled(
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	contract_id TEXT NOT NULL,
+	contract_amount BIGINT,
+	reason TEXT,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE lnv1_refund_claimed(
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	contract_id TEXT NOT NULL,
+	amount BIGINT NOT NULL,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE lnv2_contract_cancelled(
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	payment_image TEXT NOT NULL,
+	amount BIGINT,
+	reason TEXT,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE lnv2_refund_claimed(
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	payment_image TEXT NOT NULL,
+	amount BIGINT NOT NULL,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE reconciliation_records(
+	id BIGSERIAL PRIMARY KEY,
+	imported_at TIMESTAMP NOT NULL,
+	source TEXT NOT NULL,
+	external_id TEXT,
+	ts TIMESTAMP NOT NULL,
+	payment_hash TEXT NOT NULL,
+	amount_msat BIGINT NOT NULL,
+	fee_msat BIGINT,
+	status TEXT NOT NULL,
+	matched_federation_id TEXT,
+	matched_log_id BIGINT,
+	matched_gateway_epoch INT
+);
+
+CREATE TABLE node_payments(
+	node TEXT NOT NULL,
+	direction TEXT NOT NULL,
+	payment_hash TEXT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	amount_msat BIGINT NOT NULL,
+	fee_msat BIGINT NOT NULL,
+	status TEXT NOT NULL,
+	collected_at TIMESTAMP NOT NULL,
+	PRIMARY KEY (node, direction, payment_hash)
+);
+
+CREATE TABLE onchain_transactions(
+	federation_id TEXT NOT NULL,
+	txid TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	direction TEXT NOT NULL,
+	operation_id TEXT,
+	first_seen TIMESTAMP NOT NULL,
+	block_height INT,
+	confirmations INT NOT NULL,
+	last_checked TIMESTAMP,
+	PRIMARY KEY (federation_id, txid)
+);
+
+CREATE TABLE slo_evaluations(
+	ts TIMESTAMP NOT NULL,
+	slo TEXT NOT NULL,
+	window_start TIMESTAMP NOT NULL,
+	window_end TIMESTAMP NOT NULL,
+	total BIGINT NOT NULL,
+	bad BIGINT NOT NULL,
+	burn_rate DOUBLE PRECISION NOT NULL,
+	budget_remaining DOUBLE PRECISION NOT NULL,
+	PRIMARY KEY (ts, slo)
+);
+
+CREATE TABLE etl_runs(
+	id BIGSERIAL PRIMARY KEY,
+	started_at TIMESTAMP NOT NULL,
+	finished_at TIMESTAMP,
+	gateway_epoch INT NOT NULL,
+	status TEXT NOT NULL,
+	error TEXT,
+	config_hash TEXT NOT NULL,
+	config JSONB NOT NULL,
+	ingest_stats JSONB,
+	backfill_checkpoints JSONB
+);
+
+CREATE TABLE webhook_dead_letters(
+	id BIGSERIAL PRIMARY KEY,
+	created_at TIMESTAMP NOT NULL,
+	gateway_epoch INT NOT NULL,
+	federation_id TEXT NOT NULL,
+	log_id BIGINT NOT NULL,
+	event JSONB,
+	event_zstd BYTEA,
+	attempts INT NOT NULL,
+	error TEXT NOT NULL,
+	delivered_at TIMESTAMP,
+	CHECK (event IS NOT NULL OR event_zstd IS NOT NULL)
+);
+
+CREATE SCHEMA staging;
+
+CREATE TABLE staging.gateway_events(
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	log_id BIGINT NOT NULL,
+	ts TIMESTAMP NOT NULL,
+	module TEXT,
+	kind TEXT NOT NULL,
+	payload JSONB NOT NULL,
+	_loaded_at TIMESTAMP NOT NULL,
+	_etl_run_id BIGINT,
+	PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE back_dated_events(
+	event_log_id BIGINT NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	event_ts TIMESTAMP NOT NULL,
+	newest_ts TIMESTAMP NOT NULL,
+	accepted BOOLEAN NOT NULL,
+	recorded_at TIMESTAMP NOT NULL,
+	PRIMARY KEY (event_log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE gateway_uptime(
+	ts TIMESTAMP PRIMARY KEY,
+	reachable BOOLEAN NOT NULL,
+	latency_ms DOUBLE PRECISION NOT NULL,
+	error TEXT
+);
+
+CREATE TABLE payment_latency_splits(
+	terminal_log_id BIGINT NOT NULL,
+	federation_id TEXT NOT NULL,
+	federation_name TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	payment_hash TEXT NOT NULL,
+	started_ts TIMESTAMP NOT NULL,
+	lightning_started_ts TIMESTAMP NOT NULL,
+	completed_ts TIMESTAMP NOT NULL,
+	gateway_ms DOUBLE PRECISION NOT NULL,
+	lightning_ms DOUBLE PRECISION NOT NULL,
+	PRIMARY KEY (terminal_log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE etl_cursor(
+	federation_id TEXT NOT NULL,
+	gateway_epoch INT NOT NULL,
+	last_log_id BIGINT NOT NULL,
+	updated_at TIMESTAMP NOT NULL,
+	PRIMARY KEY (federation_id, gateway_epoch)
+);
+
+-- Seeds the cursor of existing deployments from the event tables
+INSERT INTO etl_cursor (federation_id, gateway_epoch, last_log_id, updated_at)
+SELECT federation_id, gateway_epoch, MAX(log_id), NOW() AT TIME ZONE 'UTC'
+FROM (
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_outgoing_payment_started
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_outgoing_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_outgoing_payment_failed
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_incoming_payment_started
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_incoming_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_incoming_payment_failed
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_complete_lightning_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_outgoing_payment_started
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_outgoing_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_outgoing_payment_failed
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_incoming_payment_started
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_incoming_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_incoming_payment_failed
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_complete_lightning_payment_succeeded
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM gateway_ledger
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_contract_cancelled
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv1_refund_claimed
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_contract_cancelled
+	UNION ALL
+	SELECT federation_id, gateway_epoch, log_id FROM lnv2_refund_claimed
+) AS combined_log_ids
+GROUP BY federation_id, gateway_epoch;
+
+CREATE TABLE computed_columns(
+	table_name TEXT NOT NULL,
+	column_name TEXT NOT NULL,
+	expression TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL,
+	PRIMARY KEY (table_name, column_name)
+);
+
+-- Log ids are per federation, so they are only unique together with the
+-- federation id. Every event insert relies on this key with ON CONFLICT DO NOTHING
+ALTER TABLE lnv1_outgoing_payment_started DROP CONSTRAINT lnv1_outgoing_payment_started_pkey;
+ALTER TABLE lnv1_outgoing_payment_started ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_outgoing_payment_succeeded DROP CONSTRAINT lnv1_outgoing_payment_succeeded_pkey;
+ALTER TABLE lnv1_outgoing_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_outgoing_payment_failed DROP CONSTRAINT lnv1_outgoing_payment_failed_pkey;
+ALTER TABLE lnv1_outgoing_payment_failed ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_incoming_payment_started DROP CONSTRAINT lnv1_incoming_payment_started_pkey;
+ALTER TABLE lnv1_incoming_payment_started ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_incoming_payment_succeeded DROP CONSTRAINT lnv1_incoming_payment_succeeded_pkey;
+ALTER TABLE lnv1_incoming_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_incoming_payment_failed DROP CONSTRAINT lnv1_incoming_payment_failed_pkey;
+ALTER TABLE lnv1_incoming_payment_failed ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv1_complete_lightning_payment_succeeded DROP CONSTRAINT lnv1_complete_lightning_payment_succeeded_pkey;
+ALTER TABLE lnv1_complete_lightning_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_outgoing_payment_started DROP CONSTRAINT lnv2_outgoing_payment_started_pkey;
+ALTER TABLE lnv2_outgoing_payment_started ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_outgoing_payment_succeeded DROP CONSTRAINT lnv2_outgoing_payment_succeeded_pkey;
+ALTER TABLE lnv2_outgoing_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_outgoing_payment_failed DROP CONSTRAINT lnv2_outgoing_payment_failed_pkey;
+ALTER TABLE lnv2_outgoing_payment_failed ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_incoming_payment_started DROP CONSTRAINT lnv2_incoming_payment_started_pkey;
+ALTER TABLE lnv2_incoming_payment_started ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_incoming_payment_succeeded DROP CONSTRAINT lnv2_incoming_payment_succeeded_pkey;
+ALTER TABLE lnv2_incoming_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_incoming_payment_failed DROP CONSTRAINT lnv2_incoming_payment_failed_pkey;
+ALTER TABLE lnv2_incoming_payment_failed ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+ALTER TABLE lnv2_complete_lightning_payment_succeeded DROP CONSTRAINT lnv2_complete_lightning_payment_succeeded_pkey;
+ALTER TABLE lnv2_complete_lightning_payment_succeeded ADD PRIMARY KEY (log_id, federation_id, gateway_epoch);
+
+CREATE TABLE selftest(
+	id BIGSERIAL PRIMARY KEY,
+	ts TIMESTAMP NOT NULL,
+	etl_run_id BIGINT NOT NULL,
+	federation_id TEXT NOT NULL,
+	passed BOOLEAN NOT NULL,
+	duration_ms DOUBLE PRECISION NOT NULL,
+	error TEXT
+);
+
+-- Schema changes from here on are migrations in src/migrations, applied on
+-- startup and by `init-db`
+
 
 DROP TABLE lnv1_outgoing_payment_started;
 DROP TABLE lnv1_outgoing_payment_succeeded;
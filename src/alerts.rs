@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, Utc};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::TelegramClient;
+
+/// How long a firing alert stays active in Alertmanager without being sent
+/// again. Slightly longer than the daily run interval, so an alert whose
+/// condition still holds is refreshed by the next run before it expires.
+const ALERT_TTL_HOURS: i64 = 25;
+
+/// An alert identified by its name and labels. Alerts that are evaluated on
+/// every run are sent as resolved once their condition clears.
+#[derive(Debug, Clone)]
+pub(crate) struct Alert {
+    name: &'static str,
+    labels: BTreeMap<&'static str, String>,
+    summary: String,
+    firing: bool,
+}
+
+impl Alert {
+    pub fn firing(name: &'static str, summary: String) -> Self {
+        Self {
+            name,
+            labels: BTreeMap::new(),
+            summary,
+            firing: true,
+        }
+    }
+
+    pub fn resolved(name: &'static str) -> Self {
+        Self {
+            name,
+            labels: BTreeMap::new(),
+            summary: String::new(),
+            firing: false,
+        }
+    }
+
+    pub fn with_label(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.labels.insert(name, value.to_string());
+        self
+    }
+}
+
+/// Sends alerts to Telegram, a Prometheus Alertmanager, or both.
+#[derive(Debug, Clone)]
+pub(crate) struct Alerter {
+    telegram_client: Option<TelegramClient>,
+    alertmanager_url: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Alerter {
+    pub fn new(
+        telegram_client: TelegramClient,
+        alertmanager_url: Option<String>,
+        alertmanager_only: bool,
+    ) -> Self {
+        Self {
+            telegram_client: (!alertmanager_only).then_some(telegram_client),
+            alertmanager_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Drops every alert, for work that runs without the alerting options.
+    pub fn disabled() -> Self {
+        Self {
+            telegram_client: None,
+            alertmanager_url: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn send(&self, alert: Alert) {
+        if alert.firing
+            && let Some(telegram_client) = &self.telegram_client
+        {
+            telegram_client
+                .send_telegram_message(alert.summary.clone())
+                .await;
+        }
+
+        if let Some(alertmanager_url) = &self.alertmanager_url {
+            self.send_to_alertmanager(alertmanager_url, &alert).await;
+        }
+    }
+
+    async fn send_to_alertmanager(&self, alertmanager_url: &str, alert: &Alert) {
+        let now = Utc::now();
+        let ends_at = if alert.firing {
+            now + Duration::hours(ALERT_TTL_HOURS)
+        } else {
+            now
+        };
+        let mut labels = alert.labels.clone();
+        labels.insert("alertname", alert.name.to_string());
+        labels.insert("job", "etl_gateway".to_string());
+
+        let res = self
+            .client
+            .post(format!(
+                "{}/api/v2/alerts",
+                alertmanager_url.trim_end_matches('/')
+            ))
+            .json(&json!([{
+                "labels": labels,
+                "annotations": { "summary": alert.summary },
+                "endsAt": ends_at.to_rfc3339(),
+            }]))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+
+        match res {
+            Ok(_) => {
+                info!(
+                    alert = alert.name,
+                    firing = alert.firing,
+                    "Sent alert to Alertmanager"
+                );
+            }
+            Err(err) => {
+                error!("Error sending alert to Alertmanager: {}", err);
+            }
+        }
+    }
+}
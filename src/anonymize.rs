@@ -0,0 +1,77 @@
+use fedimint_core::{
+    anyhow,
+    bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256},
+};
+
+/// Identifiers that are replaced with their keyed hash in an anonymized
+/// export. Payment hashes and images, contract ids and keys link a row to a
+/// payment or a user outside the dataset; federation ids and names link it to
+/// a federation.
+pub(crate) const ANONYMIZED_COLUMNS: &[&str] = &[
+    "federation_id",
+    "federation_name",
+    "payment_hash",
+    "payment_image",
+    "contract_id",
+    "gateway_key",
+    "user_key",
+    "claim_pk",
+    "ephemeral_pk",
+    "refund_pk",
+    "txid",
+];
+
+/// Shorter secrets could be brute forced together with the small set of
+/// known federation ids.
+const MIN_KEY_LEN: usize = 16;
+
+/// Replaces identifiers with an HMAC-SHA256 under an operator secret. The
+/// same value always maps to the same hash, across tables and exports with
+/// the same key, so researchers can still join the rows of a dataset without
+/// learning the identifiers behind them.
+#[derive(Clone)]
+pub(crate) struct Anonymizer {
+    key: Vec<u8>,
+}
+
+impl Anonymizer {
+    pub fn new(key: &str) -> anyhow::Result<Self> {
+        if key.len() < MIN_KEY_LEN {
+            anyhow::bail!("The anonymization key needs at least {MIN_KEY_LEN} characters");
+        }
+        Ok(Self {
+            key: key.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn anonymize(&self, value: &str) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.key);
+        engine.input(value.as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine).to_string()
+    }
+
+    /// Anonymizes the [`ANONYMIZED_COLUMNS`] of a CSV file with a header row.
+    /// Empty fields stay empty, they are `NULL` in the database.
+    pub fn anonymize_csv(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut reader = csv::Reader::from_reader(data);
+        let headers = reader.headers()?.clone();
+        let anonymized = headers
+            .iter()
+            .map(|header| ANONYMIZED_COLUMNS.contains(&header))
+            .collect::<Vec<_>>();
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(&headers)?;
+        for record in reader.records() {
+            let record = record?;
+            writer.write_record(record.iter().zip(&anonymized).map(|(field, anonymized)| {
+                if *anonymized && !field.is_empty() {
+                    self.anonymize(field)
+                } else {
+                    field.to_string()
+                }
+            }))?;
+        }
+        Ok(writer.into_inner().map_err(|err| err.into_error())?)
+    }
+}
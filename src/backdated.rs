@@ -0,0 +1,117 @@
+use std::fmt;
+
+use chrono::{Duration, NaiveDateTime};
+use fedimint_core::{anyhow, config::FederationId};
+use tokio_postgres::Client;
+use tracing::warn;
+
+use crate::{GatewayEpoch, clock::Clock, schema};
+
+/// Detects events timestamped before the newest event already stored for
+/// the federation, which happens when the gateway's clock was set back.
+/// Every such event is recorded in `back_dated_events`. Events back-dated by
+/// more than the tolerance are not stored in the event tables.
+#[derive(Debug)]
+pub(crate) struct BackDatedEvents {
+    federation_id: FederationId,
+    federation_name: String,
+    gateway_epoch: GatewayEpoch,
+    tolerance: Option<Duration>,
+    newest_ts: Option<NaiveDateTime>,
+    accepted: u64,
+    rejected: u64,
+    clock: Clock,
+}
+
+impl BackDatedEvents {
+    /// Starts from the newest stored event of `federation_id` in
+    /// `gateway_epoch`. Without a tolerance every back-dated event is
+    /// accepted.
+    pub async fn query(
+        pg_client: &Client,
+        federation_id: FederationId,
+        federation_name: String,
+        gateway_epoch: GatewayEpoch,
+        tolerance: Option<Duration>,
+        clock: Clock,
+    ) -> anyhow::Result<Self> {
+        let mut newest_ts = None;
+        for (table, _) in schema::event_tables() {
+            let row = pg_client
+                .query_one(
+                    &format!(
+                        "SELECT MAX(ts) FROM {table} WHERE federation_id = $1 AND gateway_epoch = $2"
+                    ),
+                    &[&federation_id.to_string(), &i32::from(gateway_epoch)],
+                )
+                .await?;
+            newest_ts = newest_ts.max(row.get::<_, Option<NaiveDateTime>>(0));
+        }
+
+        Ok(Self {
+            federation_id,
+            federation_name,
+            gateway_epoch,
+            tolerance,
+            newest_ts,
+            accepted: 0,
+            rejected: 0,
+            clock,
+        })
+    }
+
+    /// Returns whether the event at `ts` should be stored. Events have to be
+    /// checked in log id order.
+    pub async fn check(
+        &mut self,
+        pg_client: &Client,
+        log_id: i64,
+        ts: NaiveDateTime,
+    ) -> anyhow::Result<bool> {
+        let Some(newest_ts) = self.newest_ts.filter(|newest_ts| ts < *newest_ts) else {
+            self.newest_ts = Some(ts);
+            return Ok(true);
+        };
+
+        let back_dated_by = newest_ts - ts;
+        let accepted = self
+            .tolerance
+            .is_none_or(|tolerance| back_dated_by <= tolerance);
+        if accepted {
+            self.accepted += 1;
+        } else {
+            self.rejected += 1;
+            warn!(federation_name = self.federation_name, log_id, %ts, %newest_ts, "Rejecting back-dated event");
+        }
+        pg_client
+            .execute(
+                "INSERT INTO back_dated_events (event_log_id, federation_id, federation_name, gateway_epoch, event_ts, newest_ts, accepted, recorded_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING",
+                &[
+                    &log_id,
+                    &self.federation_id.to_string(),
+                    &self.federation_name,
+                    &i32::from(self.gateway_epoch),
+                    &ts,
+                    &newest_ts,
+                    &accepted,
+                    &self.clock.now().naive_utc(),
+                ],
+            )
+            .await?;
+        Ok(accepted)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accepted == 0 && self.rejected == 0
+    }
+}
+
+impl fmt::Display for BackDatedEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: accepted {}, rejected {}",
+            self.federation_name, self.accepted, self.rejected
+        )
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use futures_util::{SinkExt, TryStreamExt, pin_mut};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::IsolationLevel;
+use tracing::info;
+
+use crate::schema::EXPECTED_SCHEMA;
+
+const MANIFEST: &str = "manifest.json";
+
+/// Describes the contents of a backup, so it can be restored into a database
+/// whose columns are in a different order.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    created_at: String,
+    tables: BTreeMap<String, Vec<String>>,
+}
+
+/// Writes every ETL table to a zstd compressed tar archive, one `COPY` text
+/// file per table. All tables are read in a single repeatable read
+/// transaction, so the backup is a consistent snapshot even while the ETL is
+/// running.
+pub(crate) async fn backup(pool: &Pool, out: &Path) -> anyhow::Result<()> {
+    let mut pg_client = pool.get().await?;
+    let transaction = pg_client
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await?;
+
+    let encoder = zstd::Encoder::new(File::create(out)?, 0)?;
+    let mut archive = tar::Builder::new(encoder);
+    let mut manifest = Manifest {
+        created_at: Utc::now().to_rfc3339(),
+        tables: BTreeMap::new(),
+    };
+
+    for (table, columns) in EXPECTED_SCHEMA {
+        let stream = transaction
+            .copy_out(&format!("COPY {table} ({}) TO STDOUT", columns.join(", ")))
+            .await?;
+        pin_mut!(stream);
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        append(&mut archive, &format!("{table}.copy"), &data)?;
+        info!(%table, bytes = data.len(), "Backed up table");
+        manifest.tables.insert(
+            table.to_string(),
+            columns.iter().map(|column| column.to_string()).collect(),
+        );
+    }
+    transaction.commit().await?;
+
+    append(
+        &mut archive,
+        MANIFEST,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    archive.into_inner()?.finish()?.flush()?;
+
+    println!(
+        "Backed up {} tables to {}",
+        manifest.tables.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+/// Loads a backup written by [`backup`] in a single transaction. Refuses to
+/// restore into tables that already contain rows unless `truncate` is set.
+pub(crate) async fn restore(pool: &Pool, input: &Path, truncate: bool) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(File::open(input)?)?);
+    let mut files = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(name, data);
+    }
+    let manifest: Manifest = serde_json::from_slice(
+        files
+            .get(MANIFEST)
+            .ok_or_else(|| anyhow::anyhow!("{} has no {MANIFEST}", input.display()))?,
+    )?;
+
+    let mut pg_client = pool.get().await?;
+    let transaction = pg_client.transaction().await?;
+    for table in manifest.tables.keys() {
+        let row = transaction
+            .query_one(&format!("SELECT EXISTS (SELECT 1 FROM {table})"), &[])
+            .await?;
+        let has_rows: bool = row.get(0);
+        if has_rows {
+            anyhow::ensure!(
+                truncate,
+                "Table {table} is not empty, pass --truncate to replace its contents"
+            );
+            transaction
+                .execute(&format!("TRUNCATE TABLE {table}"), &[])
+                .await?;
+        }
+    }
+
+    for (table, columns) in &manifest.tables {
+        let data = files
+            .remove(&format!("{table}.copy"))
+            .ok_or_else(|| anyhow::anyhow!("Backup is missing the data of {table}"))?;
+        let bytes = data.len();
+        let sink = transaction
+            .copy_in(&format!("COPY {table} ({}) FROM STDIN", columns.join(", ")))
+            .await?;
+        pin_mut!(sink);
+        sink.send(bytes::Bytes::from(data)).await?;
+        let rows = sink.finish().await?;
+        info!(%table, rows, bytes, "Restored table");
+
+        // Restored ids must not be handed out again
+        for column in columns {
+            let row = transaction
+                .query_one("SELECT pg_get_serial_sequence($1, $2)", &[table, column])
+                .await?;
+            let sequence: Option<String> = row.get(0);
+            if let Some(sequence) = sequence {
+                transaction
+                    .execute(
+                        &format!(
+                            "SELECT setval($1::TEXT::REGCLASS, COALESCE((SELECT MAX({column}) FROM {table}), 0) + 1, false)"
+                        ),
+                        &[&sequence],
+                    )
+                    .await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    println!(
+        "Restored {} tables from {} (created at {})",
+        manifest.tables.len(),
+        input.display(),
+        manifest.created_at
+    );
+    Ok(())
+}
+
+fn append<W: Write>(archive: &mut tar::Builder<W>, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::{collections::BTreeMap, num::NonZeroU32};
+
+use chrono::{Duration, NaiveDateTime};
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::{error, warn};
+
+use crate::clock::Clock;
+
+/// Held while checking and recording a message, so concurrent sends can't
+/// both take the last slot of the budget.
+const BUDGET_LOCK: i64 = 0x6574_6c5f_6275_6467;
+
+/// Telegram rejects longer messages.
+const MAX_DIGEST_LEN: usize = 4000;
+
+/// How much of a held back message shows up in the digest.
+const MAX_PREVIEW_LEN: usize = 200;
+
+/// Caps the Telegram messages sent within any hour, across runs, so an event
+/// storm or a bug can't flood the operator's chat and get the bot banned.
+/// Every message is recorded in `telegram_messages`. Messages over the budget
+/// are held back and later announced in a single digest per chat, once the
+/// budget has room again.
+#[derive(Debug, Clone)]
+pub(crate) struct MessageBudget {
+    pool: Pool,
+    per_hour: NonZeroU32,
+    clock: Clock,
+}
+
+impl MessageBudget {
+    pub fn new(pool: Pool, per_hour: NonZeroU32, clock: Clock) -> Self {
+        Self {
+            pool,
+            per_hour,
+            clock,
+        }
+    }
+
+    /// Records the message and returns whether it may be sent. If the budget
+    /// can't be checked the message is sent, an alert shouldn't get lost
+    /// because the database is down.
+    pub async fn admit(&self, chat_id: &str, message: &str) -> bool {
+        match self.try_admit(chat_id, message).await {
+            Ok(admitted) => {
+                if !admitted {
+                    warn!(
+                        chat_id,
+                        per_hour = self.per_hour,
+                        "Message budget exhausted, holding message back"
+                    );
+                }
+                admitted
+            }
+            Err(err) => {
+                error!("Error checking the message budget: {}", err);
+                true
+            }
+        }
+    }
+
+    async fn try_admit(&self, chat_id: &str, message: &str) -> anyhow::Result<bool> {
+        let pg_client = self.pool.get().await?;
+        pg_client.batch_execute("BEGIN").await?;
+        let res = async {
+            pg_client
+                .execute("SELECT pg_advisory_xact_lock($1)", &[&BUDGET_LOCK])
+                .await?;
+            let now = self.clock.now().naive_utc();
+            let admitted = self.has_room(&pg_client).await?;
+            pg_client
+                .execute(
+                    "INSERT INTO telegram_messages (ts, chat_id, preview, sent) VALUES ($1, $2, $3, $4)",
+                    &[&now, &chat_id, &preview(message), &admitted],
+                )
+                .await?;
+            anyhow::Ok(admitted)
+        }
+        .await;
+        match res {
+            Ok(admitted) => {
+                pg_client.batch_execute("COMMIT").await?;
+                Ok(admitted)
+            }
+            Err(err) => {
+                pg_client.batch_execute("ROLLBACK").await?;
+                Err(err)
+            }
+        }
+    }
+
+    async fn has_room(&self, pg_client: &Client) -> anyhow::Result<bool> {
+        let sent: i64 = pg_client
+            .query_one(
+                "SELECT COUNT(*) FROM telegram_messages WHERE sent AND ts > $1",
+                &[&(self.clock.now().naive_utc() - Duration::hours(1))],
+            )
+            .await?
+            .get(0);
+        Ok(sent < i64::from(self.per_hour.get()))
+    }
+
+    /// Builds the digest of the messages held back for each chat, if the
+    /// budget has room again. The messages count as announced once this
+    /// returns, so each one shows up in one digest only. The digests
+    /// themselves are sent within the budget like any other message.
+    pub async fn take_digests(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let pg_client = self.pool.get().await?;
+        if !self.has_room(&pg_client).await? {
+            return Ok(Vec::new());
+        }
+        let rows = pg_client
+            .query(
+                "UPDATE telegram_messages SET digested_at = $1 WHERE NOT sent AND digested_at IS NULL RETURNING chat_id, ts, preview",
+                &[&self.clock.now().naive_utc()],
+            )
+            .await?;
+        let mut held: BTreeMap<String, Vec<(NaiveDateTime, String)>> = BTreeMap::new();
+        for row in &rows {
+            held.entry(row.get(0))
+                .or_default()
+                .push((row.get(1), row.get(2)));
+        }
+        Ok(held
+            .into_iter()
+            .map(|(chat_id, mut messages)| {
+                messages.sort();
+                let mut digest = format!(
+                    "Held back by the limit of {} messages per hour ({}):",
+                    self.per_hour,
+                    messages.len()
+                );
+                for (ts, preview) in messages {
+                    let line = format!("\n{} {preview}", ts.format("%H:%M"));
+                    if digest.len() + line.len() > MAX_DIGEST_LEN {
+                        break;
+                    }
+                    digest += &line;
+                }
+                (chat_id, digest)
+            })
+            .collect())
+    }
+}
+
+/// The first line of a message, shortened.
+fn preview(message: &str) -> String {
+    let line = message.lines().next().unwrap_or_default();
+    match line.char_indices().nth(MAX_PREVIEW_LEN) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use chrono::{DateTime, Duration};
+
+    use super::MessageBudget;
+    use crate::{clock::Clock, test_db};
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn held_back_messages_are_digested_once_the_hour_has_passed() {
+        let pool = test_db::pool().await;
+        let clock = Clock::frozen(
+            DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z")
+                .unwrap()
+                .to_utc(),
+        );
+        let budget = MessageBudget::new(pool, NonZeroU32::new(2).unwrap(), clock.clone());
+
+        assert!(budget.admit("chat", "first").await);
+        assert!(budget.admit("chat", "second").await);
+        assert!(!budget.admit("chat", "third").await);
+        assert!(budget.take_digests().await.unwrap().is_empty());
+
+        clock.advance(Duration::minutes(30));
+        assert!(!budget.admit("chat", "fourth\nsecond line").await);
+
+        clock.advance(Duration::minutes(31));
+        assert_eq!(
+            budget.take_digests().await.unwrap(),
+            vec![(
+                "chat".to_string(),
+                "Held back by the limit of 2 messages per hour (2):\n10:00 third\n10:30 fourth"
+                    .to_string()
+            )]
+        );
+        assert!(budget.take_digests().await.unwrap().is_empty());
+        assert!(budget.admit("chat", "fifth").await);
+    }
+}
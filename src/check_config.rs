@@ -0,0 +1,193 @@
+use fedimint_connectors::ConnectorRegistry;
+use fedimint_core::anyhow;
+use fedimint_gateway_client::get_info;
+
+use crate::{DbConnection, RunOpts, SummaryMode, schema};
+
+/// Validates `opts` and everything they point to without ingesting
+/// anything: URLs, tokens, thresholds, the databases and their schema, the
+/// gateway and the federations chats are routed for. Prints every problem
+/// found and fails if there is any, so it can gate deployments in CI.
+pub(crate) async fn check_config(opts: &RunOpts) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+
+    for (name, url) in [
+        ("--chain-source-url", &opts.chain_source_url),
+        ("--mempool-url", &opts.mempool_url),
+        ("--pushgateway-url", &opts.pushgateway_url),
+        ("--alertmanager-url", &opts.alertmanager_url),
+        ("--webhook-url", &opts.webhook_url),
+    ] {
+        if let Some(url) = url {
+            match url::Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                Ok(url) => problems.push(format!("{name} has unsupported scheme {}", url.scheme())),
+                Err(err) => problems.push(format!("{name} is not a valid URL: {err}")),
+            }
+        }
+    }
+
+    if !is_bot_token(&opts.bot_token) {
+        problems.push("--bot-token doesn't look like <bot id>:<secret>".to_string());
+    }
+    for chat_id in std::iter::once(&opts.chat_id)
+        .chain(
+            opts.federation_chats
+                .iter()
+                .map(|federation_chat| &federation_chat.chat_id),
+        )
+        .chain(opts.reports.iter().map(|report| &report.chat_id))
+    {
+        if !is_chat_id(chat_id) {
+            problems.push(format!(
+                "Chat id {chat_id} is neither a number nor an @channel"
+            ));
+        }
+    }
+    if let Some(secret) = &opts.webhook_secret
+        && secret.len() < 16
+    {
+        problems.push("--webhook-secret is shorter than 16 characters".to_string());
+    }
+
+    if opts.chain_source.is_some() != opts.chain_source_url.is_some() {
+        problems.push("--chain-source and --chain-source-url must be set together".to_string());
+    }
+    if opts.withdrawal_alert_hours <= 0 {
+        problems.push("--withdrawal-alert-hours must be positive".to_string());
+    }
+    if opts.summary_mode == SummaryMode::Failures && opts.failure_threshold == 0 {
+        problems
+            .push("--failure-threshold 0 makes --summary-mode failures always send".to_string());
+    }
+    if opts.retry_storm_attempts < 2 {
+        problems.push("--retry-storm-attempts below 2 reports every payment".to_string());
+    }
+    if opts.slo_burn_rate_alert <= 0.0 {
+        problems.push("--slo-burn-rate-alert must be positive".to_string());
+    }
+    if opts
+        .back_dated_tolerance_minutes
+        .is_some_and(|minutes| minutes < 0)
+    {
+        problems.push("--back-dated-tolerance-minutes must not be negative".to_string());
+    }
+    if opts
+        .db
+        .db_max_connections
+        .is_some_and(|max_connections| max_connections < opts.max_concurrency)
+    {
+        problems.push(
+            "--db-max-connections below --max-concurrency makes federations wait for connections"
+                .to_string(),
+        );
+    }
+    if opts.backfill_progress_minutes == Some(0) {
+        problems.push("--backfill-progress-minutes must be positive".to_string());
+    }
+    if opts.frozen_clock.is_some() {
+        problems.push("--frozen-clock is meant for tests only".to_string());
+    }
+
+    match check_database(opts).await {
+        Ok(missing_views) => {
+            for view in missing_views {
+                problems.push(format!("Materialized view {view} does not exist"));
+            }
+        }
+        Err(err) => problems.push(format!("Database: {err:#}")),
+    }
+    if let Some(secondary_db) = opts.secondary_db.db_opts() {
+        let result = async {
+            let pool = DbConnection::from_opts(&secondary_db).pool()?;
+            schema::check_schema(&*pool.get().await?).await
+        }
+        .await;
+        if let Err(err) = result {
+            problems.push(format!("Secondary database: {err:#}"));
+        }
+    }
+
+    match check_gateway(opts).await {
+        Ok(unknown_federations) => {
+            for federation_id in unknown_federations {
+                problems.push(format!(
+                    "The gateway hasn't joined federation {federation_id} from --federation-chat"
+                ));
+            }
+        }
+        Err(err) => problems.push(format!("Gateway: {err:#}")),
+    }
+
+    if problems.is_empty() {
+        println!("Configuration OK");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("- {problem}");
+    }
+    anyhow::bail!("Found {} configuration problems", problems.len())
+}
+
+/// Connects to the database, checks its schema and returns the configured
+/// materialized views that don't exist.
+async fn check_database(opts: &RunOpts) -> anyhow::Result<Vec<String>> {
+    let pool = DbConnection::from_opts(&opts.db).pool()?;
+    let pg_client = pool.get().await?;
+    schema::check_schema(&pg_client).await?;
+
+    let mut missing_views = Vec::new();
+    for view in &opts.materialized_views {
+        let row = pg_client
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM pg_matviews WHERE format('%I.%I', schemaname, matviewname)::REGCLASS = to_regclass($1))",
+                &[&view.name()],
+            )
+            .await?;
+        if !row.get::<_, bool>(0) {
+            missing_views.push(view.name().to_string());
+        }
+    }
+    Ok(missing_views)
+}
+
+/// Authenticates against the gateway and returns the federations chats are
+/// routed for that it hasn't joined.
+async fn check_gateway(opts: &RunOpts) -> anyhow::Result<Vec<String>> {
+    let connector_registry = ConnectorRegistry::build_from_client_defaults()
+        .with_env_var_overrides()?
+        .bind()
+        .await?;
+    let info = opts
+        .gateway_auth(connector_registry)?
+        .request(|client| async move { get_info(&client, &opts.gateway_addr).await })
+        .await?;
+    Ok(opts
+        .federation_chats
+        .iter()
+        .map(|federation_chat| federation_chat.federation_id)
+        .filter(|federation_id| {
+            !info
+                .federations
+                .iter()
+                .any(|federation| federation.federation_id == *federation_id)
+        })
+        .map(|federation_id| federation_id.to_string())
+        .collect())
+}
+
+/// Telegram bot tokens are `<numeric bot id>:<secret>`.
+fn is_bot_token(token: &str) -> bool {
+    token.split_once(':').is_some_and(|(id, secret)| {
+        !id.is_empty()
+            && id.chars().all(|c| c.is_ascii_digit())
+            && secret.len() >= 30
+            && secret
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+fn is_chat_id(chat_id: &str) -> bool {
+    chat_id.parse::<i64>().is_ok() || chat_id.starts_with('@')
+}
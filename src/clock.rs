@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+/// Where a run takes the current time from. The summary window, the
+/// leaderboard weekday, SLO windows and every timestamp the run writes are
+/// all derived from one `Clock`, so a run against a fixed set of events can
+/// be repeated with a frozen clock and produces the same output.
+#[derive(Debug, Clone)]
+pub(crate) enum Clock {
+    System,
+    /// Shared by all clones, so moving it moves it for every part of the run.
+    Frozen(Arc<Mutex<DateTime<Utc>>>),
+}
+
+impl Clock {
+    pub fn frozen(now: DateTime<Utc>) -> Self {
+        Clock::Frozen(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Frozen(now) => *now.lock().expect("Clock lock is never poisoned"),
+        }
+    }
+
+    /// Moves a frozen clock to `now`. The system clock can't be moved.
+    #[cfg(test)]
+    pub fn set(&self, now: DateTime<Utc>) {
+        match self {
+            Clock::System => panic!("Can't set the system clock"),
+            Clock::Frozen(frozen) => *frozen.lock().expect("Clock lock is never poisoned") = now,
+        }
+    }
+
+    /// Moves a frozen clock forward by `by`.
+    #[cfg(test)]
+    pub fn advance(&self, by: chrono::Duration) {
+        self.set(self.now() + by);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+
+    use super::Clock;
+
+    #[test]
+    fn frozen_clock_moves_for_all_clones() {
+        let start = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z")
+            .unwrap()
+            .to_utc();
+        let clock = Clock::frozen(start);
+        let copy = clock.clone();
+        assert_eq!(copy.now(), start);
+
+        clock.advance(Duration::minutes(90));
+        assert_eq!(copy.now(), start + Duration::minutes(90));
+
+        copy.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
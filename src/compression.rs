@@ -0,0 +1,44 @@
+use clap::ValueEnum;
+use fedimint_core::anyhow;
+use serde_json::Value;
+use tokio_postgres::Row;
+
+/// Columns that can be stored zstd compressed. A compressed value goes into
+/// a `BYTEA` column next to the plain one (e.g. `event_zstd` next to `event`)
+/// and the plain one is left `NULL`, so rows written before compression was
+/// turned on stay readable.
+///
+/// Preimages and keys are not offered. They are hex encoded random 32 byte
+/// values, so half of their 64 characters is redundant, but a zstd frame of
+/// a single 64 byte value doesn't get to use that: the frame header and the
+/// Huffman table it would need cost about as much as they save, and such a
+/// value compresses to 61 or 62 bytes. Storing them decoded as 32 byte
+/// `BYTEA` would halve them, but that is a change of their columns' type,
+/// not compression.
+///
+/// `raw_events` isn't covered by `RawJson`, its payloads have an encoding of
+/// their own, `--archive-encoding cbor-zstd`, that replay and
+/// `raw-events-to-json` decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColumnClass {
+    /// Event payloads stored as JSON, i.e. the webhook dead letters
+    RawJson,
+}
+
+pub(crate) fn compress_json(value: &Value) -> anyhow::Result<Vec<u8>> {
+    Ok(zstd::encode_all(serde_json::to_vec(value)?.as_slice(), 0)?)
+}
+
+/// Reads a JSON value from the plain column `idx`, or from the compressed
+/// column `compressed_idx` if the plain one is `NULL`.
+pub(crate) fn read_json(row: &Row, idx: usize, compressed_idx: usize) -> anyhow::Result<Value> {
+    if let Some(value) = row.get::<_, Option<Value>>(idx) {
+        return Ok(value);
+    }
+    let compressed: Vec<u8> = row
+        .get::<_, Option<Vec<u8>>>(compressed_idx)
+        .ok_or_else(|| anyhow::anyhow!("Neither column {idx} nor {compressed_idx} is set"))?;
+    Ok(serde_json::from_slice(&zstd::decode_all(
+        compressed.as_slice(),
+    )?)?)
+}
@@ -0,0 +1,256 @@
+use std::{fmt, iter::Peekable, str::FromStr};
+
+use chrono::Utc;
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::schema::EXPECTED_SCHEMA;
+
+/// An arithmetic expression over the columns of one table.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Column(String),
+    Number(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+impl Expr {
+    /// Renders the expression for a generated column. Columns are cast to
+    /// `NUMERIC` so divisions don't truncate, and a division by zero yields
+    /// `NULL` instead of failing the insert of the event.
+    fn to_sql(&self) -> String {
+        match self {
+            Expr::Column(column) => format!("{column}::NUMERIC"),
+            Expr::Number(number) => number.clone(),
+            Expr::Neg(expr) => format!("(-{})", expr.to_sql()),
+            Expr::Binary(lhs, '/', rhs) => {
+                format!("({} / NULLIF({}, 0))", lhs.to_sql(), rhs.to_sql())
+            }
+            Expr::Binary(lhs, op, rhs) => format!("({} {op} {})", lhs.to_sql(), rhs.to_sql()),
+        }
+    }
+
+    fn columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            Expr::Column(column) => columns.push(column),
+            Expr::Number(_) => {}
+            Expr::Neg(expr) => expr.columns(columns),
+            Expr::Binary(lhs, _, rhs) => {
+                lhs.columns(columns);
+                rhs.columns(columns);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek()
+                && (c.is_ascii_alphanumeric() || c == '_')
+            {
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+        } else if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek()
+                && (c.is_ascii_digit() || c == '.' || c == '_')
+            {
+                // `1_000_000` reads better in a config file
+                if c != '_' {
+                    number.push(c);
+                }
+                chars.next();
+            }
+            anyhow::ensure!(
+                number.parse::<f64>().is_ok(),
+                "Invalid number {number} in {s}"
+            );
+            tokens.push(Token::Number(number));
+        } else {
+            tokens.push(match c {
+                '+' | '-' | '*' | '/' => Token::Op(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => anyhow::bail!("Unexpected {c} in {s}"),
+            });
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+/// `expr := term (('+' | '-') term)*`
+fn parse_expr(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> anyhow::Result<Expr> {
+    let mut expr = parse_term(tokens)?;
+    while let Some(Token::Op(op @ ('+' | '-'))) = tokens.peek().cloned() {
+        tokens.next();
+        expr = Expr::Binary(Box::new(expr), op, Box::new(parse_term(tokens)?));
+    }
+    Ok(expr)
+}
+
+/// `term := factor (('*' | '/') factor)*`
+fn parse_term(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> anyhow::Result<Expr> {
+    let mut expr = parse_factor(tokens)?;
+    while let Some(Token::Op(op @ ('*' | '/'))) = tokens.peek().cloned() {
+        tokens.next();
+        expr = Expr::Binary(Box::new(expr), op, Box::new(parse_factor(tokens)?));
+    }
+    Ok(expr)
+}
+
+/// `factor := column | number | '-' factor | '(' expr ')'`
+fn parse_factor(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> anyhow::Result<Expr> {
+    match tokens.next() {
+        Some(Token::Ident(column)) => Ok(Expr::Column(column)),
+        Some(Token::Number(number)) => Ok(Expr::Number(number)),
+        Some(Token::Op('-')) => Ok(Expr::Neg(Box::new(parse_factor(tokens)?))),
+        Some(Token::Open) => {
+            let expr = parse_expr(tokens)?;
+            anyhow::ensure!(tokens.next() == Some(Token::Close), "Missing )");
+            Ok(expr)
+        }
+        Some(token) => anyhow::bail!("Unexpected {token:?}"),
+        None => anyhow::bail!("Unexpected end of expression"),
+    }
+}
+
+/// A column computed from other columns of the same table whenever a row is
+/// written, given as `<table>.<column>=<expression>` (e.g.
+/// `gateway_ledger.fee_ppm=fee_msat*1_000_000/amount_msat`). Expressions can
+/// use the columns of the table, numbers, `+`, `-`, `*`, `/` and
+/// parentheses. Stored as a generated `NUMERIC` column, so derived metrics
+/// don't need a view on top of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ComputedColumn {
+    table: &'static str,
+    column: String,
+    expr: Expr,
+    definition: String,
+}
+
+impl FromStr for ComputedColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, expression) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected <table>.<column>=<expression>, got {s}"))?;
+        let (table, column) = target
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("Expected <table>.<column>, got {target}"))?;
+        let (table, columns) = EXPECTED_SCHEMA
+            .iter()
+            .find(|(name, _)| *name == table)
+            .ok_or_else(|| anyhow::anyhow!("Unknown table {table}"))?;
+        // The name ends up in a statement that can't take parameters
+        anyhow::ensure!(
+            column.starts_with(|c: char| c.is_ascii_lowercase())
+                && column
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+            "Invalid column name: {column}"
+        );
+        anyhow::ensure!(
+            !columns.contains(&column),
+            "{table}.{column} is not a computed column"
+        );
+
+        let mut tokens = tokenize(expression)?.into_iter().peekable();
+        let expr = parse_expr(&mut tokens)?;
+        anyhow::ensure!(
+            tokens.next().is_none(),
+            "Unexpected trailing input in {expression}"
+        );
+        let mut used = Vec::new();
+        expr.columns(&mut used);
+        if let Some(unknown) = used.iter().find(|used| !columns.contains(used)) {
+            anyhow::bail!("{table} has no column {unknown}");
+        }
+
+        Ok(Self {
+            table,
+            column: column.to_string(),
+            expr,
+            definition: s.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ComputedColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.definition)
+    }
+}
+
+/// Adds the generated columns to their tables. A column whose expression
+/// changed is dropped and added again, which computes it anew for all
+/// existing rows. Columns that are no longer configured are kept.
+pub(crate) async fn apply(pg_client: &Client, columns: &[ComputedColumn]) -> anyhow::Result<()> {
+    for computed in columns {
+        let expression = computed.expr.to_sql();
+        let recorded = pg_client
+            .query_opt(
+                "SELECT expression FROM computed_columns WHERE table_name = $1 AND column_name = $2",
+                &[&computed.table, &computed.column],
+            )
+            .await?
+            .map(|row| row.get::<_, String>(0));
+        if recorded.as_deref() == Some(expression.as_str()) {
+            continue;
+        }
+
+        pg_client.batch_execute("BEGIN").await?;
+        let res = pg_client
+            .batch_execute(&format!(
+                "ALTER TABLE {table} DROP COLUMN IF EXISTS {column}; ALTER TABLE {table} ADD COLUMN {column} NUMERIC GENERATED ALWAYS AS ({expression}) STORED",
+                table = computed.table,
+                column = computed.column,
+            ))
+            .await;
+        let res = match res {
+            Ok(()) => pg_client
+                .execute(
+                    "INSERT INTO computed_columns (table_name, column_name, expression, created_at) VALUES ($1, $2, $3, $4) ON CONFLICT (table_name, column_name) DO UPDATE SET expression = EXCLUDED.expression, created_at = EXCLUDED.created_at",
+                    &[
+                        &computed.table,
+                        &computed.column,
+                        &expression,
+                        &Utc::now().naive_utc(),
+                    ],
+                )
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            pg_client.batch_execute("ROLLBACK").await?;
+            return Err(anyhow::anyhow!(
+                "Error adding computed column {computed}: {err}"
+            ));
+        }
+        pg_client.batch_execute("COMMIT").await?;
+        info!(%computed, "Added computed column");
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::{collections::BTreeMap, ffi::OsString, path::PathBuf};
+
+use clap::{Command, CommandFactory};
+use fedimint_core::anyhow;
+use toml::{Table, Value};
+
+use crate::GatewayETLOpts;
+
+/// The option naming the config file, read before clap parses anything.
+const CONFIG_FLAG: &str = "--config";
+const CONFIG_ENV: &str = "ETL_CONFIG";
+
+/// Where an option is taken from the config file.
+struct ConfigurableArg {
+    env: OsString,
+    delimiter: Option<char>,
+}
+
+/// Applies the TOML file given with `--config` (or `ETL_CONFIG`). Keys are
+/// the long names of the options, with `-` or `_`, and tables only group
+/// them:
+///
+/// ```toml
+/// [gateway]
+/// gateway-addr = "http://127.0.0.1:8175"
+/// password-file = "/run/secrets/gateway"
+///
+/// [database]
+/// db-url = "postgres://etl@localhost/etl"
+/// ```
+///
+/// Every setting is exported as the environment variable of its option,
+/// unless that variable is already set, so the command line beats the
+/// environment, which beats the file. The same file serves the run and all
+/// subcommands, each one picks the options it has.
+///
+/// Returns `args` without `--config`, which is passed on as `ETL_CONFIG`
+/// instead: clap would reject it in front of a subcommand. Has to run before
+/// any other thread is started, since it modifies the environment.
+pub(crate) fn load(args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let (args, path) = take_config_path(args);
+    let Some(path) = path.or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from)) else {
+        return Ok(args);
+    };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| anyhow::anyhow!("Error reading {}: {err}", path.display()))?;
+    let table: Table = contents
+        .parse()
+        .map_err(|err| anyhow::anyhow!("Error parsing {}: {err}", path.display()))?;
+
+    let mut configurable = BTreeMap::new();
+    collect_args(&GatewayETLOpts::command(), &mut configurable);
+    let mut settings = Vec::new();
+    flatten(&table, &configurable, &mut settings)?;
+    settings.push((CONFIG_ENV.into(), path.display().to_string()));
+    for (env, value) in settings {
+        if std::env::var_os(&env).is_none() || env == CONFIG_ENV {
+            // SAFETY: called at the start of `main`, before the runtime or
+            // anything else could have started another thread
+            unsafe { std::env::set_var(env, value) };
+        }
+    }
+    Ok(args)
+}
+
+/// Splits `--config <path>` or `--config=<path>` off the arguments.
+fn take_config_path(args: Vec<OsString>) -> (Vec<OsString>, Option<PathBuf>) {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == CONFIG_FLAG {
+            path = args.next().map(PathBuf::from);
+        } else if let Some(value) = text
+            .strip_prefix(CONFIG_FLAG)
+            .and_then(|value| value.strip_prefix('='))
+        {
+            path = Some(PathBuf::from(value));
+        } else {
+            rest.push(arg);
+        }
+    }
+    (rest, path)
+}
+
+/// Options with an environment variable, by long name, from the top level
+/// and all subcommands.
+fn collect_args(command: &Command, configurable: &mut BTreeMap<String, Vec<ConfigurableArg>>) {
+    for arg in command.get_arguments() {
+        if let (Some(long), Some(env)) = (arg.get_long(), arg.get_env())
+            && env != CONFIG_ENV
+        {
+            let args = configurable.entry(long.to_string()).or_default();
+            if !args.iter().any(|known| known.env == env) {
+                args.push(ConfigurableArg {
+                    env: env.to_os_string(),
+                    delimiter: arg.get_value_delimiter(),
+                });
+            }
+        }
+    }
+    for subcommand in command.get_subcommands() {
+        collect_args(subcommand, configurable);
+    }
+}
+
+fn flatten(
+    table: &Table,
+    configurable: &BTreeMap<String, Vec<ConfigurableArg>>,
+    settings: &mut Vec<(OsString, String)>,
+) -> anyhow::Result<()> {
+    for (key, value) in table {
+        if let Value::Table(group) = value {
+            flatten(group, configurable, settings)?;
+            continue;
+        }
+        let long = key.replace('_', "-");
+        let args = configurable
+            .get(&long)
+            .ok_or_else(|| anyhow::anyhow!("Unknown option {key} in the config file"))?;
+        for arg in args {
+            settings.push((arg.env.clone(), setting(key, value, arg.delimiter)?));
+        }
+    }
+    Ok(())
+}
+
+fn setting(key: &str, value: &Value, delimiter: Option<char>) -> anyhow::Result<String> {
+    Ok(match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(values) => {
+            let delimiter =
+                delimiter.ok_or_else(|| anyhow::anyhow!("Option {key} takes a single value"))?;
+            values
+                .iter()
+                .map(|value| setting(key, value, None))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join(&delimiter.to_string())
+        }
+        Value::Table(_) => anyhow::bail!("Option {key} can't be a table"),
+    })
+}
@@ -0,0 +1,97 @@
+use fedimint_core::anyhow;
+use fedimint_gateway_common::{PaymentStats, PaymentSummaryResponse};
+use tokio_postgres::Client;
+
+use crate::{msat::Msat, payments::PAYMENTS_QUERY, time_window::TimeWindow};
+
+/// One number of the daily summary as reported by the gateway and as derived
+/// from the event tables.
+#[derive(Debug, Clone)]
+pub(crate) struct MetricDrift {
+    pub direction: &'static str,
+    pub metric: &'static str,
+    pub rpc: i128,
+    pub etl: i128,
+}
+
+impl MetricDrift {
+    /// How far the event tables are off, positive if they count more.
+    pub fn drift(&self) -> i128 {
+        self.etl - self.rpc
+    }
+}
+
+/// Compares the gateway's `payment_summary` with the same numbers computed
+/// from the ingested events. Both are built from the gateway's event log, so
+/// any difference means events were lost, duplicated or interpreted
+/// differently on one side.
+#[derive(Debug, Clone)]
+pub(crate) struct SummaryDrift {
+    pub metrics: Vec<MetricDrift>,
+}
+
+impl SummaryDrift {
+    pub async fn query(
+        pg_client: &Client,
+        window: TimeWindow,
+        summary: &PaymentSummaryResponse,
+    ) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let mut metrics = Vec::new();
+        for (direction, stats) in [
+            ("outgoing", &summary.outgoing),
+            ("incoming", &summary.incoming),
+        ] {
+            let row = pg_client
+                .query_one(
+                    &format!(
+                        "
+                        WITH payments AS ({PAYMENTS_QUERY})
+                        SELECT
+                            COUNT(*) FILTER (WHERE outcome = 'succeeded'),
+                            COUNT(*) FILTER (WHERE outcome = 'failed'),
+                            COALESCE(SUM(fee_msat) FILTER (WHERE outcome = 'succeeded'), 0)::TEXT
+                        FROM payments
+                        WHERE direction = $1 AND ts >= $2 AND ts < $3
+                        "
+                    ),
+                    &[&direction, &start, &end],
+                )
+                .await?;
+            let succeeded: i64 = row.get(0);
+            let failed: i64 = row.get(1);
+            let fees: Msat = row.get(2);
+            let PaymentStats {
+                total_success,
+                total_failure,
+                total_fees,
+                ..
+            } = stats;
+            metrics.extend([
+                MetricDrift {
+                    direction,
+                    metric: "succeeded",
+                    rpc: *total_success as i128,
+                    etl: succeeded.into(),
+                },
+                MetricDrift {
+                    direction,
+                    metric: "failed",
+                    rpc: *total_failure as i128,
+                    etl: failed.into(),
+                },
+                MetricDrift {
+                    direction,
+                    metric: "fees_msat",
+                    rpc: total_fees.msats.into(),
+                    etl: fees.msats(),
+                },
+            ]);
+        }
+        Ok(Self { metrics })
+    }
+
+    pub fn discrepancies(&self) -> impl Iterator<Item = &MetricDrift> {
+        self.metrics.iter().filter(|metric| metric.drift() != 0)
+    }
+}
@@ -0,0 +1,145 @@
+use chrono::Utc;
+use fedimint_core::anyhow;
+use rand::Rng;
+use serde_json::{Value, json};
+use tracing::warn;
+
+/// Sends errors and panics that end a run to Sentry, or to a self-hosted
+/// GlitchTip which speaks the same protocol, together with the redacted
+/// configuration of the run.
+#[derive(Debug, Clone)]
+pub(crate) struct CrashReporter {
+    dsn: String,
+    /// `<base>/api/<project_id>/envelope/`
+    endpoint: String,
+    key: String,
+    context: Value,
+    client: reqwest::Client,
+}
+
+impl CrashReporter {
+    /// `dsn` is the project's `https://<key>@<host>/<project_id>`.
+    pub fn new(dsn: &str, context: Value) -> anyhow::Result<Self> {
+        let url = url::Url::parse(dsn)?;
+        if url.username().is_empty() {
+            anyhow::bail!("The Sentry DSN has no key");
+        }
+        let mut segments = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .filter(|segment| !segment.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let project_id = segments
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("The Sentry DSN has no project id"))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("The Sentry DSN has no host"))?;
+        let port = url
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+        let prefix = segments
+            .iter()
+            .map(|segment| format!("/{segment}"))
+            .collect::<String>();
+        Ok(Self {
+            dsn: dsn.to_string(),
+            endpoint: format!(
+                "{}://{host}{port}{prefix}/api/{project_id}/envelope/",
+                url.scheme()
+            ),
+            key: url.username().to_string(),
+            context,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Reports the error a run failed with. Failing to report is only
+    /// logged, the run's own error is what the caller returns.
+    pub async fn report_error(&self, err: &anyhow::Error) {
+        if let Err(report_err) = self.send("error", format!("{err:#}")).await {
+            warn!("Error reporting the error to Sentry: {}", report_err);
+        }
+    }
+
+    /// Reports panics before the default hook prints them. The report is
+    /// sent from its own thread and runtime, since the panicking thread may
+    /// be a worker of the main runtime, and is waited for as the process is
+    /// about to exit.
+    pub fn install_panic_hook(&self) {
+        let reporter = self.clone();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let message = match info.location() {
+                Some(location) => format!("{} at {location}", panic_message(info)),
+                None => panic_message(info),
+            };
+            let reporter = reporter.clone();
+            let sent = std::thread::spawn(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(reporter.send("fatal", message))
+            })
+            .join();
+            match sent {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => eprintln!("Error reporting the panic to Sentry: {err}"),
+                Err(_) => eprintln!("Error reporting the panic to Sentry"),
+            }
+            default_hook(info);
+        }));
+    }
+
+    async fn send(&self, level: &str, message: String) -> anyhow::Result<()> {
+        let event_id = format!("{:032x}", rand::thread_rng().r#gen::<u128>());
+        let event = json!({
+            "event_id": event_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "platform": "native",
+            "level": level,
+            "logger": env!("CARGO_PKG_NAME"),
+            "release": concat!(env!("CARGO_PKG_NAME"), "@", env!("CARGO_PKG_VERSION")),
+            "message": { "formatted": message },
+            "extra": self.context,
+        })
+        .to_string();
+        let envelope = format!(
+            "{}\n{}\n{event}\n",
+            json!({ "event_id": event_id, "dsn": self.dsn }),
+            json!({ "type": "event", "length": event.len() }),
+        );
+        self.client
+            .post(&self.endpoint)
+            .header(
+                "X-Sentry-Auth",
+                format!(
+                    "Sentry sentry_version=7, sentry_key={}, sentry_client={}/{}",
+                    self.key,
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                ),
+            )
+            .header("Content-Type", "application/x-sentry-envelope")
+            .timeout(std::time::Duration::from_secs(10))
+            .body(envelope)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+fn panic_message(info: &std::panic::PanicHookInfo<'_>) -> String {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Panic without a message".to_string()
+    }
+}
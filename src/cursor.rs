@@ -0,0 +1,49 @@
+use fedimint_core::{anyhow, config::FederationId};
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, clock::Clock};
+
+/// Where ingestion of a federation resumes: the newest log id of the
+/// gateway's event log that was processed, whether or not the event was
+/// stored. Advanced in the transaction that stores the events, so it can't
+/// get ahead of or fall behind the event tables.
+pub(crate) struct EtlCursor;
+
+impl EtlCursor {
+    /// The last processed log id, or 0 if nothing of `federation_id` was
+    /// ingested in `gateway_epoch` yet.
+    pub async fn get(
+        pg_client: &Client,
+        federation_id: FederationId,
+        gateway_epoch: GatewayEpoch,
+    ) -> anyhow::Result<i64> {
+        let row = pg_client
+            .query_opt(
+                "SELECT last_log_id FROM etl_cursor WHERE federation_id = $1 AND gateway_epoch = $2",
+                &[&federation_id.to_string(), &i32::from(gateway_epoch)],
+            )
+            .await?;
+        Ok(row.map_or(0, |row| row.get(0)))
+    }
+
+    pub async fn advance(
+        pg_client: &Client,
+        federation_id: FederationId,
+        gateway_epoch: GatewayEpoch,
+        log_id: i64,
+        clock: &Clock,
+    ) -> anyhow::Result<()> {
+        pg_client
+            .execute(
+                "INSERT INTO etl_cursor (federation_id, gateway_epoch, last_log_id, updated_at) VALUES ($1, $2, $3, $4) ON CONFLICT (federation_id, gateway_epoch) DO UPDATE SET last_log_id = GREATEST(etl_cursor.last_log_id, EXCLUDED.last_log_id), updated_at = EXCLUDED.updated_at",
+                &[
+                    &federation_id.to_string(),
+                    &i32::from(gateway_epoch),
+                    &log_id,
+                    &clock.now().naive_utc(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+}
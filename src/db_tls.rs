@@ -0,0 +1,155 @@
+use std::{path::Path, sync::Arc};
+
+use deadpool_postgres::Connect;
+use fedimint_core::anyhow;
+use futures_util::future::BoxFuture;
+use rustls::{
+    ClientConfig, RootCertStore,
+    crypto::aws_lc_rs,
+    pki_types::{CertificateDer, pem::PemObject},
+};
+use tokio::task::JoinHandle;
+use tokio_postgres::{
+    Client, Config, NoTls, Socket,
+    config::{Host, SslMode},
+    tls::MakeTlsConnect,
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::warn;
+
+/// Connects to the database with TLS as the `sslmode` asks, like libpq:
+/// `disable` never, `prefer` when the server supports it and falling back to
+/// plaintext if the handshake fails, and `require` always. Unix sockets never
+/// use TLS. The server's certificate is verified against the platform's roots
+/// and `root_cert`, a PEM file with the CA of a private database.
+pub(crate) struct DbConnect {
+    tls: MakeRustlsConnect,
+}
+
+impl DbConnect {
+    pub fn new(root_cert: Option<&Path>) -> anyhow::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in &native.errors {
+            warn!(%err, "Could not load a platform root certificate");
+        }
+        roots.add_parsable_certificates(native.certs);
+        if let Some(root_cert) = root_cert {
+            for cert in CertificateDer::pem_file_iter(root_cert).map_err(|err| {
+                anyhow::anyhow!("Invalid root certificate {}: {err}", root_cert.display())
+            })? {
+                roots.add(cert.map_err(|err| {
+                    anyhow::anyhow!("Invalid root certificate {}: {err}", root_cert.display())
+                })?)?;
+            }
+        }
+
+        // Both of rustls' crypto providers are in the build, so it can't pick one
+        let config = ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            tls: MakeRustlsConnect::new(config),
+        })
+    }
+}
+
+impl Connect for DbConnect {
+    fn connect(
+        &self,
+        pg_config: &Config,
+    ) -> BoxFuture<'_, Result<(Client, JoinHandle<()>), tokio_postgres::Error>> {
+        let pg_config = pg_config.clone();
+        Box::pin(async move {
+            // tokio-postgres asks the TLS connector for a hostname of "" for
+            // a socket, which rustls rejects even if TLS is never used. The
+            // server refuses TLS on sockets, so libpq ignores sslmode there.
+            let socket_only = pg_config
+                .get_hosts()
+                .iter()
+                .all(|host| matches!(host, Host::Unix(_)));
+            if socket_only || pg_config.get_ssl_mode() == SslMode::Disable {
+                let mut pg_config = pg_config.clone();
+                pg_config.ssl_mode(SslMode::Disable);
+                return spawn(&pg_config, NoTls).await;
+            }
+            match spawn(&pg_config, self.tls.clone()).await {
+                // tokio-postgres doesn't expose the kind of its errors
+                Err(err)
+                    if pg_config.get_ssl_mode() == SslMode::Prefer
+                        && err
+                            .to_string()
+                            .starts_with("error performing TLS handshake") =>
+                {
+                    warn!(%err, "TLS handshake with the database failed, connecting without TLS as sslmode=prefer allows");
+                    let mut pg_config = pg_config.clone();
+                    pg_config.ssl_mode(SslMode::Disable);
+                    spawn(&pg_config, NoTls).await
+                }
+                res => res,
+            }
+        })
+    }
+}
+
+/// Connects and drives the connection in a task of its own, as deadpool
+/// expects.
+async fn spawn<T>(
+    pg_config: &Config,
+    tls: T,
+) -> Result<(Client, JoinHandle<()>), tokio_postgres::Error>
+where
+    T: MakeTlsConnect<Socket>,
+    T::Stream: Send + 'static,
+{
+    let (pg_client, connection) = pg_config.connect(tls).await?;
+    let connection = tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            warn!(%err, "Database connection failed");
+        }
+    });
+    Ok((pg_client, connection))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use deadpool_postgres::Connect;
+    use tokio_postgres::{Config, config::SslMode};
+
+    use super::DbConnect;
+
+    async fn assert_connects(url_var: &str, ssl_modes: &[SslMode]) {
+        let url = std::env::var(url_var).unwrap_or_else(|_| panic!("{url_var} is set"));
+        let mut config = Config::from_str(&url).expect("The URL is valid");
+        for ssl_mode in ssl_modes {
+            config.ssl_mode(*ssl_mode);
+            let (pg_client, _connection) = DbConnect::new(None)
+                .unwrap()
+                .connect(&config)
+                .await
+                .unwrap_or_else(|err| panic!("Can connect with {ssl_mode:?}: {err}"));
+            pg_client.simple_query("SELECT 1").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_SOCKET_URL, e.g. postgresql:///etl_test?host=/tmp"]
+    async fn connects_through_a_socket() {
+        assert_connects(
+            "TEST_DATABASE_SOCKET_URL",
+            &[SslMode::Disable, SslMode::Prefer, SslMode::Require],
+        )
+        .await;
+    }
+
+    /// Passes whether the server has no TLS, a trusted certificate, or one
+    /// the platform doesn't trust.
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn prefer_connects_with_any_server_certificate() {
+        assert_connects("TEST_DATABASE_URL", &[SslMode::Prefer]).await;
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDateTime};
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::PersistedLogEntry;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, parse_log_id};
+
+/// An event whose payload doesn't match what its parser expects. Such events
+/// go to `dead_letter_events` instead of failing the run, since the run would
+/// fail on the same event every time and ingestion would be stuck.
+#[derive(Debug)]
+pub(crate) struct ParseError(String);
+
+impl ParseError {
+    pub fn new(err: impl fmt::Display) -> Self {
+        Self(err.to_string())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub(crate) fn parse<T: DeserializeOwned>(value: Value) -> Result<T, ParseError> {
+    serde_json::from_value(value).map_err(ParseError::new)
+}
+
+/// Converts microseconds since the epoch, failing for timestamps chrono
+/// can't represent.
+pub(crate) fn timestamp(micros: i64) -> Result<NaiveDateTime, ParseError> {
+    DateTime::from_timestamp_micros(micros)
+        .map(|ts| ts.naive_utc())
+        .ok_or_else(|| ParseError::new(format!("Timestamp {micros} is out of range")))
+}
+
+/// Stores `entry` with its raw payload and the parse error. Counts as an
+/// ingested event, so the run's ingest stats still add up. The timestamp is
+/// left empty if it is what failed to parse.
+pub(crate) async fn insert(
+    pg_client: &Client,
+    entry: &PersistedLogEntry,
+    federation_id: &FederationId,
+    federation_name: String,
+    gateway_epoch: GatewayEpoch,
+    kind: &str,
+    err: &ParseError,
+) -> anyhow::Result<()> {
+    pg_client.execute("INSERT INTO dead_letter_events (log_id, ts, federation_id, federation_name, gateway_epoch, module, kind, payload, error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO NOTHING",
+        &[
+            &parse_log_id(&entry.id())?,
+            &timestamp(entry.ts_usecs as i64).ok(),
+            &federation_id.to_string(),
+            &federation_name,
+            &i32::from(gateway_epoch),
+            &entry.module.as_ref().map(|(module, _)| module.as_str()),
+            &kind,
+            &String::from_utf8_lossy(&entry.payload),
+            &err.to_string(),
+        ]).await?;
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use std::{collections::BTreeMap, fmt};
+
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use url::form_urlencoded;
+
+use crate::{payments::PAYMENTS_QUERY, time_window::TimeWindow};
+
+/// Failure reasons are listed per federation up to this many.
+const MAX_REASONS: usize = 5;
+
+/// A URL with `{federation_id}`, `{reason}`, `{from}` and `{to}` placeholders
+/// pointing at a detail view, e.g. a Grafana dashboard:
+/// `https://grafana.example.com/d/payments?var-federation={federation_id}&var-reason={reason}&from={from}&to={to}`.
+/// `{from}` and `{to}` are the bounds of the summary window in milliseconds
+/// since the unix epoch. `{reason}` is empty in links to a whole federation.
+#[derive(Debug, Clone)]
+pub(crate) struct LinkTemplate(String);
+
+impl std::str::FromStr for LinkTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        anyhow::ensure!(
+            s.contains("{federation_id}"),
+            "Drill-down URL template must contain {{federation_id}}"
+        );
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl LinkTemplate {
+    fn render(&self, federation_id: &str, reason: &str, window: TimeWindow) -> String {
+        let encode =
+            |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+        let (from, to) = window.millis_bounds();
+        self.0
+            .replace("{federation_id}", &encode(federation_id))
+            .replace("{reason}", &encode(reason))
+            .replace("{from}", &from.to_string())
+            .replace("{to}", &to.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+struct FederationFailures {
+    federation_name: String,
+    /// `(reason, count)`, most frequent first.
+    reasons: Vec<(String, i64)>,
+}
+
+/// Failed payments within a window per federation and reason, each with a
+/// link to the operator's detail view.
+pub(crate) struct FailureLinks {
+    template: LinkTemplate,
+    window: TimeWindow,
+    federations: BTreeMap<String, FederationFailures>,
+}
+
+impl FailureLinks {
+    pub async fn query(
+        pg_client: &Client,
+        window: TimeWindow,
+        template: LinkTemplate,
+    ) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let rows = pg_client
+            .query(
+                &format!(
+                    "
+                    WITH payments AS ({PAYMENTS_QUERY})
+                    SELECT federation_id, MAX(federation_name), COALESCE(error, 'unknown'), COUNT(*)
+                    FROM payments
+                    WHERE outcome = 'failed' AND ts >= $1 AND ts < $2
+                    GROUP BY federation_id, COALESCE(error, 'unknown')
+                    ORDER BY COUNT(*) DESC
+                    "
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        let mut federations = BTreeMap::<String, FederationFailures>::new();
+        for row in rows {
+            let failures = federations.entry(row.get(0)).or_default();
+            failures.federation_name = row.get(1);
+            failures.reasons.push((row.get(2), row.get(3)));
+        }
+
+        Ok(Self {
+            template,
+            window,
+            federations,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.federations.is_empty()
+    }
+}
+
+impl fmt::Display for FailureLinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "===========FAILURES===========")?;
+        for (federation_id, failures) in &self.federations {
+            writeln!(
+                f,
+                "{}: {}",
+                failures.federation_name,
+                self.template.render(federation_id, "", self.window)
+            )?;
+            for (reason, count) in failures.reasons.iter().take(MAX_REASONS) {
+                writeln!(
+                    f,
+                    "{reason} ({count}): {}",
+                    self.template.render(federation_id, reason, self.window)
+                )?;
+            }
+        }
+        writeln!(f)
+    }
+}
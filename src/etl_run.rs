@@ -0,0 +1,217 @@
+use fedimint_core::{
+    anyhow,
+    bitcoin::hashes::{Hash, sha256},
+    config::FederationId,
+};
+use serde_json::Value;
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{GatewayEpoch, clock::Clock, ingest::IngestStats};
+
+/// One execution of the ETL, recorded in `etl_runs` together with a
+/// fingerprint of the effective configuration. A dry run isn't recorded.
+#[derive(Debug, Clone)]
+pub(crate) struct EtlRun {
+    id: i64,
+    clock: Clock,
+    dry_run: bool,
+    /// Top level config keys that differ from the previous run.
+    pub config_changes: Vec<String>,
+}
+
+impl EtlRun {
+    /// Records the start of a run. `config` must not contain secrets, it is
+    /// stored verbatim so runs can be diffed later. A dry run is only
+    /// compared with the previous run, it gets id 0 and writes nothing.
+    pub async fn start(
+        pg_client: &Client,
+        gateway_epoch: GatewayEpoch,
+        config: Value,
+        clock: Clock,
+        dry_run: bool,
+    ) -> anyhow::Result<Self> {
+        let config_json = serde_json::to_string(&config)?;
+        let config_hash = sha256::Hash::hash(config_json.as_bytes()).to_string();
+
+        let previous = pg_client
+            .query_opt(
+                "SELECT config_hash, config FROM etl_runs ORDER BY id DESC LIMIT 1",
+                &[],
+            )
+            .await?;
+        let config_changes = match previous {
+            Some(row) if row.get::<_, String>(0) != config_hash => {
+                let previous_config: Value = row.get(1);
+                diff_keys(&previous_config, &config)
+            }
+            _ => Vec::new(),
+        };
+        if !config_changes.is_empty() {
+            info!(?config_changes, "Config changed since the previous run");
+        }
+
+        if dry_run {
+            return Ok(Self {
+                id: 0,
+                clock,
+                dry_run,
+                config_changes,
+            });
+        }
+        let row = pg_client
+            .query_one(
+                "INSERT INTO etl_runs (started_at, gateway_epoch, status, config_hash, config) VALUES ($1, $2, 'running', $3, $4) RETURNING id",
+                &[&clock.now().naive_utc(), &i32::from(gateway_epoch), &config_hash, &config],
+            )
+            .await?;
+
+        Ok(Self {
+            id: row.get(0),
+            clock,
+            dry_run,
+            config_changes,
+        })
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Where this run takes the current time from.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Stores the ingestion stats of all federations. A run in which not every
+    /// fetched event is accounted for ends as `degraded` instead of
+    /// `succeeded`.
+    pub async fn record_ingest(
+        &self,
+        pg_client: &Client,
+        stats: &[IngestStats],
+    ) -> anyhow::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let degraded = stats.iter().any(|stats| !stats.is_consistent());
+        pg_client
+            .execute(
+                "UPDATE etl_runs SET ingest_stats = $1, status = CASE WHEN $2 THEN 'degraded' ELSE status END WHERE id = $3",
+                &[&serde_json::to_value(stats)?, &degraded, &self.id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Records that all events of `federation_id` up to `log_id` are stored.
+    /// Meant to be called in the transaction that stored them.
+    pub async fn record_checkpoint(
+        &self,
+        pg_client: &Client,
+        federation_id: FederationId,
+        log_id: i64,
+    ) -> anyhow::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        pg_client
+            .execute(
+                "UPDATE etl_runs SET backfill_checkpoints = COALESCE(backfill_checkpoints, '{}') || jsonb_build_object($1::TEXT, $2::INT8) WHERE id = $3",
+                &[&federation_id.to_string(), &log_id, &self.id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Records how the run ended.
+    pub async fn finish(
+        &self,
+        pg_client: &Client,
+        result: &anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let (status, error) = match result {
+            Ok(()) => ("succeeded", None),
+            Err(err) => ("failed", Some(format!("{err:#}"))),
+        };
+        if self.dry_run {
+            info!(status, ?error, "Dry run finished");
+            return Ok(());
+        }
+        pg_client
+            .execute(
+                "UPDATE etl_runs SET finished_at = $1, status = CASE WHEN status = 'degraded' AND $2 = 'succeeded' THEN status ELSE $2 END, error = $3 WHERE id = $4",
+                &[&self.clock.now().naive_utc(), &status, &error, &self.id],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+fn diff_keys(previous: &Value, current: &Value) -> Vec<String> {
+    let (Some(previous), Some(current)) = (previous.as_object(), current.as_object()) else {
+        return vec!["config".to_string()];
+    };
+    let mut keys = previous
+        .keys()
+        .chain(current.keys())
+        .filter(|key| previous.get(*key) != current.get(*key))
+        .cloned()
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::anyhow;
+    use serde_json::json;
+    use tokio_postgres::Client;
+
+    use super::EtlRun;
+    use crate::{clock::Clock, ingest::IngestStats, test_db};
+
+    async fn run_count(pg_client: &Client) -> i64 {
+        pg_client
+            .query_one("SELECT COUNT(*) FROM etl_runs", &[])
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn dry_run_is_not_recorded() {
+        let pg_client = test_db::connect().await;
+        let epoch = "0".parse().unwrap();
+        let run = EtlRun::start(&pg_client, epoch, json!({"a": 1}), Clock::System, false)
+            .await
+            .unwrap();
+        run.finish(&pg_client, &Ok(())).await.unwrap();
+        assert_eq!(run_count(&pg_client).await, 1);
+
+        let dry_run = EtlRun::start(&pg_client, epoch, json!({"a": 2}), Clock::System, true)
+            .await
+            .unwrap();
+        assert_eq!(dry_run.config_changes, vec!["a".to_string()]);
+        let stats = IngestStats {
+            federation_name: "Federation".to_string(),
+            fetched: 1,
+            skipped: 0,
+            written: BTreeMap::new(),
+        };
+        dry_run.record_ingest(&pg_client, &[stats]).await.unwrap();
+        dry_run
+            .finish(&pg_client, &Err(anyhow::anyhow!("Failed")))
+            .await
+            .unwrap();
+        assert_eq!(run_count(&pg_client).await, 1);
+    }
+}
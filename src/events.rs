@@ -0,0 +1,216 @@
+use std::{convert::Infallible, fmt, str::FromStr};
+
+use fedimint_core::anyhow;
+use fedimint_eventlog::EventKind;
+use serde_json::json;
+
+use crate::output::{OutputFormat, Rows};
+
+/// An event kind understood by this binary and where it ends up.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EventRegistration {
+    pub module: &'static str,
+    pub kind: &'static str,
+    pub table: &'static str,
+    /// Bumped whenever the parser or the target table of the event changes in
+    /// a way that is visible to consumers.
+    pub parser_version: u32,
+}
+
+const fn event(
+    module: &'static str,
+    kind: &'static str,
+    table: &'static str,
+    parser_version: u32,
+) -> EventRegistration {
+    EventRegistration {
+        module,
+        kind,
+        table,
+        parser_version,
+    }
+}
+
+/// Every event kind the `FederationEventProcessor` ingests. Keep this in sync
+/// with `handle_lnv1`, `handle_lnv2` and `GatewayLedgerEntry::parse`.
+pub(crate) const EVENT_REGISTRY: &[EventRegistration] = &[
+    event(
+        "ln",
+        "outgoing-payment-started",
+        "lnv1_outgoing_payment_started",
+        1,
+    ),
+    event(
+        "ln",
+        "outgoing-payment-succeeded",
+        "lnv1_outgoing_payment_succeeded",
+        1,
+    ),
+    event(
+        "ln",
+        "outgoing-payment-failed",
+        "lnv1_outgoing_payment_failed",
+        1,
+    ),
+    event(
+        "ln",
+        "incoming-payment-started",
+        "lnv1_incoming_payment_started",
+        1,
+    ),
+    event(
+        "ln",
+        "incoming-payment-succeeded",
+        "lnv1_incoming_payment_succeeded",
+        1,
+    ),
+    event(
+        "ln",
+        "incoming-payment-failed",
+        "lnv1_incoming_payment_failed",
+        1,
+    ),
+    event(
+        "ln",
+        "complete-lightning-payment-succeeded",
+        "lnv1_complete_lightning_payment_succeeded",
+        1,
+    ),
+    event("ln", "contract-cancelled", "lnv1_contract_cancelled", 1),
+    event("ln", "refund-claimed", "lnv1_refund_claimed", 1),
+    event(
+        "lnv2",
+        "outgoing-payment-started",
+        "lnv2_outgoing_payment_started",
+        1,
+    ),
+    event(
+        "lnv2",
+        "outgoing-payment-succeeded",
+        "lnv2_outgoing_payment_succeeded",
+        1,
+    ),
+    event(
+        "lnv2",
+        "outgoing-payment-failed",
+        "lnv2_outgoing_payment_failed",
+        1,
+    ),
+    event(
+        "lnv2",
+        "incoming-payment-started",
+        "lnv2_incoming_payment_started",
+        1,
+    ),
+    event(
+        "lnv2",
+        "incoming-payment-succeeded",
+        "lnv2_incoming_payment_succeeded",
+        1,
+    ),
+    event(
+        "lnv2",
+        "incoming-payment-failed",
+        "lnv2_incoming_payment_failed",
+        1,
+    ),
+    event(
+        "lnv2",
+        "complete-lightning-payment-succeeded",
+        "lnv2_complete_lightning_payment_succeeded",
+        1,
+    ),
+    event("lnv2", "contract-cancelled", "lnv2_contract_cancelled", 1),
+    event("lnv2", "refund-claimed", "lnv2_refund_claimed", 1),
+    event("mint", "payment-send", "gateway_ledger", 1),
+    event("mint", "payment-receive", "gateway_ledger", 1),
+    event("mint", "oob-notes-spent", "gateway_ledger", 1),
+    event("mint", "oob-notes-reissued", "gateway_ledger", 1),
+    event("wallet", "payment-send", "gateway_ledger", 1),
+    event("wallet", "payment-receive", "gateway_ledger", 1),
+    event("wallet", "payment-receive", "onchain_transactions", 1),
+    event("wallet", "deposit-confirmed", "onchain_transactions", 1),
+    event("wallet", "withdraw-request", "onchain_transactions", 1),
+    event("wallet", "payment-send-status", "onchain_transactions", 1),
+];
+
+/// The kind of an `ln` or `lnv2` event. Both modules use the same kinds.
+/// Kinds this binary doesn't know, e.g. ones added upstream since, are kept
+/// as `Unknown` rather than failing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum GatewayEventKind {
+    OutgoingPaymentStarted,
+    OutgoingPaymentSucceeded,
+    OutgoingPaymentFailed,
+    IncomingPaymentStarted,
+    IncomingPaymentSucceeded,
+    IncomingPaymentFailed,
+    CompleteLightningPaymentSucceeded,
+    ContractCancelled,
+    RefundClaimed,
+    Unknown(String),
+}
+
+impl GatewayEventKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::OutgoingPaymentStarted => "outgoing-payment-started",
+            Self::OutgoingPaymentSucceeded => "outgoing-payment-succeeded",
+            Self::OutgoingPaymentFailed => "outgoing-payment-failed",
+            Self::IncomingPaymentStarted => "incoming-payment-started",
+            Self::IncomingPaymentSucceeded => "incoming-payment-succeeded",
+            Self::IncomingPaymentFailed => "incoming-payment-failed",
+            Self::CompleteLightningPaymentSucceeded => "complete-lightning-payment-succeeded",
+            Self::ContractCancelled => "contract-cancelled",
+            Self::RefundClaimed => "refund-claimed",
+            Self::Unknown(kind) => kind,
+        }
+    }
+}
+
+impl FromStr for GatewayEventKind {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "outgoing-payment-started" => Self::OutgoingPaymentStarted,
+            "outgoing-payment-succeeded" => Self::OutgoingPaymentSucceeded,
+            "outgoing-payment-failed" => Self::OutgoingPaymentFailed,
+            "incoming-payment-started" => Self::IncomingPaymentStarted,
+            "incoming-payment-succeeded" => Self::IncomingPaymentSucceeded,
+            "incoming-payment-failed" => Self::IncomingPaymentFailed,
+            "complete-lightning-payment-succeeded" => Self::CompleteLightningPaymentSucceeded,
+            "contract-cancelled" => Self::ContractCancelled,
+            "refund-claimed" => Self::RefundClaimed,
+            kind => Self::Unknown(kind.to_string()),
+        })
+    }
+}
+
+impl From<&EventKind> for GatewayEventKind {
+    fn from(kind: &EventKind) -> Self {
+        match kind.to_string().parse() {
+            Ok(kind) => kind,
+            Err(infallible) => match infallible {},
+        }
+    }
+}
+
+impl fmt::Display for GatewayEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub(crate) fn list(format: OutputFormat) -> anyhow::Result<()> {
+    let mut rows = Rows::new(&["module", "kind", "table", "parser_version"]);
+    for registration in EVENT_REGISTRY {
+        rows.push(vec![
+            json!(registration.module),
+            json!(registration.kind),
+            json!(registration.table),
+            json!(registration.parser_version),
+        ]);
+    }
+    rows.print(format)
+}
@@ -0,0 +1,205 @@
+use std::{fs, io::Write, path::Path};
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use deadpool_postgres::Pool;
+use fedimint_core::{anyhow, config::FederationId};
+use futures_util::{TryStreamExt, pin_mut};
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{
+    anonymize::{ANONYMIZED_COLUMNS, Anonymizer},
+    payments::PAYMENTS_QUERY,
+    schema,
+};
+
+/// Columns that must not leave the gateway operator's hands: preimages prove
+/// payment and the keys belong to contracts. The raw payloads of dead-lettered
+/// events may contain either.
+const REDACTED_COLUMNS: &[&str] = &[
+    "preimage",
+    "gateway_key",
+    "user_key",
+    "claim_pk",
+    "ephemeral_pk",
+    "refund_pk",
+    "operation_id",
+    "payload",
+];
+
+/// File format of `export`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum ExportFormat {
+    /// One CSV file per table
+    Csv,
+    /// Parquet files partitioned by table and day
+    #[cfg(feature = "parquet-export")]
+    Parquet,
+}
+
+/// The event tables named in `tables`, or all of them if it is empty.
+pub(crate) fn selected_event_tables(
+    tables: &[String],
+) -> anyhow::Result<Vec<(&'static str, &'static [&'static str])>> {
+    for table in tables {
+        anyhow::ensure!(
+            schema::event_tables().any(|(event_table, _)| event_table == table),
+            "{table} is not an event table"
+        );
+    }
+    Ok(schema::event_tables()
+        .filter(|(table, _)| tables.is_empty() || tables.iter().any(|name| name == table))
+        .collect())
+}
+
+/// Writes one CSV file per selected event table into `out_dir`, with the
+/// rows of the days from `since` up to `until`, for spreadsheets. Rows are
+/// written as they arrive, so large tables don't have to fit in memory.
+pub(crate) async fn export_tables(
+    pool: &Pool,
+    tables: &[String],
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !out_dir.starts_with("s3:"),
+        "CSV files are only written to a local directory"
+    );
+    let tables = selected_event_tables(tables)?;
+    fs::create_dir_all(out_dir)?;
+    let pg_client = pool.get().await?;
+
+    // COPY doesn't take parameters, the dates are safe to inline since they
+    // were parsed as a `NaiveDate`
+    let mut conditions = Vec::new();
+    if let Some(since) = since {
+        conditions.push(format!("ts >= '{since}'"));
+    }
+    if let Some(until) = until {
+        conditions.push(format!("ts < '{until}'"));
+    }
+    let filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    for (table, columns) in &tables {
+        let query = format!(
+            "SELECT {} FROM {table} {filter} ORDER BY ts, log_id",
+            columns.join(", ")
+        );
+        copy_to_csv(
+            &pg_client,
+            &query,
+            &out_dir.join(format!("{table}.csv")),
+            None,
+        )
+        .await?;
+    }
+
+    println!("Exported {} tables to {}", tables.len(), out_dir.display());
+    Ok(())
+}
+
+/// Writes one CSV file per event table with the rows of a single federation,
+/// plus `payments.csv` with the flattened payments, into `out_dir`. With
+/// `redact`, preimages, keys and operation ids are left out so the dataset can
+/// be shared with the federation's guardians. With an `anonymizer`, the
+/// remaining preimages and operation ids are left out as well and
+/// identifiers are replaced with their keyed hash, for sharing with
+/// researchers.
+pub(crate) async fn export_federation(
+    pool: &Pool,
+    federation_id: FederationId,
+    out_dir: &Path,
+    redact: bool,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let pg_client = pool.get().await?;
+
+    // COPY doesn't take parameters, the id is safe to inline since it was
+    // parsed as a `FederationId`
+    let filter = format!("federation_id = '{federation_id}'");
+    for (table, columns) in schema::event_tables() {
+        let columns = columns
+            .iter()
+            .filter(|column| {
+                !REDACTED_COLUMNS.contains(column)
+                    || match anonymizer {
+                        Some(_) => ANONYMIZED_COLUMNS.contains(column) && !redact,
+                        None => !redact,
+                    }
+            })
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query =
+            format!("SELECT {columns} FROM {table} WHERE {filter} ORDER BY gateway_epoch, log_id");
+        copy_to_csv(
+            &pg_client,
+            &query,
+            &out_dir.join(format!("{table}.csv")),
+            anonymizer,
+        )
+        .await?;
+    }
+
+    let query = format!(
+        "WITH payments AS ({PAYMENTS_QUERY}) SELECT * FROM payments WHERE {filter} ORDER BY ts"
+    );
+    copy_to_csv(
+        &pg_client,
+        &query,
+        &out_dir.join("payments.csv"),
+        anonymizer,
+    )
+    .await?;
+
+    println!(
+        "Exported federation {federation_id} to {}{}{}",
+        out_dir.display(),
+        if redact { " (redacted)" } else { "" },
+        if anonymizer.is_some() {
+            " (anonymized)"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+async fn copy_to_csv(
+    pg_client: &Client,
+    query: &str,
+    path: &Path,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<()> {
+    let stream = pg_client
+        .copy_out(&format!(
+            "COPY ({query}) TO STDOUT WITH (FORMAT csv, HEADER)"
+        ))
+        .await?;
+    pin_mut!(stream);
+    let Some(anonymizer) = anonymizer else {
+        let mut file = fs::File::create(path)?;
+        let mut bytes = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            file.write_all(&chunk)?;
+            bytes += chunk.len();
+        }
+        info!(path = %path.display(), bytes, "Wrote CSV");
+        return Ok(());
+    };
+    // The anonymizer rewrites whole CSV records, so the file is buffered
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.try_next().await? {
+        data.extend_from_slice(&chunk);
+    }
+    let data = anonymizer.anonymize_csv(&data)?;
+    fs::write(path, &data)?;
+    info!(path = %path.display(), bytes = data.len(), "Wrote CSV");
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use chrono::NaiveDateTime;
+use fedimint_core::anyhow;
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::{payments::PAYMENTS_QUERY, time_window::TimeWindow};
+
+#[derive(Debug, Serialize)]
+struct FailedPayment {
+    ts: NaiveDateTime,
+    federation_id: String,
+    federation_name: String,
+    protocol: String,
+    direction: String,
+    payment_hash: Option<String>,
+    amount_msat: Option<i64>,
+    reason: String,
+    error: Option<String>,
+}
+
+/// The failed payments of a window, to be attached to the report as CSV so
+/// triage can start from the notification.
+pub(crate) struct FailedPayments(Vec<FailedPayment>);
+
+impl FailedPayments {
+    pub async fn query(pg_client: &Client, window: TimeWindow) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let rows = pg_client
+            .query(
+                &format!(
+                    "
+                    WITH payments AS ({PAYMENTS_QUERY})
+                    SELECT ts, federation_id, federation_name, protocol, direction, payment_hash, amount_msat::INT8, error
+                    FROM payments
+                    WHERE outcome = 'failed' AND ts >= $1 AND ts < $2
+                    ORDER BY ts
+                    "
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        Ok(Self(
+            rows.iter()
+                .map(|row| {
+                    let error: Option<String> = row.get(7);
+                    FailedPayment {
+                        ts: row.get(0),
+                        federation_id: row.get(1),
+                        federation_name: row.get(2),
+                        protocol: row.get(3),
+                        direction: row.get(4),
+                        payment_hash: row.get(5),
+                        amount_msat: row.get(6),
+                        reason: normalize_reason(error.as_deref()),
+                        error,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for payment in &self.0 {
+            writer.serialize(payment)?;
+        }
+        Ok(writer.into_inner().map_err(|err| err.into_error())?)
+    }
+}
+
+/// Groups error messages that only differ in the payment they are about:
+/// hex strings (hashes, keys, ids) and numbers (amounts, heights) are
+/// replaced with placeholders.
+fn normalize_reason(error: Option<&str>) -> String {
+    let Some(error) = error else {
+        return "unknown".to_string();
+    };
+    error
+        .split_inclusive(|c: char| !c.is_ascii_alphanumeric())
+        .map(|word| {
+            let end = word
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(word.len());
+            let (token, rest) = word.split_at(end);
+            let replacement = if token.len() >= 16 && token.chars().all(|c| c.is_ascii_hexdigit()) {
+                "<hex>"
+            } else if !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) {
+                "<n>"
+            } else {
+                token
+            };
+            format!("{replacement}{rest}")
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_gateway_common::FederationConfig;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::clock::Clock;
+
+/// The subset of a federation's config that affects the economics of the
+/// gateway. Snapshots are stored every run so changes can be detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FederationConfigSnapshot {
+    lightning_base_msat: i64,
+    lightning_ppm: i64,
+    transaction_base_msat: i64,
+    transaction_ppm: i64,
+    tos_url: Option<String>,
+    modules: String,
+}
+
+impl FederationConfigSnapshot {
+    pub fn new(config: &FederationConfig, client_config: Option<&Value>) -> Self {
+        let tos_url = client_config
+            .and_then(|config| config["global"]["meta"]["tos_url"].as_str())
+            .map(|s| s.to_string());
+
+        let mut modules = client_config
+            .and_then(|config| config["modules"].as_object())
+            .map(|modules| {
+                modules
+                    .values()
+                    .filter_map(|module| module["kind"].as_str())
+                    .map(|kind| kind.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        modules.sort();
+
+        Self {
+            lightning_base_msat: config.lightning_fee.base.msats as i64,
+            lightning_ppm: config.lightning_fee.parts_per_million as i64,
+            transaction_base_msat: config.transaction_fee.base.msats as i64,
+            transaction_ppm: config.transaction_fee.parts_per_million as i64,
+            tos_url,
+            modules: modules.join(","),
+        }
+    }
+
+    pub async fn get_latest(
+        pg_client: &Client,
+        federation_id: &FederationId,
+    ) -> anyhow::Result<Option<FederationConfigSnapshot>> {
+        let row = pg_client
+            .query_opt(
+                "SELECT lightning_base_msat, lightning_ppm, transaction_base_msat, transaction_ppm, tos_url, modules FROM federation_config_snapshots WHERE federation_id = $1 ORDER BY ts DESC LIMIT 1",
+                &[&federation_id.to_string()],
+            )
+            .await?;
+
+        Ok(row.map(|row| FederationConfigSnapshot {
+            lightning_base_msat: row.get(0),
+            lightning_ppm: row.get(1),
+            transaction_base_msat: row.get(2),
+            transaction_ppm: row.get(3),
+            tos_url: row.get(4),
+            modules: row.get(5),
+        }))
+    }
+
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        federation_id: &FederationId,
+        federation_name: String,
+        clock: &Clock,
+    ) -> anyhow::Result<()> {
+        let timestamp = clock.now().naive_utc();
+        pg_client.execute("INSERT INTO federation_config_snapshots (ts, federation_id, federation_name, lightning_base_msat, lightning_ppm, transaction_base_msat, transaction_ppm, tos_url, modules) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        &[&timestamp, &federation_id.to_string(), &federation_name, &self.lightning_base_msat, &self.lightning_ppm, &self.transaction_base_msat, &self.transaction_ppm, &self.tos_url, &self.modules]).await?;
+        Ok(())
+    }
+
+    /// Returns the list of `(field, old_value, new_value)` that differ between
+    /// `previous` and `self`.
+    pub fn diff(&self, previous: &FederationConfigSnapshot) -> Vec<(&'static str, String, String)> {
+        let fields = [
+            (
+                "lightning_base_msat",
+                previous.lightning_base_msat.to_string(),
+                self.lightning_base_msat.to_string(),
+            ),
+            (
+                "lightning_ppm",
+                previous.lightning_ppm.to_string(),
+                self.lightning_ppm.to_string(),
+            ),
+            (
+                "transaction_base_msat",
+                previous.transaction_base_msat.to_string(),
+                self.transaction_base_msat.to_string(),
+            ),
+            (
+                "transaction_ppm",
+                previous.transaction_ppm.to_string(),
+                self.transaction_ppm.to_string(),
+            ),
+            (
+                "tos_url",
+                previous.tos_url.clone().unwrap_or_default(),
+                self.tos_url.clone().unwrap_or_default(),
+            ),
+            ("modules", previous.modules.clone(), self.modules.clone()),
+        ];
+
+        fields
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .collect()
+    }
+
+    pub async fn insert_changes(
+        pg_client: &Client,
+        federation_id: &FederationId,
+        federation_name: String,
+        changes: &[(&'static str, String, String)],
+        clock: &Clock,
+    ) -> anyhow::Result<()> {
+        let timestamp = clock.now().naive_utc();
+        for (field, old_value, new_value) in changes {
+            pg_client.execute("INSERT INTO federation_config_changes (ts, federation_id, federation_name, field, old_value, new_value) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&timestamp, &federation_id.to_string(), &federation_name, field, old_value, new_value]).await?;
+        }
+        Ok(())
+    }
+}
@@ -1,35 +1,85 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt, num::NonZeroUsize, ops::Deref, time::Duration};
 
+use deadpool_postgres::{Object, Pool};
 use fedimint_core::{anyhow, bitcoin, config::FederationId, util::SafeUrl};
-use fedimint_eventlog::{EventKind, EventLogId};
+use fedimint_eventlog::{EventKind, EventLogId, PersistedLogEntry};
 use fedimint_gateway_client::payment_log;
 use fedimint_gateway_common::{FederationInfo, PaymentLogPayload};
-use fedimint_ln_common::client::GatewayApi;
-use serde_json::Value;
+use serde_json::{Value, json};
 use tokio_postgres::Client;
-use tracing::warn;
+use tracing::{info, warn};
 
 use crate::{
-    DbConnection, LNv1CompleteLightningPaymentSucceeded, LNv1IncomingPaymentFailed,
-    LNv1IncomingPaymentStarted, LNv1IncomingPaymentSucceeded, LNv1OutgoingPaymentFailed,
-    LNv1OutgoingPaymentStarted, LNv1OutgoingPaymentSucceeded, TelegramClient,
-    incoming::{
-        LNv2CompleteLightningPaymentSucceeded, LNv2IncomingPaymentFailed,
-        LNv2IncomingPaymentStarted, LNv2IncomingPaymentSucceeded,
-    },
-    outgoing::{
-        LNv2OutgoingPaymentFailed, LNv2OutgoingPaymentStarted, LNv2OutgoingPaymentSucceeded,
-    },
+    EtlRun, FederationConfigSnapshot, GatewayEpoch, GatewayLedgerEntry, TelegramClient,
+    alerts::{Alert, Alerter},
+    backdated::BackDatedEvents,
+    clock::Clock,
+    cursor::EtlCursor,
+    dead_letter::{self, ParseError},
+    events::GatewayEventKind,
+    gateway_auth::GatewayAuth,
+    ingest::IngestStats,
+    mempool::FeeRateHistory,
+    onchain::OnchainTransaction,
     parse_log_id,
+    progress::BackfillProgress,
+    raw_events::{self, ArchiveEncoding},
+    sink::{EventOrigin, EventSink, PostgresSink, TypedEvent},
+    staging,
+    watermark::IngestionWatermark,
+    webhook::WebhookClient,
 };
 
+/// Events committed per transaction unless set with `with_checkpoints`.
+const DEFAULT_CHUNK_SIZE: usize = 1000;
+
+/// Payment log events fetched per request unless set with `with_page_size`.
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// A page of new events in the payment log. `end_position` is exclusive.
+struct LogPage {
+    end_position: EventLogId,
+    new_entries: usize,
+    newest: i64,
+    oldest: i64,
+}
+
+/// Log id of the synthetic self-test event, far above any real one.
+const SELF_TEST_LOG_ID: i64 = i64::MAX;
+
+/// Operation id that marks the synthetic self-test event.
+const SELF_TEST_OPERATION_ID: &str = "etl-gateway-self-test";
+
+/// Channel notified with the federation id whenever new events of that
+/// federation were committed.
+const NEW_EVENTS_CHANNEL: &str = "gateway_etl_new_events";
+
+/// The connection of one transaction of a walk: a pooled one with its own
+/// transaction, or the one of the walk when it is a single transaction.
+enum WalkClient<'a> {
+    Pooled(Box<Object>),
+    Shared(&'a Client),
+}
+
+impl Deref for WalkClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match self {
+            WalkClient::Pooled(pg_client) => pg_client,
+            WalkClient::Shared(pg_client) => pg_client,
+        }
+    }
+}
+
 pub(crate) struct FederationEventProcessor {
     federation_id: FederationId,
     federation_name: String,
     max_log_id: i64,
-    pg_client: Client,
-    gw_client: GatewayApi,
-    telegram_client: TelegramClient,
+    pool: Pool,
+    /// The gateway and its address, `None` when replaying `raw_events`.
+    gateway: Option<(GatewayAuth, SafeUrl)>,
+    alerter: Alerter,
     outgoing_payment_started_count: u64,
     outgoing_payment_succeeded_count: u64,
     outgoing_payment_failed_count: u64,
@@ -37,26 +87,52 @@ pub(crate) struct FederationEventProcessor {
     incoming_payment_succeeded_count: u64,
     incoming_payment_failed_count: u64,
     complete_lightning_payment_succeeded_count: u64,
-    gw_epoch: i32,
+    contract_cancelled_count: u64,
+    refund_claimed_count: u64,
+    ledger_entry_count: u64,
+    fetched_count: u64,
+    skipped_count: u64,
+    gw_epoch: GatewayEpoch,
     amount: fedimint_core::Amount,
-    base_url: SafeUrl,
+    fee_rates: Option<FeeRateHistory>,
+    webhook: Option<WebhookClient>,
+    progress_notifications: Option<(TelegramClient, Duration)>,
+    etl_run: Option<EtlRun>,
+    chunk_size: usize,
+    page_size: usize,
+    notify_new_events: bool,
+    staging: bool,
+    archive_encoding: ArchiveEncoding,
+    back_dated_tolerance: Option<chrono::Duration>,
+    back_dated: Option<BackDatedEvents>,
+    dry_run: bool,
+    /// Rows the transactions of a dry run wrote before they were rolled back.
+    dry_run_written: BTreeMap<&'static str, i64>,
+    notify: bool,
+    /// Largest hole in `raw_events` that is re-fetched from the gateway.
+    gap_repair: Option<NonZeroUsize>,
+    repaired_count: u64,
+    single_transaction: bool,
+    clock: Clock,
 }
 
 impl fmt::Display for FederationEventProcessor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let balance = bitcoin::Amount::from_sat(self.amount.msats / 1000);
+        let balance = self.balance();
         write!(
             f,
             "Federation: {}\n\
             Balance: {}\n\
             Outgoing Payments - Succeeded: {}, Failed: {}\n\
-            Incoming Payments - Succeeded: {}, Failed: {}\n\n",
+            Incoming Payments - Succeeded: {}, Failed: {}\n\
+            Refunds Issued: {}\n\n",
             self.federation_name,
             balance,
             self.outgoing_payment_succeeded_count,
             self.outgoing_payment_failed_count,
             self.incoming_payment_succeeded_count,
             self.incoming_payment_failed_count,
+            self.refund_claimed_count,
         )
     }
 }
@@ -64,24 +140,45 @@ impl fmt::Display for FederationEventProcessor {
 impl FederationEventProcessor {
     pub async fn new(
         fed_info: FederationInfo,
-        db_conn: DbConnection,
-        gw_client: GatewayApi,
-        telegram_client: TelegramClient,
-        gw_epoch: i32,
+        pool: Pool,
+        gateway: GatewayAuth,
+        alerter: Alerter,
+        gw_epoch: GatewayEpoch,
         amount: fedimint_core::Amount,
         base_url: SafeUrl,
     ) -> anyhow::Result<FederationEventProcessor> {
-        let pg_client = db_conn.connect().await?;
-        let max_log_id = Self::get_max_log_id(&pg_client, fed_info.federation_id, gw_epoch).await?;
+        let pg_client = pool.get().await?;
+        let max_log_id = EtlCursor::get(&pg_client, fed_info.federation_id, gw_epoch).await?;
+        let federation_name = fed_info.federation_name.unwrap_or_else(|| {
+            warn!(federation_id = %fed_info.federation_id, "Federation has no name, using its id");
+            fed_info.federation_id.to_string()
+        });
         Ok(Self {
-            federation_id: fed_info.federation_id,
-            federation_name: fed_info
-                .federation_name
-                .expect("No federation name provided"),
             max_log_id,
-            pg_client,
-            gw_client,
-            telegram_client,
+            gateway: Some((gateway, base_url)),
+            alerter,
+            amount,
+            notify: true,
+            ..Self::replaying(fed_info.federation_id, federation_name, pool, gw_epoch)
+        })
+    }
+
+    /// A processor for events read back from `raw_events` with
+    /// [`Self::store_entry`]. It doesn't talk to the gateway, and sends no
+    /// alerts or webhooks.
+    pub fn replaying(
+        federation_id: FederationId,
+        federation_name: String,
+        pool: Pool,
+        gw_epoch: GatewayEpoch,
+    ) -> FederationEventProcessor {
+        Self {
+            federation_id,
+            federation_name,
+            max_log_id: 0,
+            pool,
+            gateway: None,
+            alerter: Alerter::disabled(),
             outgoing_payment_started_count: 0,
             outgoing_payment_succeeded_count: 0,
             outgoing_payment_failed_count: 0,
@@ -89,367 +186,956 @@ impl FederationEventProcessor {
             incoming_payment_succeeded_count: 0,
             incoming_payment_failed_count: 0,
             complete_lightning_payment_succeeded_count: 0,
+            contract_cancelled_count: 0,
+            refund_claimed_count: 0,
+            ledger_entry_count: 0,
+            fetched_count: 0,
+            skipped_count: 0,
             gw_epoch,
-            amount,
-            base_url,
-        })
+            amount: fedimint_core::Amount::ZERO,
+            fee_rates: None,
+            webhook: None,
+            progress_notifications: None,
+            etl_run: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            page_size: DEFAULT_PAGE_SIZE,
+            notify_new_events: false,
+            staging: false,
+            archive_encoding: ArchiveEncoding::default(),
+            back_dated_tolerance: None,
+            back_dated: None,
+            dry_run: false,
+            dry_run_written: BTreeMap::new(),
+            notify: false,
+            gap_repair: None,
+            repaired_count: 0,
+            single_transaction: false,
+            clock: Clock::System,
+        }
     }
 
-    async fn get_max_log_id(
-        pg_client: &Client,
-        federation_id: FederationId,
-        gw_epoch: i32,
-    ) -> anyhow::Result<i64> {
-        let query = "
-            SELECT MAX(log_id)
-            FROM (
-                SELECT log_id FROM lnv1_outgoing_payment_started WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_outgoing_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_outgoing_payment_failed WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_incoming_payment_started WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_incoming_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_incoming_payment_failed WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv1_complete_lightning_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_outgoing_payment_started WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_outgoing_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_outgoing_payment_failed WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_incoming_payment_started WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_incoming_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_incoming_payment_failed WHERE federation_id = $1 AND gateway_epoch = $2
-                UNION ALL
-                SELECT log_id FROM lnv2_complete_lightning_payment_succeeded WHERE federation_id = $1 AND gateway_epoch = $2
-            ) AS combined_log_ids
-        ";
-
-        let rows = pg_client
-            .query(query, &[&federation_id.to_string(), &gw_epoch])
-            .await?;
-        if let Some(row) = rows.get(0) {
-            let max_log_id: Option<i64> = row.get(0);
-            if let Some(max_log_id) = max_log_id {
-                return Ok(max_log_id);
+    pub fn federation_id(&self) -> FederationId {
+        self.federation_id
+    }
+
+    pub fn federation_name(&self) -> &str {
+        &self.federation_name
+    }
+
+    /// The gateway's balance in the federation when the run started.
+    pub fn balance(&self) -> bitcoin::Amount {
+        bitcoin::Amount::from_sat(self.amount.msats / 1000)
+    }
+
+    /// Compares the events fetched in this run with the rows that ended up in
+    /// the event tables, or for a dry run with the rows written before the
+    /// rollbacks.
+    pub async fn ingest_stats(&self) -> anyhow::Result<IngestStats> {
+        if self.dry_run {
+            return Ok(IngestStats {
+                federation_name: self.federation_name.clone(),
+                fetched: self.fetched_count,
+                skipped: self.skipped_count,
+                written: self.dry_run_written.clone(),
+            });
+        }
+        IngestStats::query(
+            &*self.pool.get().await?,
+            self.federation_id,
+            self.federation_name.clone(),
+            self.gw_epoch,
+            self.max_log_id,
+            self.fetched_count,
+            self.skipped_count,
+        )
+        .await
+    }
+
+    /// Suppresses alerts, for processors that mirror the events into
+    /// a secondary database.
+    pub fn without_notifications(mut self) -> Self {
+        self.notify = false;
+        self
+    }
+
+    /// Attaches the market fee rate to peg-outs using `fee_rates`.
+    pub fn with_fee_rates(mut self, fee_rates: Option<FeeRateHistory>) -> Self {
+        self.fee_rates = fee_rates;
+        self
+    }
+
+    /// Posts every LNv1 and LNv2 event to `webhook` after it was stored.
+    pub fn with_webhook(mut self, webhook: Option<WebhookClient>) -> Self {
+        self.webhook = webhook;
+        self
+    }
+
+    /// Sends the progress of processing the new events to Telegram at the
+    /// given interval, for first runs that backfill a long history.
+    pub fn with_progress_notifications(
+        mut self,
+        progress_notifications: Option<(TelegramClient, Duration)>,
+    ) -> Self {
+        self.progress_notifications = progress_notifications;
+        self
+    }
+
+    /// Commits the new events in chunks of `chunk_size` and records the last
+    /// log id of each committed chunk as checkpoint in `etl_run`.
+    pub fn with_checkpoints(mut self, etl_run: EtlRun, chunk_size: usize) -> Self {
+        self.etl_run = Some(etl_run);
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Fetches the payment log in pages of `page_size` events.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Sends a Postgres `NOTIFY` on [`NEW_EVENTS_CHANNEL`] with every
+    /// committed chunk, so downstream jobs don't have to poll the tables.
+    pub fn with_new_event_notifications(mut self, notify_new_events: bool) -> Self {
+        self.notify_new_events = notify_new_events;
+        self
+    }
+
+    /// Also lands every event untyped in the `staging` schema.
+    pub fn with_staging(mut self, staging: bool) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// How the events are stored in `raw_events`.
+    pub fn with_archive_encoding(mut self, archive_encoding: ArchiveEncoding) -> Self {
+        self.archive_encoding = archive_encoding;
+        self
+    }
+
+    /// Doesn't store events that are timestamped more than `tolerance` before
+    /// the newest stored event. Back-dated events are recorded either way.
+    pub fn with_back_dated_tolerance(mut self, tolerance: Option<chrono::Duration>) -> Self {
+        self.back_dated_tolerance = tolerance;
+        self
+    }
+
+    /// Processes and counts events without keeping anything: every chunk is
+    /// rolled back instead of committed, and config snapshots aren't stored.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Re-fetches holes of up to `max_events` log ids in `raw_events` before
+    /// fetching new events. Larger holes are only reported.
+    pub fn with_gap_repair(mut self, max_events: Option<NonZeroUsize>) -> Self {
+        self.gap_repair = max_events;
+        self
+    }
+
+    /// Stores the whole walk, including repaired holes, the cursor and the
+    /// watermark, in one transaction instead of one per chunk, so a failed
+    /// walk keeps nothing. The walk holds its connection while it waits for
+    /// the gateway, and a failed backfill has to start over.
+    pub fn with_single_transaction(mut self, single_transaction: bool) -> Self {
+        self.single_transaction = single_transaction;
+        self
+    }
+
+    /// Takes the time written with events and cursors from `clock` instead of
+    /// the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Events stored by filling holes in `raw_events`.
+    pub fn repaired_count(&self) -> u64 {
+        self.repaired_count
+    }
+
+    /// Events of this run timestamped before an already stored event.
+    pub fn back_dated(&self) -> Option<&BackDatedEvents> {
+        self.back_dated
+            .as_ref()
+            .filter(|back_dated| !back_dated.is_empty())
+    }
+
+    pub async fn record_config_snapshot(
+        &self,
+        snapshot: FederationConfigSnapshot,
+    ) -> anyhow::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let pg_client = self.pool.get().await?;
+        if let Some(previous) =
+            FederationConfigSnapshot::get_latest(&pg_client, &self.federation_id).await?
+        {
+            let changes = snapshot.diff(&previous);
+            if !changes.is_empty() {
+                warn!(federation_name = ?self.federation_name, ?changes, "Federation config changed");
+                FederationConfigSnapshot::insert_changes(
+                    &pg_client,
+                    &self.federation_id,
+                    self.federation_name.clone(),
+                    &changes,
+                    &self.clock,
+                )
+                .await?;
+
+                let mut message = format!("Federation config changed: {}\n", self.federation_name);
+                for (field, old_value, new_value) in &changes {
+                    message += format!("{field}: {old_value} -> {new_value}\n").as_str();
+                }
+                if self.notify {
+                    self.alerter
+                        .send(
+                            Alert::firing("FederationConfigChanged", message)
+                                .with_label("federation_id", self.federation_id)
+                                .with_label("federation_name", &self.federation_name),
+                        )
+                        .await;
+                }
             }
         }
 
-        Ok(0)
+        snapshot
+            .insert(
+                &pg_client,
+                &self.federation_id,
+                self.federation_name.clone(),
+                &self.clock,
+            )
+            .await
     }
 
+    /// Walks the new events of the payment log. A connection is taken from
+    /// the pool for each chunk after its page was fetched, never while
+    /// waiting for the gateway, so slow gateways don't starve the other
+    /// federations of connections. With [`Self::with_single_transaction`] the
+    /// walk takes one connection and commits once at the end instead.
     pub async fn process_events(&mut self) -> anyhow::Result<()> {
-        let payment_log = payment_log(&self.gw_client, &self.base_url, PaymentLogPayload {
-                end_position: None,
-                pagination_size: usize::MAX,
-                federation_id: self.federation_id,
-                event_kinds: vec![],
-            }).await?;
-
-        for entry in payment_log.0 {
-            tracing::info!(max_log_id = ?self.max_log_id, entry_log_id = ?entry.id(), federation_name = ?self.federation_name, "Processing event...");
-            if parse_log_id(&entry.id()) <= self.max_log_id {
-                break;
-            }
+        if !self.single_transaction {
+            return self.walk(None).await;
+        }
+        let pg_client = self.pool.get().await?;
+        pg_client.batch_execute("BEGIN").await?;
+        let mut res = self.walk(Some(&pg_client)).await;
+        if res.is_ok() && self.dry_run {
+            res = self.count_dry_run_writes(&pg_client).await;
+        }
+        pg_client
+            .batch_execute(if res.is_err() || self.dry_run {
+                "ROLLBACK"
+            } else {
+                "COMMIT"
+            })
+            .await?;
+        res
+    }
 
-            match &entry.module {
-                Some((module, _)) if module.as_str() == "ln" => {
-                    self.handle_lnv1(
-                        entry.id(),
-                        entry.kind.clone(),
-                        entry.ts_usecs,
-                        serde_json::from_slice(&entry.payload)?,
-                    )
-                    .await?;
+    /// The connection of the walk, or one from the pool without it.
+    async fn client<'a>(&self, shared: Option<&'a Client>) -> anyhow::Result<WalkClient<'a>> {
+        Ok(match shared {
+            Some(pg_client) => WalkClient::Shared(pg_client),
+            None => WalkClient::Pooled(Box::new(self.pool.get().await?)),
+        })
+    }
+
+    /// Opens a transaction on a pooled connection, or goes on in the
+    /// transaction of the walk on `shared`.
+    async fn begin<'a>(&self, shared: Option<&'a Client>) -> anyhow::Result<WalkClient<'a>> {
+        let pg_client = self.client(shared).await?;
+        if let WalkClient::Pooled(pg_client) = &pg_client {
+            pg_client.batch_execute("BEGIN").await?;
+        }
+        Ok(pg_client)
+    }
+
+    /// Ends a transaction opened by [`Self::begin`]. Errors in the shared
+    /// transaction are rolled back by [`Self::process_events`].
+    async fn end(
+        &mut self,
+        pg_client: WalkClient<'_>,
+        res: &anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if let WalkClient::Pooled(pg_client) = pg_client {
+            let counted = if res.is_ok() && self.dry_run {
+                self.count_dry_run_writes(&pg_client).await
+            } else {
+                Ok(())
+            };
+            pg_client
+                .batch_execute(if res.is_err() || self.dry_run {
+                    "ROLLBACK"
+                } else {
+                    "COMMIT"
+                })
+                .await?;
+            counted?;
+        }
+        Ok(())
+    }
+
+    /// Adds the rows of the open transaction to the run's written rows before
+    /// a dry run rolls it back.
+    async fn count_dry_run_writes(&mut self, pg_client: &Client) -> anyhow::Result<()> {
+        let written = IngestStats::written(
+            pg_client,
+            self.federation_id,
+            self.gw_epoch,
+            self.max_log_id,
+        )
+        .await?;
+        for (table, count) in written {
+            *self.dry_run_written.entry(table).or_default() += count;
+        }
+        Ok(())
+    }
+
+    /// Repairs the holes and stores the new events, in transactions of
+    /// their own or all in the one open on `shared`.
+    async fn walk(&mut self, shared: Option<&Client>) -> anyhow::Result<()> {
+        // Before the back-dated check is set up, which the old events of a
+        // hole would all fail
+        self.repair_gaps(shared).await?;
+        self.back_dated = Some(
+            BackDatedEvents::query(
+                &*self.client(shared).await?,
+                self.federation_id,
+                self.federation_name.clone(),
+                self.gw_epoch,
+                self.back_dated_tolerance,
+                self.clock.clone(),
+            )
+            .await?,
+        );
+
+        let pages = self.new_pages().await?;
+        let mut progress = BackfillProgress::new(
+            self.federation_name.clone(),
+            pages.iter().map(|page| page.new_entries as u64).sum(),
+            (
+                pages.first().map_or(self.max_log_id, |page| page.newest),
+                pages.last().map_or(self.max_log_id, |page| page.oldest),
+            ),
+            self.progress_notifications.clone().filter(|_| self.notify),
+        );
+
+        // The log is ordered from the newest event to the oldest one. New
+        // events are stored oldest first, so an interrupted run leaves no gap
+        // below the newest stored log id.
+        for page in pages.iter().rev() {
+            let mut new_entries = Vec::new();
+            for entry in self.fetch_page(Some(page.end_position)).await? {
+                if (page.oldest..=page.newest).contains(&parse_log_id(&entry.id())?) {
+                    new_entries.push(entry);
                 }
-                Some((module, _)) if module.as_str() == "lnv2" => {
-                    self.handle_lnv2(
-                        entry.id(),
-                        entry.kind.clone(),
-                        entry.ts_usecs,
-                        serde_json::from_slice(&entry.payload)?,
-                    )
-                    .await?;
+            }
+            new_entries.reverse();
+
+            for chunk in new_entries.chunks(self.chunk_size) {
+                let pg_client = self.begin(shared).await?;
+                let res = self.process_chunk(&pg_client, chunk, &mut progress).await;
+                self.end(pg_client, &res).await?;
+                res?;
+            }
+        }
+
+        // Once per walk rather than per chunk, it scans the archive for holes
+        if !self.dry_run {
+            IngestionWatermark::advance(
+                &*self.client(shared).await?,
+                self.federation_id,
+                self.gw_epoch,
+                &self.clock,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the events missing from `raw_events` again and stores them in
+    /// one transaction per hole, together with the hole as checked so it
+    /// isn't fetched again. They are below the cursor, so they neither move
+    /// it nor count towards the run's ingest stats.
+    async fn repair_gaps(&mut self, shared: Option<&Client>) -> anyhow::Result<()> {
+        let Some(max_events) = self.gap_repair else {
+            return Ok(());
+        };
+        let gaps = raw_events::gaps(
+            &*self.client(shared).await?,
+            self.federation_id,
+            self.gw_epoch,
+        )
+        .await?;
+        for gap in gaps {
+            let (start, end) = (*gap.start(), *gap.end());
+            let size = end - start + 1;
+            if size > max_events.get() as i64 {
+                warn!(federation_name = ?self.federation_name, start, end, "Events missing from raw_events, too many to re-fetch automatically");
+                continue;
+            }
+
+            // Newest first, like the payment log
+            let mut missing = Vec::new();
+            let mut end_position = EventLogId::LOG_START.saturating_add(end as u64 + 1);
+            loop {
+                let page = self.fetch_page(Some(end_position)).await?;
+                let Some(oldest) = page.last().map(|entry| entry.id()) else {
+                    break;
+                };
+                for entry in page {
+                    if gap.contains(&parse_log_id(&entry.id())?) {
+                        missing.push(entry);
+                    }
                 }
-                Some((module, _)) => {
-                    warn!(module = %module, "Unsupported module");
-                    //self.telegram_client
-                    //    .send_telegram_message(format!("Found unsupported module: {module}"))
-                    //    .await;
+                if parse_log_id(&oldest)? <= start {
+                    break;
                 }
-                None => {
-                    warn!("No module provided");
-                    self.telegram_client
-                        .send_telegram_message("Found event without a module".to_string())
-                        .await;
+                end_position = oldest;
+            }
+            if (missing.len() as i64) < size {
+                info!(federation_name = ?self.federation_name, start, end, found = missing.len(), "Not all events missing from raw_events are in the payment log, which leaves out other kinds");
+            }
+
+            let skipped_count = self.skipped_count;
+            let pg_client = self.begin(shared).await?;
+            let res = async {
+                for entry in missing.iter().rev() {
+                    self.process_entry(&pg_client, entry).await?;
                 }
+                raw_events::record_checked_gap(
+                    &pg_client,
+                    self.federation_id,
+                    self.gw_epoch,
+                    &gap,
+                    self.clock.now().naive_utc(),
+                )
+                .await
             }
+            .await;
+            self.end(pg_client, &res).await?;
+            res?;
+            self.skipped_count = skipped_count;
+            self.repaired_count += missing.len() as u64;
+            info!(federation_name = ?self.federation_name, start, end, repaired = missing.len(), "Re-fetched events missing from raw_events");
         }
-
         Ok(())
     }
 
-    async fn handle_lnv2(
+    /// Fetches up to `page_size` events before `end_position`, or the newest
+    /// ones without it, newest first.
+    async fn fetch_page(
+        &self,
+        end_position: Option<EventLogId>,
+    ) -> anyhow::Result<Vec<PersistedLogEntry>> {
+        let (gateway, base_url) = self
+            .gateway
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Can't fetch the payment log while replaying"))?;
+        Ok(gateway
+            .request(|client| async move {
+                payment_log(
+                    &client,
+                    base_url,
+                    PaymentLogPayload {
+                        end_position,
+                        pagination_size: self.page_size,
+                        federation_id: self.federation_id,
+                        event_kinds: vec![],
+                    },
+                )
+                .await
+            })
+            .await?
+            .0)
+    }
+
+    /// Walks the payment log back from the newest event until it reaches
+    /// stored events, keeping only the bounds of each page. The pages are
+    /// fetched again one at a time while processing, so memory stays bounded
+    /// by the page size even on a first run against a busy gateway.
+    async fn new_pages(&self) -> anyhow::Result<Vec<LogPage>> {
+        let mut pages = Vec::new();
+        let mut end_position = None;
+        loop {
+            let page = self.fetch_page(end_position).await?;
+            let end = end_position.as_ref().map(parse_log_id).transpose()?;
+            let log_ids = page
+                .iter()
+                .map(|entry| parse_log_id(&entry.id()))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|log_id| end.is_none_or(|end| *log_id < end))
+                .collect::<Vec<_>>();
+            let new_log_ids = log_ids
+                .iter()
+                .take_while(|log_id| **log_id > self.max_log_id)
+                .collect::<Vec<_>>();
+            let (Some(&&newest), Some(&&oldest)) = (new_log_ids.first(), new_log_ids.last()) else {
+                break;
+            };
+
+            pages.push(LogPage {
+                // Pinned to the newest event, so events logged while the run
+                // is going don't shift the first page
+                end_position: EventLogId::LOG_START.saturating_add(newest as u64 + 1),
+                new_entries: new_log_ids.len(),
+                newest,
+                oldest,
+            });
+            if new_log_ids.len() < log_ids.len() {
+                break;
+            }
+            end_position = Some(EventLogId::LOG_START.saturating_add(oldest as u64));
+        }
+        Ok(pages)
+    }
+
+    /// Pushes one synthetic mint event through `process_entry` and checks that
+    /// it arrived in `gateway_ledger`, which exercises parsing, the optional
+    /// staging and the database permissions. Runs in a transaction that is
+    /// always rolled back, so nothing of the event is kept.
+    pub async fn self_test(&mut self) -> anyhow::Result<()> {
+        let entry: PersistedLogEntry = serde_json::from_value(json!({
+            "id": SELF_TEST_LOG_ID,
+            "kind": "payment-receive",
+            "module": ["mint", 0],
+            "ts_usecs": self.clock.now().timestamp_micros(),
+            "payload": {
+                "operation_id": SELF_TEST_OPERATION_ID,
+                "amount": 0,
+            },
+        }))?;
+
+        let pg_client = self.pool.get().await?;
+        pg_client.batch_execute("BEGIN").await?;
+        let res = async {
+            self.process_entry(&pg_client, &entry).await?;
+            let row = pg_client
+                .query_opt(
+                    "SELECT 1 FROM gateway_ledger WHERE log_id = $1 AND federation_id = $2 AND gateway_epoch = $3 AND operation_id = $4",
+                    &[
+                        &SELF_TEST_LOG_ID,
+                        &self.federation_id.to_string(),
+                        &i32::from(self.gw_epoch),
+                        &SELF_TEST_OPERATION_ID,
+                    ],
+                )
+                .await?;
+            anyhow::ensure!(
+                row.is_some(),
+                "Synthetic event did not arrive in gateway_ledger"
+            );
+            Ok(())
+        }
+        .await;
+        pg_client.batch_execute("ROLLBACK").await?;
+        res
+    }
+
+    /// Stores `chunk` and advances the cursor to its last log id, within the
+    /// transaction of the caller, so an interrupted run resumes after the last
+    /// committed chunk.
+    async fn process_chunk(
         &mut self,
-        log_id: EventLogId,
-        kind: EventKind,
-        timestamp: u64,
-        value: Value,
+        pg_client: &Client,
+        chunk: &[PersistedLogEntry],
+        progress: &mut BackfillProgress,
     ) -> anyhow::Result<()> {
-        let kind = Self::parse_event_kind(format!("{kind:?}"));
-        match kind.as_str() {
-            "outgoing-payment-started" => {
-                let outgoing_payment_started_event: LNv2OutgoingPaymentStarted =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_started_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_started_count += 1;
-            }
-            "outgoing-payment-succeeded" => {
-                let outgoing_payment_succeeded_event: LNv2OutgoingPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_succeeded_count += 1;
-            }
-            "outgoing-payment-failed" => {
-                let outgoing_payment_failed_event: LNv2OutgoingPaymentFailed =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_failed_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_failed_count += 1;
-            }
-            "incoming-payment-started" => {
-                let incoming_payment_started_event: LNv2IncomingPaymentStarted =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_started_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.incoming_payment_started_count += 1;
-            }
-            "incoming-payment-succeeded" => {
-                let incoming_payment_succeeded_event: LNv2IncomingPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.incoming_payment_succeeded_count += 1;
-            }
-            "incoming-payment-failed" => {
-                let incoming_payment_failed_event: LNv2IncomingPaymentFailed =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_failed_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
+        for entry in chunk {
+            tracing::info!(entry_log_id = ?entry.id(), federation_name = ?self.federation_name, "Processing event...");
+            self.fetched_count += 1;
+            self.process_entry(pg_client, entry).await?;
+            progress.record(parse_log_id(&entry.id())?).await;
+        }
+
+        if let Some(last) = chunk.last() {
+            let log_id = parse_log_id(&last.id())?;
+            EtlCursor::advance(
+                pg_client,
+                self.federation_id,
+                self.gw_epoch,
+                log_id,
+                &self.clock,
+            )
+            .await?;
+            if let Some(etl_run) = &self.etl_run {
+                etl_run
+                    .record_checkpoint(pg_client, self.federation_id, log_id)
                     .await?;
-                self.incoming_payment_failed_count += 1;
-            }
-            "complete-lightning-payment-succeeded" => {
-                let complete_lightning_payment_succeeded_event: LNv2CompleteLightningPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                complete_lightning_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
+            }
+        }
+
+        // Delivered to listeners when the transaction commits
+        if self.notify_new_events {
+            pg_client
+                .execute(
+                    "SELECT pg_notify($1, $2)",
+                    &[&NEW_EVENTS_CHANNEL, &self.federation_id.to_string()],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn process_entry(
+        &mut self,
+        pg_client: &Client,
+        entry: &PersistedLogEntry,
+    ) -> anyhow::Result<()> {
+        let ts = dead_letter::timestamp(entry.ts_usecs as i64);
+        raw_events::archive(
+            pg_client,
+            ts.as_ref().ok().copied(),
+            self.federation_id,
+            &self.federation_name,
+            self.gw_epoch,
+            entry,
+            self.archive_encoding,
+        )
+        .await?;
+        let ts = match ts {
+            Ok(ts) => ts,
+            Err(err) => return self.dead_letter(pg_client, entry, err).await,
+        };
+        if self.staging {
+            staging::land(
+                pg_client,
+                ts,
+                self.federation_id,
+                &self.federation_name,
+                self.gw_epoch,
+                self.etl_run.as_ref().map(EtlRun::id),
+                entry,
+            )
+            .await?;
+        }
+
+        if let Some(back_dated) = &mut self.back_dated
+            && !back_dated
+                .check(pg_client, parse_log_id(&entry.id())?, ts)
+                .await?
+        {
+            self.skipped_count += 1;
+            return Ok(());
+        }
+
+        self.store_entry(pg_client, entry).await
+    }
+
+    /// Stores `entry` in the table of its kind, or in `dead_letter_events`
+    /// if it doesn't parse. Replaying `raw_events` calls this directly,
+    /// skipping the archive, staging and the back-dated check.
+    pub async fn store_entry(
+        &mut self,
+        pg_client: &Client,
+        entry: &PersistedLogEntry,
+    ) -> anyhow::Result<()> {
+        let mut sink = PostgresSink::new(pg_client);
+        if let Err(err) = self.handle_entry(&mut sink, pg_client, entry).await {
+            let err = err.downcast::<ParseError>()?;
+            self.dead_letter(pg_client, entry, err).await?;
+        }
+        sink.flush().await
+    }
+
+    /// Stores an event that couldn't be parsed and alerts about it, so the
+    /// run can go on with the next event.
+    async fn dead_letter(
+        &self,
+        pg_client: &Client,
+        entry: &PersistedLogEntry,
+        err: ParseError,
+    ) -> anyhow::Result<()> {
+        let kind = entry.kind.to_string();
+        let log_id = parse_log_id(&entry.id())?;
+        warn!(log_id, %kind, federation_name = ?self.federation_name, %err, "Could not parse event, moving it to dead_letter_events");
+        dead_letter::insert(
+            pg_client,
+            entry,
+            &self.federation_id,
+            self.federation_name.clone(),
+            self.gw_epoch,
+            &kind,
+            &err,
+        )
+        .await?;
+        if self.notify {
+            self.alerter
+                .send(
+                    Alert::firing(
+                        "EventParseFailed",
+                        format!(
+                            "Could not parse {kind} event {log_id} of {}, moved it to dead_letter_events: {err}",
+                            self.federation_name
+                        ),
                     )
-                    .await?;
-                self.complete_lightning_payment_succeeded_count += 1;
+                    .with_label("federation_id", self.federation_id)
+                    .with_label("federation_name", &self.federation_name),
+                )
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Parses `entry` and writes it to `sink`. Fails with a [`ParseError`]
+    /// if the payload doesn't match the kind.
+    async fn handle_entry(
+        &mut self,
+        sink: &mut impl EventSink,
+        pg_client: &Client,
+        entry: &PersistedLogEntry,
+    ) -> anyhow::Result<()> {
+        let origin = EventOrigin {
+            log_id: entry.id(),
+            timestamp: entry.ts_usecs,
+            federation_id: self.federation_id,
+            federation_name: self.federation_name.clone(),
+            gateway_epoch: self.gw_epoch.into(),
+        };
+        match &entry.module {
+            Some((module, _)) if module.as_str() == "ln" || module.as_str() == "lnv2" => {
+                let value: Value =
+                    serde_json::from_slice(&entry.payload).map_err(ParseError::new)?;
+                let event = if module.as_str() == "ln" {
+                    Self::parse_lnv1(&entry.kind, value.clone())?
+                } else {
+                    Self::parse_lnv2(&entry.kind, value.clone())?
+                };
+                match event {
+                    Some(event) => {
+                        sink.write_event(&origin, &event).await?;
+                        self.count(&event);
+                    }
+                    None => {
+                        warn!(event = %entry.kind, "Unrecognized event");
+                        self.skipped_count += 1;
+                    }
+                }
+                self.deliver_webhook(
+                    pg_client,
+                    entry.id(),
+                    module.as_str(),
+                    entry.kind.clone(),
+                    entry.ts_usecs,
+                    value,
+                )
+                .await?;
+            }
+            Some((module, _)) if module.as_str() == "mint" || module.as_str() == "wallet" => {
+                self.handle_ledger(
+                    sink,
+                    &origin,
+                    module.as_str(),
+                    entry.kind.clone(),
+                    serde_json::from_slice(&entry.payload).map_err(ParseError::new)?,
+                )
+                .await?;
             }
-            event => {
-                warn!(?event, "Unrecognized event");
+            Some((module, _)) => {
+                warn!(module = %module, "Unsupported module");
+                self.skipped_count += 1;
+                //self.telegram_client
+                //    .send_telegram_message(format!("Found unsupported module: {module}"))
+                //    .await;
+            }
+            None => {
+                warn!("No module provided");
+                self.skipped_count += 1;
+                if self.notify {
+                    self.alerter
+                        .send(
+                            Alert::firing(
+                                "EventWithoutModule",
+                                "Found event without a module".to_string(),
+                            )
+                            .with_label("federation_id", self.federation_id),
+                        )
+                        .await;
+                }
             }
         }
 
         Ok(())
     }
 
-    async fn handle_lnv1(
-        &mut self,
+    async fn deliver_webhook(
+        &self,
+        pg_client: &Client,
         log_id: EventLogId,
+        module: &str,
         kind: EventKind,
         timestamp: u64,
+        payload: Value,
+    ) -> anyhow::Result<()> {
+        let Some(webhook) = &self.webhook else {
+            return Ok(());
+        };
+        let log_id = parse_log_id(&log_id)?;
+        let ts = dead_letter::timestamp(timestamp as i64)?.and_utc();
+        let event = json!({
+            "gateway_epoch": i32::from(self.gw_epoch),
+            "federation_id": self.federation_id.to_string(),
+            "federation_name": self.federation_name,
+            "log_id": log_id,
+            "ts": ts.to_rfc3339(),
+            "module": module,
+            "kind": kind.to_string(),
+            "payload": payload,
+        });
+        webhook
+            .deliver(
+                pg_client,
+                self.gw_epoch.into(),
+                &self.federation_id.to_string(),
+                log_id,
+                event,
+            )
+            .await
+    }
+
+    async fn handle_ledger(
+        &mut self,
+        sink: &mut impl EventSink,
+        origin: &EventOrigin,
+        module: &str,
+        kind: EventKind,
         value: Value,
     ) -> anyhow::Result<()> {
-        let kind = Self::parse_event_kind(format!("{kind:?}"));
-        match kind.as_str() {
-            "outgoing-payment-started" => {
-                let outgoing_payment_started_event: LNv1OutgoingPaymentStarted =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_started_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_started_count += 1;
-            }
-            "outgoing-payment-succeeded" => {
-                let outgoing_payment_succeeded_event: LNv1OutgoingPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_succeeded_count += 1;
-            }
-            "outgoing-payment-failed" => {
-                let outgoing_payment_failed_event: LNv1OutgoingPaymentFailed =
-                    serde_json::from_value(value).expect("Could not parse event");
-                outgoing_payment_failed_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.outgoing_payment_failed_count += 1;
-            }
-            "incoming-payment-started" => {
-                let incoming_payment_started_event: LNv1IncomingPaymentStarted =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_started_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.incoming_payment_started_count += 1;
-            }
-            "incoming-payment-succeeded" => {
-                let incoming_payment_succeeded_event: LNv1IncomingPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.incoming_payment_succeeded_count += 1;
-            }
-            "incoming-payment-failed" => {
-                let incoming_payment_failed_event: LNv1IncomingPaymentFailed =
-                    serde_json::from_value(value).expect("Could not parse event");
-                incoming_payment_failed_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.incoming_payment_failed_count += 1;
-            }
-            "complete-lightning-payment-succeeded" => {
-                let complete_lightning_payment_succeeded_event: LNv1CompleteLightningPaymentSucceeded =
-                    serde_json::from_value(value).expect("Could not parse event");
-                complete_lightning_payment_succeeded_event
-                    .insert(
-                        &self.pg_client,
-                        &log_id,
-                        timestamp,
-                        &self.federation_id,
-                        self.federation_name.clone(),
-                        self.gw_epoch,
-                    )
-                    .await?;
-                self.complete_lightning_payment_succeeded_count += 1;
-            }
-            event => {
-                warn!(?event, "Unrecognized event");
+        let kind = kind.to_string();
+        let mut events = Vec::new();
+        if let Some(mut ledger_entry) =
+            GatewayLedgerEntry::parse(module, &kind, &value).map_err(ParseError::new)?
+        {
+            if ledger_entry.is_peg_out()
+                && let Some(fee_rates) = &self.fee_rates
+            {
+                ledger_entry.set_market_fee_rate(fee_rates.at(origin.timestamp));
             }
+            events.push(TypedEvent::LedgerEntry(ledger_entry));
+        } else {
+            self.skipped_count += 1;
         }
 
+        if module == "wallet"
+            && let Some(transaction) = OnchainTransaction::parse(&kind, &value)
+        {
+            events.push(TypedEvent::OnchainTransaction(transaction));
+        }
+
+        sink.write_batch(origin, &events).await?;
+        for event in &events {
+            self.count(event);
+        }
         Ok(())
     }
 
-    // TODO: Remove this once EventKind can be parsed correctly
-    fn parse_event_kind(input: String) -> String {
-        if let Some(start) = input.find('(') {
-            if let Some(end) = input.rfind(')') {
-                let extracted = &input[start + 2..end - 1]; // Skip `("` and `")`
-                return extracted.to_string();
+    /// The LNv2 event of `kind`, `None` for kinds we don't store.
+    fn parse_lnv2(kind: &EventKind, value: Value) -> anyhow::Result<Option<TypedEvent>> {
+        Ok(Some(match GatewayEventKind::from(kind) {
+            GatewayEventKind::OutgoingPaymentStarted => {
+                TypedEvent::LNv2OutgoingPaymentStarted(dead_letter::parse(value)?)
             }
-        }
+            GatewayEventKind::OutgoingPaymentSucceeded => {
+                TypedEvent::LNv2OutgoingPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::OutgoingPaymentFailed => {
+                TypedEvent::LNv2OutgoingPaymentFailed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentStarted => {
+                TypedEvent::LNv2IncomingPaymentStarted(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentSucceeded => {
+                TypedEvent::LNv2IncomingPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentFailed => {
+                TypedEvent::LNv2IncomingPaymentFailed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::CompleteLightningPaymentSucceeded => {
+                TypedEvent::LNv2CompleteLightningPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::ContractCancelled => {
+                TypedEvent::LNv2ContractCancelled(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::RefundClaimed => {
+                TypedEvent::LNv2RefundClaimed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::Unknown(_) => return Ok(None),
+        }))
+    }
 
-        panic!("Malformatted String");
+    /// The LNv1 event of `kind`, `None` for kinds we don't store.
+    fn parse_lnv1(kind: &EventKind, value: Value) -> anyhow::Result<Option<TypedEvent>> {
+        Ok(Some(match GatewayEventKind::from(kind) {
+            GatewayEventKind::OutgoingPaymentStarted => {
+                TypedEvent::LNv1OutgoingPaymentStarted(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::OutgoingPaymentSucceeded => {
+                TypedEvent::LNv1OutgoingPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::OutgoingPaymentFailed => {
+                TypedEvent::LNv1OutgoingPaymentFailed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentStarted => {
+                TypedEvent::LNv1IncomingPaymentStarted(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentSucceeded => {
+                TypedEvent::LNv1IncomingPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::IncomingPaymentFailed => {
+                TypedEvent::LNv1IncomingPaymentFailed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::CompleteLightningPaymentSucceeded => {
+                TypedEvent::LNv1CompleteLightningPaymentSucceeded(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::ContractCancelled => {
+                TypedEvent::LNv1ContractCancelled(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::RefundClaimed => {
+                TypedEvent::LNv1RefundClaimed(dead_letter::parse(value)?)
+            }
+            GatewayEventKind::Unknown(_) => return Ok(None),
+        }))
+    }
+
+    /// Counts a written event towards the summary of this run.
+    fn count(&mut self, event: &TypedEvent) {
+        let counter = match event {
+            TypedEvent::LNv1OutgoingPaymentStarted(_)
+            | TypedEvent::LNv2OutgoingPaymentStarted(_) => &mut self.outgoing_payment_started_count,
+            TypedEvent::LNv1OutgoingPaymentSucceeded(_)
+            | TypedEvent::LNv2OutgoingPaymentSucceeded(_) => {
+                &mut self.outgoing_payment_succeeded_count
+            }
+            TypedEvent::LNv1OutgoingPaymentFailed(_) | TypedEvent::LNv2OutgoingPaymentFailed(_) => {
+                &mut self.outgoing_payment_failed_count
+            }
+            TypedEvent::LNv1IncomingPaymentStarted(_)
+            | TypedEvent::LNv2IncomingPaymentStarted(_) => &mut self.incoming_payment_started_count,
+            TypedEvent::LNv1IncomingPaymentSucceeded(_)
+            | TypedEvent::LNv2IncomingPaymentSucceeded(_) => {
+                &mut self.incoming_payment_succeeded_count
+            }
+            TypedEvent::LNv1IncomingPaymentFailed(_) | TypedEvent::LNv2IncomingPaymentFailed(_) => {
+                &mut self.incoming_payment_failed_count
+            }
+            TypedEvent::LNv1CompleteLightningPaymentSucceeded(_)
+            | TypedEvent::LNv2CompleteLightningPaymentSucceeded(_) => {
+                &mut self.complete_lightning_payment_succeeded_count
+            }
+            TypedEvent::LNv1ContractCancelled(_) | TypedEvent::LNv2ContractCancelled(_) => {
+                &mut self.contract_cancelled_count
+            }
+            TypedEvent::LNv1RefundClaimed(_) | TypedEvent::LNv2RefundClaimed(_) => {
+                &mut self.refund_claimed_count
+            }
+            TypedEvent::LedgerEntry(_) => &mut self.ledger_entry_count,
+            TypedEvent::OnchainTransaction(_) => return,
+        };
+        *counter += 1;
     }
 }
@@ -0,0 +1,113 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use fedimint_connectors::{ConnectorRegistry, ServerResult, error::ServerError};
+use fedimint_core::anyhow;
+use fedimint_ln_common::client::GatewayApi;
+use tracing::{info, warn};
+
+/// Authenticates against the gateway while its password is being rotated.
+/// When the gateway answers `401`, the password file is read again and the
+/// other configured passwords are tried, and the first one accepted is used
+/// from then on. That way the ETL doesn't have to be restarted in step with
+/// the gateway.
+#[derive(Clone)]
+pub(crate) struct GatewayAuth {
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    previous_password: Option<String>,
+    connector_registry: ConnectorRegistry,
+    current: Arc<Mutex<(String, GatewayApi)>>,
+}
+
+impl GatewayAuth {
+    pub fn new(
+        password: Option<String>,
+        password_file: Option<PathBuf>,
+        previous_password: Option<String>,
+        connector_registry: ConnectorRegistry,
+    ) -> anyhow::Result<Self> {
+        let initial = match &password_file {
+            Some(path) => read_password_file(path)?,
+            None => password
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("No gateway password configured"))?,
+        };
+        let client = GatewayApi::new(Some(initial.clone()), connector_registry.clone());
+        Ok(Self {
+            password,
+            password_file,
+            previous_password,
+            connector_registry,
+            current: Arc::new(Mutex::new((initial, client))),
+        })
+    }
+
+    /// Passwords to try after the current one was rejected, most recent
+    /// first.
+    fn candidates(&self) -> anyhow::Result<Vec<String>> {
+        let mut candidates = Vec::new();
+        if let Some(path) = &self.password_file {
+            candidates.push(read_password_file(path)?);
+        }
+        candidates.extend(self.password.clone());
+        candidates.extend(self.previous_password.clone());
+        Ok(candidates)
+    }
+
+    /// Runs `request` with the current password, and again with every other
+    /// candidate if the gateway rejects it.
+    pub async fn request<T, F, Fut>(&self, request: F) -> ServerResult<T>
+    where
+        F: Fn(GatewayApi) -> Fut,
+        Fut: Future<Output = ServerResult<T>>,
+    {
+        let (current, client) = self
+            .current
+            .lock()
+            .expect("Gateway auth lock poisoned")
+            .clone();
+        let err = match request(client).await {
+            Err(err) if is_unauthorized(&err) => err,
+            res => return res,
+        };
+
+        let mut tried = vec![current];
+        for password in self
+            .candidates()
+            .map_err(ServerError::InternalClientError)?
+        {
+            if tried.contains(&password) {
+                continue;
+            }
+            let client = GatewayApi::new(Some(password.clone()), self.connector_registry.clone());
+            match request(client.clone()).await {
+                Err(err) if is_unauthorized(&err) => tried.push(password),
+                res => {
+                    info!("Gateway accepted another configured password, switching to it");
+                    *self.current.lock().expect("Gateway auth lock poisoned") = (password, client);
+                    return res;
+                }
+            }
+        }
+        warn!(
+            passwords = tried.len(),
+            "Gateway rejected every configured password"
+        );
+        Err(err)
+    }
+}
+
+fn read_password_file(path: &Path) -> anyhow::Result<String> {
+    let password = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Error reading {}: {err}", path.display()))?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// The HTTP connector reports any status other than `200` as an invalid
+/// request, with the status only in the message.
+fn is_unauthorized(err: &ServerError) -> bool {
+    matches!(err, ServerError::InvalidRequest(err) if err.to_string().contains("401"))
+}
@@ -0,0 +1,83 @@
+use std::{fmt, str::FromStr};
+
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+use crate::clock::Clock;
+
+/// Upper bound for a gateway epoch. Epochs are bumped by hand every time the
+/// gateway is restored, so anything above this is almost certainly a typo.
+const MAX_GATEWAY_EPOCH: i32 = 9999;
+
+/// The epoch of the gateway's event log. The event log restarts at log id 0
+/// when a gateway is restored, so every row is keyed by `(log_id,
+/// gateway_epoch)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct GatewayEpoch(i32);
+
+impl FromStr for GatewayEpoch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let epoch = s.parse::<i32>()?;
+        anyhow::ensure!(
+            (0..=MAX_GATEWAY_EPOCH).contains(&epoch),
+            "Gateway epoch must be between 0 and {MAX_GATEWAY_EPOCH}, got {epoch}"
+        );
+        Ok(Self(epoch))
+    }
+}
+
+impl fmt::Display for GatewayEpoch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<GatewayEpoch> for i32 {
+    fn from(epoch: GatewayEpoch) -> Self {
+        epoch.0
+    }
+}
+
+impl GatewayEpoch {
+    /// Records this epoch in `gateway_epochs` the first time it is seen.
+    /// Refuses to run with an epoch lower than the latest recorded one unless
+    /// `allow_rollback` is set, since that would mix rows from two different
+    /// event logs.
+    pub async fn register(
+        &self,
+        pg_client: &Client,
+        reason: Option<String>,
+        allow_rollback: bool,
+        clock: &Clock,
+    ) -> anyhow::Result<()> {
+        let row = pg_client
+            .query_one("SELECT MAX(gateway_epoch) FROM gateway_epochs", &[])
+            .await?;
+        let max_epoch: Option<i32> = row.get(0);
+        if let Some(max_epoch) = max_epoch
+            && self.0 < max_epoch
+        {
+            anyhow::ensure!(
+                allow_rollback,
+                "Gateway epoch {self} is lower than the latest recorded epoch {max_epoch}. Pass --allow-epoch-rollback to run anyway."
+            );
+            warn!(epoch = %self, %max_epoch, "Running with a rolled back gateway epoch");
+        }
+
+        let created_at = clock.now().naive_utc();
+        let inserted = pg_client
+            .execute(
+                "INSERT INTO gateway_epochs (gateway_epoch, created_at, reason) VALUES ($1, $2, $3) ON CONFLICT (gateway_epoch) DO NOTHING",
+                &[&self.0, &created_at, &reason],
+            )
+            .await?;
+        if inserted > 0 {
+            info!(epoch = %self, ?reason, "Recorded new gateway epoch");
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,10 @@
-use chrono::DateTime;
 use fedimint_core::{anyhow, config::FederationId};
 use fedimint_eventlog::EventLogId;
-use serde::Deserialize;
+use serde::{Deserialize, de};
 use serde_json::Value;
 use tokio_postgres::Client;
 
-use crate::{outgoing::LNv2PaymentImage, parse_log_id};
+use crate::{dead_letter, outgoing::LNv2PaymentImage, parse_log_id};
 
 #[derive(Debug, Clone)]
 pub(crate) struct LNv2IncomingPaymentStarted {
@@ -22,13 +21,13 @@ impl<'de> Deserialize<'de> for LNv2IncomingPaymentStarted {
         let value = Value::deserialize(deserializer)?;
         let incoming_contract_commitment: LNv2IncomingContractCommitment =
             serde_json::from_value(value["incoming_contract_commitment"].clone())
-                .expect("Could not parse LNv2PaymentImage");
+                .map_err(|e| de::Error::custom(e.to_string()))?;
         let invoice_amount = value["invoice_amount"]
             .as_i64()
-            .expect("amount should be present");
+            .ok_or_else(|| de::Error::missing_field("invoice_amount"))?;
         let operation_start = value["operation_start"]
             .as_i64()
-            .expect("amount should be present");
+            .ok_or_else(|| de::Error::missing_field("operation_start"))?;
 
         Ok(Self {
             incoming_contract_commitment,
@@ -48,14 +47,10 @@ impl LNv2IncomingPaymentStarted {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        let operation_start = DateTime::from_timestamp_micros(self.operation_start as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_incoming_payment_started (log_id, ts, federation_id, federation_name, gateway_epoch, amount, claim_pk, ephemeral_pk, expiration, payment_image, refund_pk, invoice_amount, operation_start) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        let operation_start = dead_letter::timestamp(self.operation_start)?;
+        pg_client.execute("INSERT INTO lnv2_incoming_payment_started (log_id, ts, federation_id, federation_name, gateway_epoch, amount, claim_pk, ephemeral_pk, expiration, payment_image, refund_pk, invoice_amount, operation_start) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) ON CONFLICT DO NOTHING",
         &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.incoming_contract_commitment.amount, &self.incoming_contract_commitment.claim_pk, &self.incoming_contract_commitment.ephemeral_pk, &self.incoming_contract_commitment.expiration, &self.incoming_contract_commitment.payment_image.hash, &self.incoming_contract_commitment.refund_pk, &self.invoice_amount, &operation_start]).await?;
         Ok(())
     }
@@ -77,24 +72,26 @@ impl<'de> Deserialize<'de> for LNv2IncomingContractCommitment {
         D: serde::Deserializer<'de>,
     {
         let value = Value::deserialize(deserializer)?;
-        let amount = value["amount"].as_i64().expect("amount should be present");
+        let amount = value["amount"]
+            .as_i64()
+            .ok_or_else(|| de::Error::missing_field("amount"))?;
         let claim_pk = value["claim_pk"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("claim_pk"))?
             .to_string();
         let ephemeral_pk = value["ephemeral_pk"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("ephemeral_pk"))?
             .to_string();
         let expiration = value["expiration"]
             .as_i64()
-            .expect("amount should be present");
+            .ok_or_else(|| de::Error::missing_field("expiration"))?;
         let payment_image: LNv2PaymentImage =
             serde_json::from_value(value["payment_image"].clone())
-                .expect("Could not parse LNv2PaymentImage");
+                .map_err(|e| de::Error::custom(e.to_string()))?;
         let refund_pk = value["refund_pk"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("refund_pk"))?
             .to_string();
 
         Ok(Self {
@@ -126,21 +123,21 @@ impl<'de> Deserialize<'de> for LNv1IncomingPaymentStarted {
 
         let contract_id = value["contract_id"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("contract_id"))?
             .to_string();
         let contract_amount = value["contract_amount"]
             .as_i64()
-            .expect("contract amount should be present");
+            .ok_or_else(|| de::Error::missing_field("contract_amount"))?;
         let invoice_amount = value["invoice_amount"]
             .as_i64()
-            .expect("invoice amount should be present");
+            .ok_or_else(|| de::Error::missing_field("invoice_amount"))?;
         let operation_id = value["operation_id"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("operation_id"))?
             .to_string();
         let payment_hash = value["payment_hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("payment_hash"))?
             .to_string();
 
         Ok(LNv1IncomingPaymentStarted {
@@ -163,11 +160,9 @@ impl LNv1IncomingPaymentStarted {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_incoming_payment_started (log_id, ts, federation_id, federation_name, contract_id, contract_amount, invoice_amount, operation_id, payment_hash, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_incoming_payment_started (log_id, ts, federation_id, federation_name, contract_id, contract_amount, invoice_amount, operation_id, payment_hash, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT DO NOTHING",
         &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.contract_id, &self.contract_amount, &self.invoice_amount, &self.operation_id, &self.payment_hash, &gateway_epoch]).await?;
         Ok(())
     }
@@ -188,11 +183,11 @@ impl<'de> Deserialize<'de> for LNv1IncomingPaymentSucceeded {
 
         let payment_hash = value["payment_hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("payment_hash"))?
             .to_string();
         let preimage = value["preimage"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("preimage"))?
             .to_string();
 
         Ok(LNv1IncomingPaymentSucceeded {
@@ -212,11 +207,9 @@ impl LNv1IncomingPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_incoming_payment_succeeded (log_id, ts, federation_id, federation_name, payment_hash, preimage, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_incoming_payment_succeeded (log_id, ts, federation_id, federation_name, payment_hash, preimage, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.payment_hash, &self.preimage, &gateway_epoch]).await?;
         Ok(())
     }
@@ -235,7 +228,7 @@ impl<'de> Deserialize<'de> for LNv2IncomingPaymentSucceeded {
         let value = Value::deserialize(deserializer)?;
         let payment_image: LNv2PaymentImage =
             serde_json::from_value(value["payment_image"].clone())
-                .expect("Could not parse payment_image");
+                .map_err(|e| de::Error::custom(e.to_string()))?;
         Ok(Self { payment_image })
     }
 }
@@ -250,11 +243,9 @@ impl LNv2IncomingPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_incoming_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image) VALUES ($1, $2, $3, $4, $5, $6)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_incoming_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash]).await?;
         Ok(())
     }
@@ -275,11 +266,11 @@ impl<'de> Deserialize<'de> for LNv1IncomingPaymentFailed {
 
         let payment_hash = value["payment_hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("payment_hash"))?
             .to_string();
         let error = value["error"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("error"))?
             .to_string();
 
         Ok(LNv1IncomingPaymentFailed {
@@ -299,11 +290,9 @@ impl LNv1IncomingPaymentFailed {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_incoming_payment_failed (log_id, ts, federation_id, federation_name, payment_hash, error_reason, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_incoming_payment_failed (log_id, ts, federation_id, federation_name, payment_hash, error_reason, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.payment_hash, &self.error, &gateway_epoch]).await?;
         Ok(())
     }
@@ -323,10 +312,10 @@ impl<'de> Deserialize<'de> for LNv2IncomingPaymentFailed {
         let value = Value::deserialize(deserializer)?;
         let payment_image: LNv2PaymentImage =
             serde_json::from_value(value["payment_image"].clone())
-                .expect("Could not parse payment_image");
+                .map_err(|e| de::Error::custom(e.to_string()))?;
         let error = value["error"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("error"))?
             .to_string();
 
         Ok(Self {
@@ -346,11 +335,9 @@ impl LNv2IncomingPaymentFailed {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_incoming_payment_failed (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, error) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_incoming_payment_failed (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, error) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash, &self.error]).await?;
         Ok(())
     }
@@ -370,7 +357,7 @@ impl<'de> Deserialize<'de> for LNv1CompleteLightningPaymentSucceeded {
 
         let payment_hash = value["payment_hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("payment_hash"))?
             .to_string();
 
         Ok(LNv1CompleteLightningPaymentSucceeded { payment_hash })
@@ -387,11 +374,9 @@ impl LNv1CompleteLightningPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_complete_lightning_payment_succeeded (log_id, ts, federation_id, federation_name, payment_hash, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_complete_lightning_payment_succeeded (log_id, ts, federation_id, federation_name, payment_hash, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.payment_hash, &gateway_epoch]).await?;
         Ok(())
     }
@@ -410,7 +395,7 @@ impl<'de> Deserialize<'de> for LNv2CompleteLightningPaymentSucceeded {
         let value = Value::deserialize(deserializer)?;
         let payment_image: LNv2PaymentImage =
             serde_json::from_value(value["payment_image"].clone())
-                .expect("Could not parse payment_image");
+                .map_err(|e| de::Error::custom(e.to_string()))?;
         Ok(Self { payment_image })
     }
 }
@@ -425,11 +410,9 @@ impl LNv2CompleteLightningPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_complete_lightning_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image) VALUES ($1, $2, $3, $4, $5, $6)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_complete_lightning_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash]).await?;
         Ok(())
     }
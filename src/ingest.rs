@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use fedimint_core::{anyhow, config::FederationId};
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, schema};
+
+/// Accounts for every event a processor fetched in one run: each one has to
+/// either be found in an event table afterwards or have been skipped on
+/// purpose (unknown module or kind, ledger events without a balance change).
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct IngestStats {
+    pub federation_name: String,
+    pub fetched: u64,
+    pub skipped: u64,
+    pub written: BTreeMap<&'static str, i64>,
+}
+
+impl IngestStats {
+    /// Counts the rows the run added to the event tables, i.e. rows of the
+    /// current gateway epoch above the log id the run started from.
+    pub async fn query(
+        pg_client: &Client,
+        federation_id: FederationId,
+        federation_name: String,
+        gateway_epoch: GatewayEpoch,
+        start_log_id: i64,
+        fetched: u64,
+        skipped: u64,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            federation_name,
+            fetched,
+            skipped,
+            written: Self::written(pg_client, federation_id, gateway_epoch, start_log_id).await?,
+        })
+    }
+
+    /// Rows per event table above `start_log_id`. A dry run counts them in
+    /// each transaction before rolling it back.
+    pub async fn written(
+        pg_client: &Client,
+        federation_id: FederationId,
+        gateway_epoch: GatewayEpoch,
+        start_log_id: i64,
+    ) -> anyhow::Result<BTreeMap<&'static str, i64>> {
+        let mut written = BTreeMap::new();
+        for (table, _) in schema::event_tables() {
+            let row = pg_client
+                .query_one(
+                    &format!(
+                        "SELECT COUNT(*) FROM {table} WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id > $3"
+                    ),
+                    &[
+                        &federation_id.to_string(),
+                        &i32::from(gateway_epoch),
+                        &start_log_id,
+                    ],
+                )
+                .await?;
+            let count: i64 = row.get(0);
+            if count > 0 {
+                written.insert(table, count);
+            }
+        }
+        Ok(written)
+    }
+
+    pub fn accounted(&self) -> u64 {
+        self.written.values().sum::<i64>() as u64 + self.skipped
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.accounted() == self.fetched
+    }
+}
+
+impl fmt::Display for IngestStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: fetched {}, written {}, skipped {}",
+            self.federation_name,
+            self.fetched,
+            self.accounted() - self.skipped,
+            self.skipped
+        )
+    }
+}
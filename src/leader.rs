@@ -0,0 +1,33 @@
+use deadpool_postgres::{ClientWrapper, Object, Pool};
+use fedimint_core::anyhow;
+use tracing::info;
+
+/// Advisory lock held by the replica that ingests.
+const LEADER_LOCK: i64 = 0x6574_6c5f_6c65_6164;
+
+/// Leadership among ETL replicas sharing a database. Only the replica
+/// holding the lock runs, the others skip their run. The cursor already lives
+/// in the database, so a standby picks up where the leader stopped.
+///
+/// The lock is tied to a connection taken out of the pool: it's released
+/// when the run ends or the process dies, and the next scheduled run of
+/// another replica takes over.
+pub(crate) struct LeaderLock {
+    _client: ClientWrapper,
+}
+
+impl LeaderLock {
+    /// Returns `None` if another replica holds the lock.
+    pub async fn try_acquire(pool: &Pool) -> anyhow::Result<Option<Self>> {
+        let client = Object::take(pool.get().await?);
+        let acquired: bool = client
+            .query_one("SELECT pg_try_advisory_lock($1)", &[&LEADER_LOCK])
+            .await?
+            .get(0);
+        if !acquired {
+            return Ok(None);
+        }
+        info!("Acquired the leader lock");
+        Ok(Some(Self { _client: client }))
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+
+use crate::{
+    msat::Msat, payments::PAYMENTS_QUERY, rebalance::RebalanceOpts, time_window::TimeWindow,
+};
+
+/// One federation's row in the weekly leaderboard.
+#[derive(Debug, Clone)]
+pub(crate) struct LeaderboardEntry {
+    federation_name: String,
+    succeeded: i64,
+    total: i64,
+    volume: Msat,
+    fees: Msat,
+    previous_volume: Msat,
+}
+
+impl LeaderboardEntry {
+    fn success_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.succeeded as f64 * 100.0 / self.total as f64
+    }
+
+    fn growth(&self) -> Option<f64> {
+        self.volume
+            .checked_sub(self.previous_volume)?
+            .percent_of(self.previous_volume)
+    }
+}
+
+/// Ranks federations by the volume they routed in the week ending at `now`,
+/// along with their fees, success rate and growth compared to the week
+/// before. The operator's rebalances are left out, they are neither volume
+/// routed for others nor revenue.
+pub(crate) struct WeeklyLeaderboard(Vec<LeaderboardEntry>);
+
+impl WeeklyLeaderboard {
+    pub async fn query(
+        pg_client: &Client,
+        now: DateTime<Utc>,
+        rebalances: &RebalanceOpts,
+    ) -> anyhow::Result<Self> {
+        let week = TimeWindow::trailing(now, Duration::days(7));
+        let (week_start, now) = week.naive_bounds();
+        let (previous_week_start, _) = week.previous().naive_bounds();
+
+        let query = format!(
+            "
+            WITH payments AS ({PAYMENTS_QUERY})
+            SELECT
+                MAX(federation_name),
+                COUNT(*) FILTER (WHERE outcome = 'succeeded' AND ts >= $1),
+                COUNT(*) FILTER (WHERE ts >= $1),
+                COALESCE(SUM(amount_msat) FILTER (WHERE outcome = 'succeeded' AND ts >= $1), 0)::TEXT,
+                COALESCE(SUM(fee_msat) FILTER (WHERE outcome = 'succeeded' AND ts >= $1), 0)::TEXT,
+                COALESCE(SUM(amount_msat) FILTER (WHERE outcome = 'succeeded' AND ts < $1), 0)::TEXT
+            FROM payments
+            WHERE ts >= $2 AND ts < $3 AND NOT ({})
+            GROUP BY federation_id
+            ORDER BY SUM(amount_msat) FILTER (WHERE outcome = 'succeeded' AND ts >= $1) DESC NULLS LAST
+            ",
+            rebalances.condition()
+        );
+
+        let rows = pg_client
+            .query(&query, &[&week_start, &previous_week_start, &now])
+            .await?;
+        let entries = rows
+            .iter()
+            .map(|row| LeaderboardEntry {
+                federation_name: row.get(0),
+                succeeded: row.get(1),
+                total: row.get(2),
+                volume: row.get(3),
+                fees: row.get(4),
+                previous_volume: row.get(5),
+            })
+            .collect();
+
+        Ok(Self(entries))
+    }
+}
+
+impl fmt::Display for WeeklyLeaderboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "===========WEEKLY TOP FEDERATIONS===========")?;
+        for (rank, entry) in self.0.iter().enumerate() {
+            let volume = entry.volume.to_bitcoin_amount();
+            let fees = entry.fees.to_bitcoin_amount();
+            let growth = entry
+                .growth()
+                .map(|growth| format!("{growth:+.1}%"))
+                .unwrap_or_else(|| "n/a".to_string());
+            writeln!(
+                f,
+                "{}. {} - Volume: {}, Fees: {}, Success Rate: {:.1}%, Growth: {}",
+                rank + 1,
+                entry.federation_name,
+                volume,
+                fees,
+                entry.success_rate(),
+                growth,
+            )?;
+        }
+        writeln!(f)
+    }
+}
@@ -0,0 +1,106 @@
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::EventLogId;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::{dead_letter, parse_log_id};
+
+/// A single balance change of the gateway's ecash wallet inside a federation,
+/// derived from the mint and wallet module events. Together with the payment
+/// events this gives a double-entry view of the gateway's funds.
+#[derive(Debug, Clone)]
+pub(crate) struct GatewayLedgerEntry {
+    module: String,
+    kind: String,
+    operation_id: Option<String>,
+    direction: LedgerDirection,
+    amount_msat: i64,
+    fee_msat: i64,
+    market_fee_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LedgerDirection {
+    Credit,
+    Debit,
+}
+
+impl LedgerDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LedgerDirection::Credit => "credit",
+            LedgerDirection::Debit => "debit",
+        }
+    }
+}
+
+impl GatewayLedgerEntry {
+    /// Parses a mint or wallet module event. Returns `None` for events that
+    /// don't change the gateway's balance (e.g. `note-created`).
+    pub fn parse(module: &str, kind: &str, value: &Value) -> anyhow::Result<Option<Self>> {
+        let (direction, amount_msat, fee_msat) = match (module, kind) {
+            ("mint", "payment-send") => (LedgerDirection::Debit, amount(value, "amount")?, 0),
+            ("mint", "payment-receive") => (LedgerDirection::Credit, amount(value, "amount")?, 0),
+            ("mint", "oob-notes-spent") => {
+                (LedgerDirection::Debit, amount(value, "spent_amount")?, 0)
+            }
+            ("mint", "oob-notes-reissued") => {
+                (LedgerDirection::Credit, amount(value, "amount")?, 0)
+            }
+            // Peg-out amounts are denominated in sats
+            ("wallet", "payment-send") => (
+                LedgerDirection::Debit,
+                amount(value, "amount")? * 1000,
+                amount(value, "fee")? * 1000,
+            ),
+            ("wallet", "payment-receive") => (LedgerDirection::Credit, amount(value, "amount")?, 0),
+            _ => return Ok(None),
+        };
+
+        let operation_id = value
+            .get("operation_id")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        Ok(Some(Self {
+            module: module.to_string(),
+            kind: kind.to_string(),
+            operation_id,
+            direction,
+            amount_msat,
+            fee_msat,
+            market_fee_rate: None,
+        }))
+    }
+
+    pub fn is_peg_out(&self) -> bool {
+        self.module == "wallet" && self.kind == "payment-send"
+    }
+
+    /// Records the onchain fee rate (sat/vB) at the time of a peg-out.
+    pub fn set_market_fee_rate(&mut self, market_fee_rate: Option<f64>) {
+        self.market_fee_rate = market_fee_rate;
+    }
+
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        log_id: &EventLogId,
+        timestamp: u64,
+        federation_id: &FederationId,
+        federation_name: String,
+        gateway_epoch: i32,
+    ) -> anyhow::Result<()> {
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO gateway_ledger (log_id, ts, federation_id, federation_name, gateway_epoch, module, kind, operation_id, direction, amount_msat, fee_msat, market_fee_rate) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.module, &self.kind, &self.operation_id, &self.direction.as_str(), &self.amount_msat, &self.fee_msat, &self.market_fee_rate]).await?;
+        Ok(())
+    }
+}
+
+fn amount(value: &Value, field: &str) -> anyhow::Result<i64> {
+    value[field]
+        .as_i64()
+        .ok_or_else(|| anyhow::anyhow!("{field} should be present"))
+}
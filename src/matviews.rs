@@ -0,0 +1,81 @@
+use std::{str::FromStr, time::Instant};
+
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::{error, info};
+
+/// A materialized view defined by the operator on top of the ETL tables,
+/// optionally schema qualified.
+#[derive(Debug, Clone)]
+pub(crate) struct MaterializedView(String);
+
+impl FromStr for MaterializedView {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The name ends up in a statement that can't take parameters
+        anyhow::ensure!(
+            !s.is_empty()
+                && s.split('.').count() <= 2
+                && s.split('.').all(|part| {
+                    !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }),
+            "Invalid materialized view name: {s}"
+        );
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl MaterializedView {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Refreshes the view without blocking readers. `CONCURRENTLY` needs a
+    /// unique index on the view and can't be used before it was populated
+    /// once, in that case a regular refresh is done.
+    async fn refresh(&self, pg_client: &Client) -> anyhow::Result<()> {
+        let row = pg_client
+            .query_opt(
+                "SELECT ispopulated FROM pg_matviews WHERE format('%I.%I', schemaname, matviewname)::REGCLASS = to_regclass($1)",
+                &[&self.0],
+            )
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Materialized view {} does not exist", self.0))?;
+        let populated: bool = row.get(0);
+        let statement = if populated {
+            format!("REFRESH MATERIALIZED VIEW CONCURRENTLY {}", self.0)
+        } else {
+            format!("REFRESH MATERIALIZED VIEW {}", self.0)
+        };
+        pg_client.batch_execute(&statement).await?;
+        Ok(())
+    }
+}
+
+/// Refreshes `views` one after the other and returns how long each refresh
+/// took in seconds. A failed refresh is logged and leaves the view out of the
+/// result, the ingested data is not affected by it.
+pub(crate) async fn refresh_all(
+    pg_client: &Client,
+    views: &[MaterializedView],
+) -> Vec<(MaterializedView, f64)> {
+    let mut durations = Vec::new();
+    for view in views {
+        let start = Instant::now();
+        match view.refresh(pg_client).await {
+            Ok(()) => {
+                let secs = start.elapsed().as_secs_f64();
+                info!(view = view.name(), secs, "Refreshed materialized view");
+                durations.push((view.clone(), secs));
+            }
+            Err(err) => {
+                error!(
+                    view = view.name(),
+                    "Error refreshing materialized view: {err:#}"
+                );
+            }
+        }
+    }
+    durations
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use fedimint_core::anyhow;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::{msat::Msat, time_window::TimeWindow};
+
+/// Fee rates further than this from the event are not attributed to it.
+const MAX_FEE_RATE_DISTANCE_SECS: i64 = 3 * 60 * 60;
+
+/// Median fee rates (sat/vB) of recently mined blocks, fetched once per run
+/// from a mempool.space compatible API. Since events are ingested long after
+/// they happened, the fee rate is looked up by the event's timestamp instead
+/// of asking for the current one.
+#[derive(Debug, Clone)]
+pub(crate) struct FeeRateHistory(Vec<(i64, f64)>);
+
+impl FeeRateHistory {
+    pub async fn fetch(mempool_url: &str) -> anyhow::Result<Self> {
+        let blocks: Vec<Value> = reqwest::get(format!(
+            "{}/api/v1/mining/blocks/fee-rates/1w",
+            mempool_url.trim_end_matches('/')
+        ))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+        let fee_rates = blocks
+            .iter()
+            .filter_map(|block| Some((block["timestamp"].as_i64()?, block["avgFee_50"].as_f64()?)))
+            .collect();
+        Ok(Self(fee_rates))
+    }
+
+    /// Returns the median fee rate of the blocks closest to `timestamp`
+    /// (microseconds, as in the event log).
+    pub fn at(&self, timestamp: u64) -> Option<f64> {
+        let secs = (timestamp / 1_000_000) as i64;
+        self.0
+            .iter()
+            .min_by_key(|(block_secs, _)| (block_secs - secs).abs())
+            .filter(|(block_secs, _)| (block_secs - secs).abs() <= MAX_FEE_RATE_DISTANCE_SECS)
+            .map(|(_, fee_rate)| *fee_rate)
+    }
+}
+
+/// Peg-outs within the summary window with the fees paid and the market fee
+/// rate at the time they were made.
+pub(crate) struct PegOutSummary {
+    count: i64,
+    amount: Msat,
+    fees: Msat,
+    avg_market_fee_rate: Option<f64>,
+}
+
+impl PegOutSummary {
+    pub async fn query(pg_client: &Client, window: TimeWindow) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let row = pg_client
+            .query_one(
+                "
+                SELECT COUNT(*), COALESCE(SUM(amount_msat), 0)::TEXT, COALESCE(SUM(fee_msat), 0)::TEXT, AVG(market_fee_rate)
+                FROM gateway_ledger
+                WHERE module = 'wallet' AND kind = 'payment-send' AND ts >= $1 AND ts < $2
+                ",
+                &[&start, &end],
+            )
+            .await?;
+        Ok(Self {
+            count: row.get(0),
+            amount: row.get(1),
+            fees: row.get(2),
+            avg_market_fee_rate: row.get(3),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl fmt::Display for PegOutSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = self.amount.to_bitcoin_amount();
+        let fees = self.fees.to_bitcoin_amount();
+        let fee_rate = self
+            .avg_market_fee_rate
+            .map(|fee_rate| format!("{fee_rate:.1} sat/vB"))
+            .unwrap_or_else(|| "n/a".to_string());
+        writeln!(
+            f,
+            "Peg-Outs: {}, Amount: {}, Fees: {}, Avg Market Fee Rate: {}\n",
+            self.count, amount, fees, fee_rate
+        )
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use chrono::{DateTime, Utc};
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+
+use crate::{
+    consistency::SummaryDrift, matviews::MaterializedView, schema, table_sizes::TableGrowth,
+};
+
+/// Started events without a succeeded or failed event for the same payment:
+/// `(direction, started table, terminal tables, key column)`.
+const PENDING_PAYMENTS: &[(&str, &str, &[&str], &str)] = &[
+    (
+        "outgoing",
+        "lnv1_outgoing_payment_started",
+        &[
+            "lnv1_outgoing_payment_succeeded",
+            "lnv1_outgoing_payment_failed",
+        ],
+        "contract_id",
+    ),
+    (
+        "incoming",
+        "lnv1_incoming_payment_started",
+        &[
+            "lnv1_incoming_payment_succeeded",
+            "lnv1_incoming_payment_failed",
+        ],
+        "payment_hash",
+    ),
+    (
+        "outgoing",
+        "lnv2_outgoing_payment_started",
+        &[
+            "lnv2_outgoing_payment_succeeded",
+            "lnv2_outgoing_payment_failed",
+        ],
+        "payment_image",
+    ),
+    (
+        "incoming",
+        "lnv2_incoming_payment_started",
+        &[
+            "lnv2_incoming_payment_succeeded",
+            "lnv2_incoming_payment_failed",
+        ],
+        "payment_image",
+    ),
+];
+
+#[derive(Debug, Default)]
+struct FederationGauges {
+    rows: BTreeMap<&'static str, i64>,
+    last_event_secs: Option<f64>,
+    watermark_secs: Option<f64>,
+    pending_payments: BTreeMap<&'static str, i64>,
+}
+
+/// Per-federation gauges computed from the warehouse after every run. The ETL
+/// is not a long running process that could be scraped, so the gauges are
+/// pushed to a Prometheus Pushgateway instead.
+#[derive(Debug)]
+pub(crate) struct FederationMetrics {
+    now: DateTime<Utc>,
+    federations: BTreeMap<String, FederationGauges>,
+    view_refreshes: Vec<(MaterializedView, f64)>,
+    summary_drift: Option<SummaryDrift>,
+    table_growth: Option<TableGrowth>,
+}
+
+impl FederationMetrics {
+    pub async fn query(pg_client: &Client, now: DateTime<Utc>) -> anyhow::Result<Self> {
+        let mut federations = BTreeMap::<String, FederationGauges>::new();
+        for (table, _) in schema::event_tables() {
+            let rows = pg_client
+                .query(
+                    &format!(
+                        "SELECT federation_id, COUNT(*), EXTRACT(EPOCH FROM MAX(ts))::FLOAT8 FROM {table} GROUP BY federation_id"
+                    ),
+                    &[],
+                )
+                .await?;
+            for row in rows {
+                let gauges = federations.entry(row.get(0)).or_default();
+                gauges.rows.insert(table, row.get(1));
+                let last_event_secs: f64 = row.get(2);
+                gauges.last_event_secs = Some(
+                    gauges
+                        .last_event_secs
+                        .map_or(last_event_secs, |secs| secs.max(last_event_secs)),
+                );
+            }
+        }
+
+        // The watermark of an earlier gateway epoch says nothing about the
+        // events ingested since
+        let rows = pg_client
+            .query(
+                "SELECT DISTINCT ON (federation_id) federation_id, EXTRACT(EPOCH FROM watermark)::FLOAT8 FROM ingestion_watermarks ORDER BY federation_id, gateway_epoch DESC",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            federations.entry(row.get(0)).or_default().watermark_secs = Some(row.get(1));
+        }
+
+        for (direction, started, terminals, key) in PENDING_PAYMENTS {
+            let not_terminated = terminals
+                .iter()
+                .map(|terminal| {
+                    format!(
+                        "NOT EXISTS (SELECT 1 FROM {terminal} t WHERE t.{key} = st.{key} AND t.federation_id = st.federation_id)"
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            let rows = pg_client
+                .query(
+                    &format!(
+                        "SELECT federation_id, COUNT(*) FROM {started} st WHERE {not_terminated} GROUP BY federation_id"
+                    ),
+                    &[],
+                )
+                .await?;
+            for row in rows {
+                let count: i64 = row.get(1);
+                *federations
+                    .entry(row.get(0))
+                    .or_default()
+                    .pending_payments
+                    .entry(direction)
+                    .or_default() += count;
+            }
+        }
+
+        Ok(Self {
+            now,
+            federations,
+            view_refreshes: Vec::new(),
+            summary_drift: None,
+            table_growth: None,
+        })
+    }
+
+    /// Adds how long refreshing each materialized view took in this run.
+    pub fn with_view_refreshes(mut self, view_refreshes: Vec<(MaterializedView, f64)>) -> Self {
+        self.view_refreshes = view_refreshes;
+        self
+    }
+
+    /// Adds how far the event tables are off from the gateway's payment
+    /// summary.
+    pub fn with_summary_drift(mut self, summary_drift: SummaryDrift) -> Self {
+        self.summary_drift = Some(summary_drift);
+        self
+    }
+
+    /// Adds the size and growth of the warehouse tables.
+    pub fn with_table_growth(mut self, table_growth: TableGrowth) -> Self {
+        self.table_growth = Some(table_growth);
+        self
+    }
+
+    /// Replaces the metrics of the `etl_gateway` job on the Pushgateway.
+    pub async fn push(&self, pushgateway_url: &str) -> anyhow::Result<()> {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/metrics/job/etl_gateway",
+                pushgateway_url.trim_end_matches('/')
+            ))
+            .body(self.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Prometheus text exposition format.
+impl fmt::Display for FederationMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rows = String::new();
+        let mut last_event = String::new();
+        let mut lag = String::new();
+        let mut watermark = String::new();
+        let mut pending = String::new();
+        for (federation_id, gauges) in &self.federations {
+            for (table, count) in &gauges.rows {
+                writeln!(
+                    rows,
+                    "etl_gateway_table_rows{{federation_id=\"{federation_id}\",table=\"{table}\"}} {count}"
+                )?;
+            }
+            if let Some(secs) = gauges.last_event_secs {
+                writeln!(
+                    last_event,
+                    "etl_gateway_last_event_timestamp_seconds{{federation_id=\"{federation_id}\"}} {secs}"
+                )?;
+                writeln!(
+                    lag,
+                    "etl_gateway_ingest_lag_seconds{{federation_id=\"{federation_id}\"}} {}",
+                    self.now.timestamp() as f64 - secs
+                )?;
+            }
+            if let Some(secs) = gauges.watermark_secs {
+                writeln!(
+                    watermark,
+                    "etl_gateway_ingestion_watermark_seconds{{federation_id=\"{federation_id}\"}} {secs}"
+                )?;
+            }
+            for direction in ["incoming", "outgoing"] {
+                writeln!(
+                    pending,
+                    "etl_gateway_pending_payments{{federation_id=\"{federation_id}\",direction=\"{direction}\"}} {}",
+                    gauges.pending_payments.get(direction).unwrap_or(&0)
+                )?;
+            }
+        }
+
+        writeln!(f, "# HELP etl_gateway_table_rows Rows per event table")?;
+        writeln!(f, "# TYPE etl_gateway_table_rows gauge")?;
+        f.write_str(&rows)?;
+        writeln!(
+            f,
+            "# HELP etl_gateway_last_event_timestamp_seconds Timestamp of the newest ingested event"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_last_event_timestamp_seconds gauge")?;
+        f.write_str(&last_event)?;
+        writeln!(
+            f,
+            "# HELP etl_gateway_ingest_lag_seconds Age of the newest ingested event"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_ingest_lag_seconds gauge")?;
+        f.write_str(&lag)?;
+        writeln!(
+            f,
+            "# HELP etl_gateway_ingestion_watermark_seconds Time up to which all events are ingested"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_ingestion_watermark_seconds gauge")?;
+        f.write_str(&watermark)?;
+        writeln!(
+            f,
+            "# HELP etl_gateway_pending_payments Started payments without a succeeded or failed event"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_pending_payments gauge")?;
+        f.write_str(&pending)?;
+
+        if !self.view_refreshes.is_empty() {
+            writeln!(
+                f,
+                "# HELP etl_gateway_materialized_view_refresh_seconds Duration of the last refresh"
+            )?;
+            writeln!(
+                f,
+                "# TYPE etl_gateway_materialized_view_refresh_seconds gauge"
+            )?;
+            for (view, secs) in &self.view_refreshes {
+                writeln!(
+                    f,
+                    "etl_gateway_materialized_view_refresh_seconds{{view=\"{}\"}} {secs}",
+                    view.name()
+                )?;
+            }
+        }
+
+        if let Some(summary_drift) = &self.summary_drift {
+            writeln!(
+                f,
+                "# HELP etl_gateway_summary_drift Event table value minus the gateway's payment summary value"
+            )?;
+            writeln!(f, "# TYPE etl_gateway_summary_drift gauge")?;
+            for metric in &summary_drift.metrics {
+                writeln!(
+                    f,
+                    "etl_gateway_summary_drift{{direction=\"{}\",metric=\"{}\"}} {}",
+                    metric.direction,
+                    metric.metric,
+                    metric.drift()
+                )?;
+            }
+        }
+
+        if let Some(table_growth) = &self.table_growth {
+            table_growth.write_metrics(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+/// Held while migrating, so two instances starting at the same time don't
+/// both apply the same migration.
+const MIGRATION_LOCK: i64 = 0x6574_6c5f_6d69_6772;
+
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// Every schema change, in order. A migration is never edited once released:
+/// changes go into a new one, together with the update of `EXPECTED_SCHEMA`.
+/// Columns added to existing tables should use `ADD COLUMN IF NOT EXISTS`, as
+/// some databases got them by hand from `ddl.sql`.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "gateway_summary_snapshots",
+        sql: include_str!("migrations/0002_gateway_summary_snapshots.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "telegram_messages",
+        sql: include_str!("migrations/0003_telegram_messages.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "table_sizes",
+        sql: include_str!("migrations/0004_table_sizes.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "dead_letter_events",
+        sql: include_str!("migrations/0005_dead_letter_events.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "raw_events",
+        sql: include_str!("migrations/0006_raw_events.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "nullable_event_ts",
+        sql: include_str!("migrations/0007_nullable_event_ts.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "paused_federations",
+        sql: include_str!("migrations/0008_paused_federations.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "rebalance_payments",
+        sql: include_str!("migrations/0009_rebalance_payments.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "report_deliveries",
+        sql: include_str!("migrations/0010_report_deliveries.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "ingestion_watermarks",
+        sql: include_str!("migrations/0011_ingestion_watermarks.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "checked_gaps",
+        sql: include_str!("migrations/0012_checked_gaps.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "event_primary_keys",
+        sql: include_str!("migrations/0013_event_primary_keys.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "raw_events_cbor_zstd",
+        sql: include_str!("migrations/0014_raw_events_cbor_zstd.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "output_sequences",
+        sql: include_str!("migrations/0015_output_sequences.sql"),
+    },
+];
+
+/// How pending migrations are handled before a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum MigrateMode {
+    /// Print the pending migrations and exit without running
+    Plan,
+    /// Apply the pending migrations
+    Auto,
+    /// Never run DDL, refuse to run while migrations are pending
+    Off,
+}
+
+/// Makes sure the schema is up to date before a run, applying the pending
+/// migrations if `mode` allows it. `plan` exits before a run, it is checked
+/// like `off`.
+pub(crate) async fn prepare(pg_client: &Client, mode: MigrateMode) -> anyhow::Result<()> {
+    match mode {
+        MigrateMode::Auto => migrate(pg_client).await,
+        MigrateMode::Plan | MigrateMode::Off => {
+            let pending = pending(pg_client).await?;
+            anyhow::ensure!(
+                pending.is_empty(),
+                "Database has pending migrations ({}), apply them with `init-db` or `--migrate auto`",
+                pending
+                    .iter()
+                    .map(|migration| format!("{} {}", migration.version, migration.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Prints the migrations [`migrate`] would apply to `database`, with their
+/// SQL, without applying them.
+pub(crate) async fn plan(pg_client: &Client, database: &str) -> anyhow::Result<()> {
+    let pending = pending(pg_client).await?;
+    if pending.is_empty() {
+        println!("The {database} database is up to date");
+        return Ok(());
+    }
+    println!(
+        "The {database} database has {} pending migrations:",
+        pending.len()
+    );
+    for migration in pending {
+        println!(
+            "\n-- {:04}_{}\n{}",
+            migration.version,
+            migration.name,
+            migration.sql.trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// The migrations the database hasn't seen yet, without taking the
+/// migration lock.
+async fn pending(pg_client: &Client) -> anyhow::Result<Vec<&'static Migration>> {
+    let has_versions: bool = pg_client
+        .query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])
+        .await?
+        .get(0);
+    let current: i32 = if has_versions {
+        pg_client
+            .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])
+            .await?
+            .get(0)
+    } else {
+        0
+    };
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+        .collect())
+}
+
+/// Applies the migrations the database hasn't seen yet, recording each one
+/// in `schema_version`. All of them run in one transaction, so a failing
+/// migration leaves the schema as it was.
+pub(crate) async fn migrate(pg_client: &Client) -> anyhow::Result<()> {
+    pg_client.batch_execute("BEGIN").await?;
+    match apply_pending(pg_client).await {
+        Ok(applied) => {
+            pg_client.batch_execute("COMMIT").await?;
+            if applied.is_empty() {
+                info!("Database schema is up to date");
+            }
+            for migration in applied {
+                info!(
+                    version = migration.version,
+                    name = migration.name,
+                    "Applied migration"
+                );
+            }
+            Ok(())
+        }
+        Err(err) => {
+            pg_client.batch_execute("ROLLBACK").await?;
+            Err(err)
+        }
+    }
+}
+
+async fn apply_pending(pg_client: &Client) -> anyhow::Result<Vec<&'static Migration>> {
+    pg_client
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    // Keeps the notices of `IF NOT EXISTS` statements out of the log
+    pg_client
+        .batch_execute("SET LOCAL client_min_messages = warning")
+        .await?;
+    pg_client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_at TIMESTAMP NOT NULL)",
+        )
+        .await?;
+    let current: i32 = pg_client
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])
+        .await?
+        .get(0);
+
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+    if current > latest {
+        warn!(
+            current,
+            latest, "Database schema is newer than this binary, skipping migrations"
+        );
+        return Ok(Vec::new());
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+    {
+        pg_client
+            .batch_execute(migration.sql)
+            .await
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "Error applying migration {} ({}): {err}",
+                    migration.version,
+                    migration.name
+                )
+            })?;
+        pg_client
+            .execute(
+                "INSERT INTO schema_version (version, name, applied_at) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &Utc::now().naive_utc()],
+            )
+            .await?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MIGRATIONS;
+    use crate::test_db;
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn event_keys_of_ddl_databases_include_the_federation() {
+        let pg_client = test_db::connect().await;
+        // The key of databases created from ddl.sql before it had the federation
+        pg_client
+            .batch_execute(
+                "ALTER TABLE lnv2_outgoing_payment_failed DROP CONSTRAINT lnv2_outgoing_payment_failed_pkey;
+                ALTER TABLE lnv2_outgoing_payment_failed ADD PRIMARY KEY (log_id, gateway_epoch);",
+            )
+            .await
+            .unwrap();
+        let event_primary_keys = MIGRATIONS
+            .iter()
+            .find(|migration| migration.name == "event_primary_keys")
+            .unwrap();
+        // Applying it again leaves the keys it already rewrote alone
+        for _ in 0..2 {
+            pg_client
+                .batch_execute(event_primary_keys.sql)
+                .await
+                .unwrap();
+        }
+
+        for federation_id in ["fed1", "fed2"] {
+            pg_client
+                .execute(
+                    "INSERT INTO lnv2_outgoing_payment_failed (gateway_epoch, log_id, ts, federation_id, federation_name, payment_image, error) VALUES (0, 7, '2024-05-01 10:00:00', $1, 'Federation', 'image', 'error') ON CONFLICT DO NOTHING",
+                    &[&federation_id],
+                )
+                .await
+                .unwrap();
+        }
+        let count: i64 = pg_client
+            .query_one("SELECT COUNT(*) FROM lnv2_outgoing_payment_failed", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(count, 2);
+    }
+}
@@ -0,0 +1,433 @@
+-- The schema at the time migrations were introduced. Databases set up from
+-- ddl.sql already have all of it, so every statement is a no-op there.
+
+CREATE SCHEMA IF NOT EXISTS staging;
+
+CREATE TABLE IF NOT EXISTS back_dated_events (
+    event_log_id BIGINT NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    event_ts TIMESTAMP NOT NULL,
+    newest_ts TIMESTAMP NOT NULL,
+    accepted BOOLEAN NOT NULL,
+    recorded_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (event_log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS computed_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    expression TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (table_name, column_name)
+);
+
+CREATE TABLE IF NOT EXISTS etl_cursor (
+    federation_id TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    last_log_id BIGINT NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS etl_runs (
+    id BIGSERIAL,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP,
+    gateway_epoch INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    config_hash TEXT NOT NULL,
+    config JSONB NOT NULL,
+    ingest_stats JSONB,
+    backfill_checkpoints JSONB,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS federation_config_changes (
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    field TEXT NOT NULL,
+    old_value TEXT NOT NULL,
+    new_value TEXT NOT NULL,
+    PRIMARY KEY (federation_id, ts, field)
+);
+
+CREATE TABLE IF NOT EXISTS federation_config_snapshots (
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    lightning_base_msat BIGINT NOT NULL,
+    lightning_ppm BIGINT NOT NULL,
+    transaction_base_msat BIGINT NOT NULL,
+    transaction_ppm BIGINT NOT NULL,
+    tos_url TEXT,
+    modules TEXT NOT NULL,
+    PRIMARY KEY (federation_id, ts)
+);
+
+CREATE TABLE IF NOT EXISTS gateway_epochs (
+    gateway_epoch INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    reason TEXT,
+    PRIMARY KEY (gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS gateway_ledger (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    module TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    operation_id TEXT,
+    direction TEXT NOT NULL,
+    amount_msat BIGINT NOT NULL,
+    fee_msat BIGINT NOT NULL,
+    market_fee_rate DOUBLE PRECISION,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS gateway_uptime (
+    ts TIMESTAMP NOT NULL,
+    reachable BOOLEAN NOT NULL,
+    latency_ms DOUBLE PRECISION NOT NULL,
+    error TEXT,
+    PRIMARY KEY (ts)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_complete_lightning_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_contract_cancelled (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    contract_id TEXT NOT NULL,
+    contract_amount BIGINT,
+    reason TEXT,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_incoming_payment_failed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    error_reason TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_incoming_payment_started (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    contract_id TEXT NOT NULL,
+    contract_amount BIGINT NOT NULL,
+    invoice_amount BIGINT NOT NULL,
+    operation_id TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_incoming_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    preimage TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_outgoing_payment_failed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    contract_id TEXT NOT NULL,
+    contract_amount BIGINT NOT NULL,
+    gateway_key TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    timelock BIGINT NOT NULL,
+    user_key TEXT NOT NULL,
+    error_reason TEXT,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_outgoing_payment_started (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    contract_id TEXT NOT NULL,
+    invoice_amount BIGINT NOT NULL,
+    operation_id TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_outgoing_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    contract_id TEXT NOT NULL,
+    contract_amount BIGINT NOT NULL,
+    gateway_key TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    timelock BIGINT NOT NULL,
+    user_key TEXT NOT NULL,
+    preimage TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv1_refund_claimed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    contract_id TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_complete_lightning_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_contract_cancelled (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    amount BIGINT,
+    reason TEXT,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_incoming_payment_failed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    error TEXT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_incoming_payment_started (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    amount BIGINT NOT NULL,
+    claim_pk TEXT NOT NULL,
+    ephemeral_pk TEXT NOT NULL,
+    expiration BIGINT NOT NULL,
+    payment_image TEXT NOT NULL,
+    refund_pk TEXT NOT NULL,
+    invoice_amount BIGINT NOT NULL,
+    operation_start TIMESTAMP NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_incoming_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_outgoing_payment_failed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    error TEXT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_outgoing_payment_started (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    invoice_amount BIGINT NOT NULL,
+    max_delay BIGINT NOT NULL,
+    min_contract_amount BIGINT NOT NULL,
+    operation_start TIMESTAMP NOT NULL,
+    amount BIGINT NOT NULL,
+    claim_pk TEXT NOT NULL,
+    ephemeral_pk TEXT NOT NULL,
+    expiration BIGINT NOT NULL,
+    payment_image TEXT NOT NULL,
+    refund_pk TEXT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_outgoing_payment_succeeded (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    target_federation TEXT,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS lnv2_refund_claimed (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_image TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS node_payments (
+    node TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    amount_msat BIGINT NOT NULL,
+    fee_msat BIGINT NOT NULL,
+    status TEXT NOT NULL,
+    collected_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (node, direction, payment_hash)
+);
+
+CREATE TABLE IF NOT EXISTS onchain_transactions (
+    federation_id TEXT NOT NULL,
+    txid TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    operation_id TEXT,
+    first_seen TIMESTAMP NOT NULL,
+    block_height INTEGER,
+    confirmations INTEGER NOT NULL,
+    last_checked TIMESTAMP,
+    PRIMARY KEY (federation_id, txid)
+);
+
+CREATE TABLE IF NOT EXISTS payment_latency_splits (
+    terminal_log_id BIGINT NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    payment_hash TEXT NOT NULL,
+    started_ts TIMESTAMP NOT NULL,
+    lightning_started_ts TIMESTAMP NOT NULL,
+    completed_ts TIMESTAMP NOT NULL,
+    gateway_ms DOUBLE PRECISION NOT NULL,
+    lightning_ms DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (terminal_log_id, federation_id, gateway_epoch)
+);
+
+CREATE TABLE IF NOT EXISTS reconciliation_records (
+    id BIGSERIAL,
+    imported_at TIMESTAMP NOT NULL,
+    source TEXT NOT NULL,
+    external_id TEXT,
+    ts TIMESTAMP NOT NULL,
+    payment_hash TEXT NOT NULL,
+    amount_msat BIGINT NOT NULL,
+    fee_msat BIGINT,
+    status TEXT NOT NULL,
+    matched_federation_id TEXT,
+    matched_log_id BIGINT,
+    matched_gateway_epoch INTEGER,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS selftest (
+    id BIGSERIAL,
+    ts TIMESTAMP NOT NULL,
+    etl_run_id BIGINT NOT NULL,
+    federation_id TEXT NOT NULL,
+    passed BOOLEAN NOT NULL,
+    duration_ms DOUBLE PRECISION NOT NULL,
+    error TEXT,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS slo_evaluations (
+    ts TIMESTAMP NOT NULL,
+    slo TEXT NOT NULL,
+    window_start TIMESTAMP NOT NULL,
+    window_end TIMESTAMP NOT NULL,
+    total BIGINT NOT NULL,
+    bad BIGINT NOT NULL,
+    burn_rate DOUBLE PRECISION NOT NULL,
+    budget_remaining DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (ts, slo)
+);
+
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id BIGSERIAL,
+    created_at TIMESTAMP NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    federation_id TEXT NOT NULL,
+    log_id BIGINT NOT NULL,
+    event JSONB,
+    event_zstd BYTEA,
+    attempts INTEGER NOT NULL,
+    error TEXT NOT NULL,
+    delivered_at TIMESTAMP,
+    CHECK (event IS NOT NULL OR event_zstd IS NOT NULL),
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS staging.gateway_events (
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    module TEXT,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    _loaded_at TIMESTAMP NOT NULL,
+    _etl_run_id BIGINT,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
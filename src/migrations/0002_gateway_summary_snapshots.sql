@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS gateway_summary_snapshots (
+    etl_run_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    window_start TIMESTAMP NOT NULL,
+    window_end TIMESTAMP NOT NULL,
+    direction TEXT NOT NULL,
+    average_latency_ms DOUBLE PRECISION,
+    median_latency_ms DOUBLE PRECISION,
+    total_fees_msat BIGINT NOT NULL,
+    total_success BIGINT NOT NULL,
+    total_failure BIGINT NOT NULL,
+    PRIMARY KEY (etl_run_id, direction)
+);
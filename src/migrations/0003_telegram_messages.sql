@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS telegram_messages (
+    id BIGSERIAL PRIMARY KEY,
+    ts TIMESTAMP NOT NULL,
+    chat_id TEXT NOT NULL,
+    preview TEXT NOT NULL,
+    sent BOOLEAN NOT NULL,
+    digested_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS telegram_messages_ts ON telegram_messages (ts) WHERE sent;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS table_sizes (
+    ts TIMESTAMP NOT NULL,
+    table_name TEXT NOT NULL,
+    total_bytes BIGINT NOT NULL,
+    PRIMARY KEY (ts, table_name)
+);
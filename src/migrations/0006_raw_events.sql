@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS raw_events (
+    log_id BIGINT NOT NULL,
+    ts TIMESTAMP NOT NULL,
+    federation_id TEXT NOT NULL,
+    federation_name TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    module TEXT,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    PRIMARY KEY (log_id, federation_id, gateway_epoch)
+);
@@ -0,0 +1,4 @@
+-- Events with a timestamp out of range are archived and dead-lettered
+-- without one instead of aborting the run
+ALTER TABLE dead_letter_events ALTER COLUMN ts DROP NOT NULL;
+ALTER TABLE raw_events ALTER COLUMN ts DROP NOT NULL;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS paused_federations (
+    federation_id TEXT PRIMARY KEY,
+    reason TEXT,
+    paused_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS rebalance_payments (
+    payment_hash TEXT PRIMARY KEY,
+    note TEXT,
+    tagged_at TIMESTAMP NOT NULL
+);
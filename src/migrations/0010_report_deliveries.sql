@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS report_deliveries (
+    destination TEXT PRIMARY KEY,
+    sent_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS ingestion_watermarks (
+    federation_id TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    watermark TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (federation_id, gateway_epoch)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS checked_gaps (
+    federation_id TEXT NOT NULL,
+    gateway_epoch INTEGER NOT NULL,
+    first_log_id BIGINT NOT NULL,
+    last_log_id BIGINT NOT NULL,
+    checked_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (federation_id, gateway_epoch, first_log_id)
+);
@@ -0,0 +1,43 @@
+-- Log ids are per federation, so the payment tables of databases created
+-- from ddl.sql before the key included the federation id can still be keyed
+-- by (log_id, gateway_epoch). Their ON CONFLICT DO NOTHING inserts then drop
+-- the events of every federation but the first with the same log id.
+DO $$
+DECLARE
+    event_table TEXT;
+    constraint_name TEXT;
+    key_columns TEXT[];
+BEGIN
+    FOREACH event_table IN ARRAY ARRAY[
+        'lnv1_outgoing_payment_started',
+        'lnv1_outgoing_payment_succeeded',
+        'lnv1_outgoing_payment_failed',
+        'lnv1_incoming_payment_started',
+        'lnv1_incoming_payment_succeeded',
+        'lnv1_incoming_payment_failed',
+        'lnv1_complete_lightning_payment_succeeded',
+        'lnv2_outgoing_payment_started',
+        'lnv2_outgoing_payment_succeeded',
+        'lnv2_outgoing_payment_failed',
+        'lnv2_incoming_payment_started',
+        'lnv2_incoming_payment_succeeded',
+        'lnv2_incoming_payment_failed',
+        'lnv2_complete_lightning_payment_succeeded'
+    ] LOOP
+        SELECT c.conname, array_agg(a.attname::TEXT ORDER BY k.ord)
+        INTO constraint_name, key_columns
+        FROM pg_constraint c
+        CROSS JOIN unnest(c.conkey) WITH ORDINALITY AS k(attnum, ord)
+        JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum
+        WHERE c.conrelid = to_regclass(quote_ident(event_table)) AND c.contype = 'p'
+        GROUP BY c.conname;
+
+        IF key_columns IS DISTINCT FROM ARRAY['log_id', 'federation_id', 'gateway_epoch'] THEN
+            IF constraint_name IS NOT NULL THEN
+                EXECUTE format('ALTER TABLE %I DROP CONSTRAINT %I', event_table, constraint_name);
+            END IF;
+            EXECUTE format('ALTER TABLE %I ADD PRIMARY KEY (log_id, federation_id, gateway_epoch)', event_table);
+        END IF;
+    END LOOP;
+END
+$$;
@@ -0,0 +1,4 @@
+-- Payloads archived with `--archive-encoding cbor-zstd` are stored compact
+-- instead of as JSONB
+ALTER TABLE raw_events ADD COLUMN IF NOT EXISTS payload_cbor_zstd BYTEA;
+ALTER TABLE raw_events ALTER COLUMN payload DROP NOT NULL;
@@ -0,0 +1,7 @@
+-- The last sequence number given to a message of each output, e.g. the
+-- webhook, so its consumers can tell whether they missed one
+CREATE TABLE IF NOT EXISTS output_sequences (
+    output TEXT NOT NULL,
+    last_sequence BIGINT NOT NULL,
+    PRIMARY KEY (output)
+);
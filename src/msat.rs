@@ -0,0 +1,152 @@
+use std::{error::Error, fmt};
+
+use fedimint_core::bitcoin;
+use tokio_postgres::types::{FromSql, Type};
+
+/// An amount of millisatoshis in aggregation code. Sums are accumulated in
+/// 128 bits, so adding up `BIGINT` columns over long windows can't overflow,
+/// and unlike a bare integer it can't be mixed up with sats.
+///
+/// Read sums as `SUM(amount_msat)::TEXT`: Postgres sums `BIGINT` into a
+/// `NUMERIC`, which would fail to cast back to `BIGINT` once it exceeds the
+/// range. Plain `BIGINT` columns are accepted as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Msat(i128);
+
+impl Msat {
+    pub fn msats(self) -> i128 {
+        self.0
+    }
+
+    pub fn checked_sub(self, other: Msat) -> Option<Msat> {
+        self.0.checked_sub(other.0).map(Msat)
+    }
+
+    /// `self` relative to `base` in percent, `None` if `base` is zero.
+    pub fn percent_of(self, base: Msat) -> Option<f64> {
+        (base.0 != 0).then(|| self.0 as f64 * 100.0 / base.0 as f64)
+    }
+
+    /// Whole sats, negative amounts are clamped to zero.
+    pub fn to_bitcoin_amount(self) -> bitcoin::Amount {
+        bitcoin::Amount::from_sat(u64::try_from(self.0.max(0) / 1000).unwrap_or(u64::MAX))
+    }
+}
+
+impl From<i64> for Msat {
+    fn from(msat: i64) -> Self {
+        Msat(msat.into())
+    }
+}
+
+impl fmt::Display for Msat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} msat", self.0)
+    }
+}
+
+impl<'a> FromSql<'a> for Msat {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if *ty == Type::INT8 {
+            return Ok(i64::from_sql(ty, raw)?.into());
+        }
+        Ok(Msat(<&str>::from_sql(ty, raw)?.parse()?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::INT8 | Type::TEXT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::bitcoin;
+    use tokio_postgres::types::{FromSql, Type};
+
+    use super::Msat;
+
+    fn from_text(sum: &str) -> Msat {
+        Msat::from_sql(&Type::TEXT, sum.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_bigint_columns() {
+        for msat in [0, 1, -1, i64::MAX, i64::MIN] {
+            let msat_from_sql = Msat::from_sql(&Type::INT8, &msat.to_be_bytes()).unwrap();
+            assert_eq!(msat_from_sql, Msat::from(msat));
+        }
+    }
+
+    #[test]
+    fn reads_sums_beyond_bigint() {
+        let above_i64 = from_text("9223372036854775808");
+        assert_eq!(above_i64.msats(), i128::from(i64::MAX) + 1);
+        assert!(above_i64 > Msat::from(i64::MAX));
+
+        let u64_max = from_text(&u64::MAX.to_string());
+        assert_eq!(u64_max.msats(), i128::from(u64::MAX));
+        assert_eq!(
+            from_text("-9223372036854775809").msats(),
+            i128::from(i64::MIN) - 1
+        );
+    }
+
+    #[test]
+    fn rejects_sums_that_are_not_integers() {
+        assert!(Msat::from_sql(&Type::TEXT, b"12.5").is_err());
+        assert!(Msat::from_sql(&Type::TEXT, b"").is_err());
+    }
+
+    #[test]
+    fn bitcoin_amount_is_whole_sats() {
+        assert_eq!(Msat::from(0).to_bitcoin_amount(), bitcoin::Amount::ZERO);
+        assert_eq!(Msat::from(999).to_bitcoin_amount(), bitcoin::Amount::ZERO);
+        assert_eq!(
+            Msat::from(1_999).to_bitcoin_amount(),
+            bitcoin::Amount::from_sat(1)
+        );
+        assert_eq!(
+            Msat::from(i64::MAX).to_bitcoin_amount(),
+            bitcoin::Amount::from_sat(i64::MAX as u64 / 1000)
+        );
+    }
+
+    #[test]
+    fn negative_fee_is_zero_sats() {
+        let fee = Msat::from(1_000).checked_sub(Msat::from(5_000)).unwrap();
+        assert_eq!(fee.msats(), -4_000);
+        assert_eq!(fee.to_bitcoin_amount(), bitcoin::Amount::ZERO);
+        assert_eq!(
+            Msat::from(i64::MIN).to_bitcoin_amount(),
+            bitcoin::Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn bitcoin_amount_saturates_above_u64() {
+        let sats_above_u64 = from_text(&(i128::from(u64::MAX) * 1000 + 1000).to_string());
+        assert_eq!(sats_above_u64.to_bitcoin_amount().to_sat(), u64::MAX);
+        assert_eq!(Msat(i128::MAX).to_bitcoin_amount().to_sat(), u64::MAX);
+    }
+
+    #[test]
+    fn checked_sub_overflows_to_none() {
+        assert_eq!(
+            Msat::from(i64::MIN).checked_sub(Msat::from(i64::MAX)),
+            Some(Msat(i128::from(i64::MIN) - i128::from(i64::MAX)))
+        );
+        assert_eq!(Msat(i128::MIN).checked_sub(Msat::from(1)), None);
+        assert_eq!(Msat(i128::MAX).checked_sub(Msat::from(-1)), None);
+    }
+
+    #[test]
+    fn percent_of() {
+        assert_eq!(Msat::from(5).percent_of(Msat::from(0)), None);
+        assert_eq!(Msat::from(0).percent_of(Msat::from(5)), Some(0.0));
+        assert_eq!(Msat::from(-50).percent_of(Msat::from(200)), Some(-25.0));
+        assert_eq!(
+            Msat::from(i64::MAX).percent_of(Msat::from(i64::MAX)),
+            Some(100.0)
+        );
+    }
+}
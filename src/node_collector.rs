@@ -0,0 +1,345 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use serde_json::{Value, json};
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{
+    payments::PAYMENTS_QUERY,
+    reconciliation::{self, ReconciliationRecord},
+};
+
+/// Maximum number of payments or invoices requested from LND per page.
+const LND_PAGE_SIZE: usize = 1000;
+
+/// The Lightning node implementation behind the gateway.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum NodeKind {
+    Lnd,
+    Cln,
+}
+
+impl NodeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            NodeKind::Lnd => "lnd",
+            NodeKind::Cln => "cln",
+        }
+    }
+}
+
+/// A payment as seen by the Lightning node, independent of which federation
+/// (if any) the gateway routed it for.
+#[derive(Debug, Clone)]
+pub(crate) struct NodePayment {
+    direction: &'static str,
+    payment_hash: String,
+    amount_msat: i64,
+    fee_msat: i64,
+    ts: DateTime<Utc>,
+    status: String,
+}
+
+impl NodePayment {
+    fn succeeded(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "SUCCEEDED" | "SETTLED" | "complete" | "paid"
+        )
+    }
+
+    async fn insert(&self, pg_client: &Client, node: NodeKind) -> anyhow::Result<()> {
+        pg_client.execute("INSERT INTO node_payments (node, direction, payment_hash, ts, amount_msat, fee_msat, status, collected_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (node, direction, payment_hash) DO UPDATE SET ts = EXCLUDED.ts, amount_msat = EXCLUDED.amount_msat, fee_msat = EXCLUDED.fee_msat, status = EXCLUDED.status, collected_at = EXCLUDED.collected_at",
+        &[&node.as_str(), &self.direction, &self.payment_hash, &self.ts.naive_utc(), &self.amount_msat, &self.fee_msat, &self.status, &Utc::now().naive_utc()]).await?;
+        Ok(())
+    }
+}
+
+/// Talks to the REST interface of the gateway's Lightning node: LND's REST
+/// proxy (authenticated with a hex encoded macaroon) or CLN's `clnrest`
+/// plugin (authenticated with a rune).
+pub(crate) struct NodeClient {
+    kind: NodeKind,
+    url: String,
+    credential: String,
+    client: reqwest::Client,
+}
+
+impl NodeClient {
+    pub fn new(
+        kind: NodeKind,
+        url: String,
+        credential: String,
+        insecure_tls: bool,
+    ) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(insecure_tls)
+            .build()?;
+        Ok(Self {
+            kind,
+            url: url.trim_end_matches('/').to_string(),
+            credential,
+            client,
+        })
+    }
+
+    /// Fetches all outgoing payments and incoming invoices created since
+    /// `since`.
+    pub async fn fetch(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<NodePayment>> {
+        match self.kind {
+            NodeKind::Lnd => self.fetch_lnd(since).await,
+            NodeKind::Cln => self.fetch_cln(since).await,
+        }
+    }
+
+    async fn lnd_get(&self, path: &str, query: &[(&str, String)]) -> anyhow::Result<Value> {
+        Ok(self
+            .client
+            .get(format!("{}{path}", self.url))
+            .header("Grpc-Metadata-macaroon", &self.credential)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn fetch_lnd(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<NodePayment>> {
+        let mut node_payments = Vec::new();
+
+        let mut index_offset = 0;
+        loop {
+            let page = self
+                .lnd_get(
+                    "/v1/payments",
+                    &[
+                        ("creation_date_start", since.timestamp().to_string()),
+                        ("index_offset", index_offset.to_string()),
+                        ("max_payments", LND_PAGE_SIZE.to_string()),
+                    ],
+                )
+                .await?;
+            let payments = page["payments"].as_array().cloned().unwrap_or_default();
+            for payment in &payments {
+                node_payments.push(NodePayment {
+                    direction: "outgoing",
+                    payment_hash: string(payment, "payment_hash")?,
+                    amount_msat: integer(payment, "value_msat")?,
+                    fee_msat: integer(payment, "fee_msat")?,
+                    ts: DateTime::from_timestamp_nanos(integer(payment, "creation_time_ns")?),
+                    status: string(payment, "status")?,
+                });
+            }
+            if payments.len() < LND_PAGE_SIZE {
+                break;
+            }
+            index_offset = integer(&page, "last_index_offset")?;
+        }
+
+        let mut index_offset = 0;
+        loop {
+            let page = self
+                .lnd_get(
+                    "/v1/invoices",
+                    &[
+                        ("creation_date_start", since.timestamp().to_string()),
+                        ("index_offset", index_offset.to_string()),
+                        ("num_max_invoices", LND_PAGE_SIZE.to_string()),
+                    ],
+                )
+                .await?;
+            let invoices = page["invoices"].as_array().cloned().unwrap_or_default();
+            for invoice in &invoices {
+                // LND returns the payment hash of invoices base64 encoded
+                let r_hash =
+                    base64::engine::general_purpose::STANDARD.decode(string(invoice, "r_hash")?)?;
+                node_payments.push(NodePayment {
+                    direction: "incoming",
+                    payment_hash: r_hash.iter().map(|b| format!("{b:02x}")).collect(),
+                    amount_msat: integer(invoice, "amt_paid_msat")?,
+                    fee_msat: 0,
+                    ts: timestamp(integer(invoice, "settle_date")?)
+                        .or(timestamp(integer(invoice, "creation_date")?))
+                        .unwrap_or(since),
+                    status: string(invoice, "state")?,
+                });
+            }
+            if invoices.len() < LND_PAGE_SIZE {
+                break;
+            }
+            index_offset = integer(&page, "last_index_offset")?;
+        }
+
+        Ok(node_payments)
+    }
+
+    async fn cln_post(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        Ok(self
+            .client
+            .post(format!("{}/v1/{method}", self.url))
+            .header("Rune", &self.credential)
+            .json(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn fetch_cln(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<NodePayment>> {
+        let mut node_payments = Vec::new();
+
+        let pays = self.cln_post("listpays", json!({})).await?;
+        for pay in pays["pays"].as_array().into_iter().flatten() {
+            let Some(ts) = timestamp(integer(pay, "created_at")?) else {
+                continue;
+            };
+            if ts < since {
+                continue;
+            }
+            // Amounts are only known once the payment completed
+            let amount_msat = integer(pay, "amount_msat").unwrap_or_default();
+            let amount_sent_msat = integer(pay, "amount_sent_msat").unwrap_or(amount_msat);
+            node_payments.push(NodePayment {
+                direction: "outgoing",
+                payment_hash: string(pay, "payment_hash")?,
+                amount_msat,
+                fee_msat: amount_sent_msat - amount_msat,
+                ts,
+                status: string(pay, "status")?,
+            });
+        }
+
+        let invoices = self.cln_post("listinvoices", json!({})).await?;
+        for invoice in invoices["invoices"].as_array().into_iter().flatten() {
+            let status = string(invoice, "status")?;
+            if status != "paid" {
+                continue;
+            }
+            let Some(ts) = timestamp(integer(invoice, "paid_at")?) else {
+                continue;
+            };
+            if ts < since {
+                continue;
+            }
+            node_payments.push(NodePayment {
+                direction: "incoming",
+                payment_hash: string(invoice, "payment_hash")?,
+                amount_msat: integer(invoice, "amount_received_msat")?,
+                fee_msat: 0,
+                ts,
+                status,
+            });
+        }
+
+        Ok(node_payments)
+    }
+}
+
+/// Collects the node's payment history since `since` into `node_payments`
+/// and reconciles the succeeded payments against the warehouse.
+pub(crate) async fn collect(
+    pool: &Pool,
+    node_client: &NodeClient,
+    since: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let node_payments = node_client.fetch(since).await?;
+    let pg_client = pool.get().await?;
+    for node_payment in &node_payments {
+        node_payment.insert(&pg_client, node_client.kind).await?;
+    }
+    info!(
+        node = node_client.kind.as_str(),
+        payments = node_payments.len(),
+        %since,
+        "Collected node payment history"
+    );
+
+    record_latency_splits(&pg_client).await?;
+
+    let records = node_payments
+        .iter()
+        .filter(|node_payment| node_payment.succeeded())
+        .map(|node_payment| ReconciliationRecord {
+            ts: node_payment.ts,
+            payment_hash: node_payment.payment_hash.clone(),
+            amount_msat: node_payment.amount_msat,
+            fee_msat: Some(node_payment.fee_msat),
+            external_id: None,
+        })
+        .collect::<Vec<_>>();
+    reconciliation::reconcile(pool, &records, node_client.kind.as_str().to_string()).await
+}
+
+fn string(value: &Value, field: &str) -> anyhow::Result<String> {
+    value[field]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Missing field {field}"))
+}
+
+/// Reads an integer that is either a JSON number, a decimal string (LND
+/// encodes 64 bit integers as strings) or a legacy CLN `msat` string.
+fn integer(value: &Value, field: &str) -> anyhow::Result<i64> {
+    match &value[field] {
+        Value::Number(number) => number
+            .as_i64()
+            .ok_or_else(|| anyhow::anyhow!("Field {field} is out of range")),
+        Value::String(s) => Ok(s.trim_end_matches("msat").parse()?),
+        _ => Err(anyhow::anyhow!("Missing field {field}")),
+    }
+}
+
+/// Converts unix seconds to a timestamp, treating 0 as unset.
+fn timestamp(secs: i64) -> Option<DateTime<Utc>> {
+    if secs == 0 {
+        return None;
+    }
+    DateTime::from_timestamp(secs, 0)
+}
+
+/// Splits the latency of succeeded outgoing payments at the moment the node
+/// started paying: before it is time spent in the gateway and on federation
+/// consensus for the contract, after it is time on the Lightning network
+/// until the gateway logged the outcome. Only payments whose node payment
+/// started between the gateway's started and terminal events are split.
+async fn record_latency_splits(pg_client: &Client) -> anyhow::Result<()> {
+    let row = pg_client
+        .query_one(
+            &format!(
+                "
+                WITH payments AS ({PAYMENTS_QUERY}),
+                splits AS (
+                    INSERT INTO payment_latency_splits (terminal_log_id, federation_id, federation_name, gateway_epoch, payment_hash, started_ts, lightning_started_ts, completed_ts, gateway_ms, lightning_ms)
+                    SELECT p.log_id, p.federation_id, p.federation_name, p.gateway_epoch, p.payment_hash, p.started_ts, n.ts, p.ts,
+                        EXTRACT(EPOCH FROM n.ts - p.started_ts) * 1000,
+                        EXTRACT(EPOCH FROM p.ts - n.ts) * 1000
+                    FROM payments p
+                    JOIN node_payments n ON n.payment_hash = p.payment_hash AND n.direction = 'outgoing'
+                    WHERE p.direction = 'outgoing' AND p.outcome = 'succeeded'
+                        AND n.ts BETWEEN p.started_ts AND p.ts
+                    ON CONFLICT (terminal_log_id, federation_id, gateway_epoch) DO UPDATE SET
+                        lightning_started_ts = EXCLUDED.lightning_started_ts,
+                        gateway_ms = EXCLUDED.gateway_ms,
+                        lightning_ms = EXCLUDED.lightning_ms
+                    RETURNING gateway_ms, lightning_ms
+                )
+                SELECT COUNT(*), COALESCE(AVG(gateway_ms), 0)::FLOAT8, COALESCE(AVG(lightning_ms), 0)::FLOAT8 FROM splits
+                "
+            ),
+            &[],
+        )
+        .await?;
+    let payments: i64 = row.get(0);
+    let gateway_ms: f64 = row.get(1);
+    let lightning_ms: f64 = row.get(2);
+    info!(
+        payments,
+        gateway_ms, lightning_ms, "Recorded payment latency splits"
+    );
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use clap::ValueEnum;
+use rand::Rng;
+
+/// Counts below this are where a single payment stands out.
+const SMALL_COUNT: u64 = 10;
+
+/// How counts are blurred in summaries shared outside the gateway operator,
+/// so the figures of a small federation don't reveal single payments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub(crate) enum StatsNoise {
+    /// Exact counts
+    #[default]
+    None,
+    /// Counts below 10 are shown as `<10`, larger ones rounded to the nearest
+    /// 10
+    Buckets,
+    /// Counts below 10 are replaced with a random one half of the time
+    /// (randomized response), larger ones rounded to the nearest 10
+    Randomized,
+}
+
+impl StatsNoise {
+    pub fn apply(&self, count: u64) -> String {
+        match self {
+            StatsNoise::None => count.to_string(),
+            StatsNoise::Buckets if count < SMALL_COUNT => format!("<{SMALL_COUNT}"),
+            StatsNoise::Randomized if count < SMALL_COUNT => {
+                let mut rng = rand::thread_rng();
+                if rng.gen_bool(0.5) {
+                    count.to_string()
+                } else {
+                    rng.gen_range(0..SMALL_COUNT).to_string()
+                }
+            }
+            StatsNoise::Buckets | StatsNoise::Randomized => {
+                let rounded = (count + SMALL_COUNT / 2) / SMALL_COUNT * SMALL_COUNT;
+                format!("~{rounded}")
+            }
+        }
+    }
+}
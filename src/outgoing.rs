@@ -1,4 +1,3 @@
-use chrono::DateTime;
 use fedimint_core::{anyhow, config::FederationId};
 use fedimint_eventlog::EventLogId;
 use serde::{Deserialize, de};
@@ -6,7 +5,7 @@ use serde_json::Value;
 use tokio_postgres::Client;
 use tracing::info;
 
-use crate::parse_log_id;
+use crate::{dead_letter, parse_log_id};
 
 #[derive(Debug, Clone)]
 pub(crate) struct LNv2OutgoingPaymentStarted {
@@ -63,14 +62,10 @@ impl LNv2OutgoingPaymentStarted {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        let operation_start = DateTime::from_timestamp_micros(self.operation_start)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_outgoing_payment_started (log_id, ts, federation_id, federation_name, gateway_epoch, invoice_amount, max_delay, min_contract_amount, operation_start, amount, claim_pk, ephemeral_pk, expiration, payment_image, refund_pk) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        let operation_start = dead_letter::timestamp(self.operation_start)?;
+        pg_client.execute("INSERT INTO lnv2_outgoing_payment_started (log_id, ts, federation_id, federation_name, gateway_epoch, invoice_amount, max_delay, min_contract_amount, operation_start, amount, claim_pk, ephemeral_pk, expiration, payment_image, refund_pk) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) ON CONFLICT DO NOTHING",
         &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.invoice_amount, &self.max_delay, &self.min_contract_amount, &operation_start, &self.outgoing_contract.amount, &self.outgoing_contract.claim_pk, &self.outgoing_contract.ephemeral_pk, &self.outgoing_contract.expiration, &self.outgoing_contract.payment_image.hash, &self.outgoing_contract.refund_pk]).await?;
         Ok(())
     }
@@ -190,12 +185,10 @@ impl LNv1OutgoingPaymentStarted {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_outgoing_payment_started (log_id, ts, federation_id, federation_name, contract_id, invoice_amount, operation_id, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.contract_id, &(self.amount as i64), &self.operation_id, &gateway_epoch]).await?;
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_outgoing_payment_started (log_id, ts, federation_id, federation_name, contract_id, invoice_amount, operation_id, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.contract_id, &self.amount, &self.operation_id, &gateway_epoch]).await?;
         Ok(())
     }
 }
@@ -220,29 +213,29 @@ impl<'de> Deserialize<'de> for LNv1OutgoingPaymentSucceeded {
 
         let contract_id = value["contract_id"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("contract_id"))?
             .to_string();
         let contract_amount = value["outgoing_contract"]["amount"]
             .as_i64()
-            .expect("contract amount should be present");
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.amount"))?;
         let gateway_key = value["outgoing_contract"]["contract"]["gateway_key"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.gateway_key"))?
             .to_string();
         let payment_hash = value["outgoing_contract"]["contract"]["hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.hash"))?
             .to_string();
         let timelock = value["outgoing_contract"]["contract"]["timelock"]
             .as_i64()
-            .expect("Should be present");
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.timelock"))?;
         let user_key = value["outgoing_contract"]["contract"]["user_key"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.user_key"))?
             .to_string();
         let preimage = value["preimage"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("preimage"))?
             .to_string();
 
         Ok(LNv1OutgoingPaymentSucceeded {
@@ -267,11 +260,9 @@ impl LNv1OutgoingPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_outgoing_payment_succeeded (log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, preimage, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)", 
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_outgoing_payment_succeeded (log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, preimage, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) ON CONFLICT DO NOTHING", 
         &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.contract_id, &self.contract_amount, &self.gateway_key, &self.payment_hash, &self.timelock, &self.user_key, &self.preimage, &gateway_epoch]).await?;
         Ok(())
     }
@@ -313,11 +304,9 @@ impl LNv2OutgoingPaymentSucceeded {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_outgoing_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, target_federation) VALUES ($1, $2, $3, $4, $5, $6, $7)", 
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_outgoing_payment_succeeded (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, target_federation) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING", 
         &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash, &self.target_federation]).await?;
         Ok(())
     }
@@ -343,28 +332,28 @@ impl<'de> Deserialize<'de> for LNv1OutgoingPaymentFailed {
 
         let contract_id = value["contract_id"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("contract_id"))?
             .to_string();
         let contract_amount = value["outgoing_contract"]["amount"]
             .as_i64()
-            .expect("contract amount should be present");
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.amount"))?;
         let gateway_key = value["outgoing_contract"]["contract"]["gateway_key"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.gateway_key"))?
             .to_string();
         let payment_hash = value["outgoing_contract"]["contract"]["hash"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.hash"))?
             .to_string();
         let timelock = value["outgoing_contract"]["contract"]["timelock"]
             .as_i64()
-            .expect("Should be present");
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.timelock"))?;
         let user_key = value["outgoing_contract"]["contract"]["user_key"]
             .as_str()
-            .expect("Should be present")
+            .ok_or_else(|| de::Error::missing_field("outgoing_contract.contract.user_key"))?
             .to_string();
         let error_reason = LNv1OutgoingPaymentFailed::extract_error_reason(value)
-            .expect("Could not get error_reason");
+            .map_err(|e| de::Error::custom(e.to_string()))?;
 
         Ok(LNv1OutgoingPaymentFailed {
             contract_id,
@@ -381,32 +370,29 @@ impl<'de> Deserialize<'de> for LNv1OutgoingPaymentFailed {
 impl LNv1OutgoingPaymentFailed {
     fn extract_error_reason(data: Value) -> anyhow::Result<Option<String>> {
         // Check for the 'error_type' key and handle different types of errors
-        if let Some(error) = data.get("error") {
-            if let Some(error_type) = error.get("error_type") {
-                if let Some(lightning_error) = error_type.get("LightningPayError") {
-                    if let Some(failed_payment) = lightning_error.get("lightning_error") {
-                        if let Some(failure_reason) = failed_payment
-                            .get("FailedPayment")
-                            .and_then(|e| e.get("failure_reason"))
-                        {
-                            return Ok(Some(
-                                failure_reason.as_str().unwrap_or_default().to_string(),
-                            ));
-                        }
-                    }
-                } else if let Some(invalid_outgoing_contract) =
-                    error_type.get("InvalidOutgoingContract")
+        if let Some(error) = data.get("error")
+            && let Some(error_type) = error.get("error_type")
+        {
+            if let Some(lightning_error) = error_type.get("LightningPayError") {
+                if let Some(failed_payment) = lightning_error.get("lightning_error")
+                    && let Some(failure_reason) = failed_payment
+                        .get("FailedPayment")
+                        .and_then(|e| e.get("failure_reason"))
                 {
-                    if let Some(invoice_expired) = invalid_outgoing_contract
-                        .get("error")
-                        .and_then(|e| e.get("InvoiceExpired"))
-                    {
-                        return Ok(Some(format!(
-                            "Invoice expired: {}",
-                            invoice_expired.as_i64().unwrap_or_default()
-                        )));
-                    }
+                    return Ok(Some(
+                        failure_reason.as_str().unwrap_or_default().to_string(),
+                    ));
                 }
+            } else if let Some(invalid_outgoing_contract) =
+                error_type.get("InvalidOutgoingContract")
+                && let Some(invoice_expired) = invalid_outgoing_contract
+                    .get("error")
+                    .and_then(|e| e.get("InvoiceExpired"))
+            {
+                return Ok(Some(format!(
+                    "Invoice expired: {}",
+                    invoice_expired.as_i64().unwrap_or_default()
+                )));
             }
         }
 
@@ -425,11 +411,9 @@ impl LNv1OutgoingPaymentFailed {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv1_outgoing_payment_failed (log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, error_reason, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)", 
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_outgoing_payment_failed (log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, error_reason, gateway_epoch) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) ON CONFLICT DO NOTHING", 
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &self.contract_id, &self.contract_amount, &self.gateway_key, &self.payment_hash, &self.timelock, &self.user_key, &self.error_reason, &gateway_epoch]).await?;
         Ok(())
     }
@@ -473,11 +457,9 @@ impl LNv2OutgoingPaymentFailed {
         federation_name: String,
         gateway_epoch: i32,
     ) -> anyhow::Result<()> {
-        let log_id = parse_log_id(&log_id);
-        let timestamp = DateTime::from_timestamp_micros(timestamp as i64)
-            .expect("Should convert DateTime correctly")
-            .naive_utc();
-        pg_client.execute("INSERT INTO lnv2_outgoing_payment_failed (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, error) VALUES ($1, $2, $3, $4, $5, $6, $7)", 
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_outgoing_payment_failed (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, error) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING", 
     &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash, &self.error]).await?;
         Ok(())
     }
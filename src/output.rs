@@ -0,0 +1,121 @@
+use std::io::Write;
+
+use clap::{Args, ValueEnum};
+use fedimint_core::anyhow;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// An array with one object per row
+    Json,
+    /// A header line and one line per row
+    Csv,
+}
+
+#[derive(Args, Debug, Clone)]
+pub(crate) struct OutputOpts {
+    /// How the results are printed
+    #[arg(long = "output", value_enum, default_value = "table")]
+    pub output: OutputFormat,
+}
+
+/// Rows a subcommand prints, rendered in the format picked with `--output`
+/// so every subcommand can be read by people and by scripts alike. Cells are
+/// JSON values so numbers stay numbers in the JSON output.
+pub(crate) struct Rows {
+    columns: &'static [&'static str],
+    rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    /// `columns` are the JSON keys and, upper cased, the table header.
+    pub fn new(columns: &'static [&'static str]) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    pub fn print(&self, format: OutputFormat) -> anyhow::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        match format {
+            OutputFormat::Table => self.write_table(&mut stdout)?,
+            OutputFormat::Json => {
+                let objects = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        Value::Object(
+                            self.columns
+                                .iter()
+                                .map(|column| column.to_string())
+                                .zip(row.iter().cloned())
+                                .collect::<Map<_, _>>(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut stdout, &objects)?;
+                writeln!(stdout)?;
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(stdout);
+                writer.write_record(self.columns)?;
+                for row in &self.rows {
+                    writer.write_record(row.iter().map(cell))?;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_table(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        let header = self
+            .columns
+            .iter()
+            .map(|column| column.to_uppercase())
+            .collect::<Vec<_>>();
+        let cells = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(cell).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let widths = header
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([title.len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        for line in [header].iter().chain(&cells) {
+            let padded = line
+                .iter()
+                .zip(&widths)
+                .map(|(text, width)| format!("{text:<width$}"))
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", padded.join(" ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Strings without the quotes JSON would add, everything else as in JSON.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
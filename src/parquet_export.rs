@@ -0,0 +1,342 @@
+use std::{collections::BTreeMap, fs, sync::Arc};
+
+use arrow_array::{
+    ArrayRef, RecordBatch,
+    builder::{
+        BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder,
+        TimestampMicrosecondBuilder,
+    },
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{NaiveDate, NaiveDateTime};
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use futures_util::{TryStreamExt, pin_mut};
+use object_store::{
+    ObjectStore, PutPayload, aws::AmazonS3Builder, local::LocalFileSystem, path::Path,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use tokio_postgres::{Client, Row, types::ToSql};
+use tracing::info;
+use url::Url;
+
+use crate::export;
+
+/// Rows per record batch, bounds what is buffered in the column builders.
+const BATCH_ROWS: usize = 10_000;
+
+/// Partition of the rows without a timestamp, the name Hive and Athena read
+/// as NULL.
+const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Writes the event tables as Parquet files partitioned by table and day
+/// (`<table>/date=<YYYY-MM-DD>/part-0.parquet`) to a directory or an
+/// `s3://bucket/prefix` URL, for archival and for querying with DuckDB or
+/// Athena. Only whole days are exported, so exporting a range again replaces
+/// its files instead of adding to them. S3 credentials and the region are
+/// taken from the usual `AWS_*` environment variables.
+pub(crate) async fn export(
+    pool: &Pool,
+    tables: &[String],
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    output: &str,
+) -> anyhow::Result<()> {
+    let tables = export::selected_event_tables(tables)?;
+    let (store, prefix) = open_output(output)?;
+    let pg_client = pool.get().await?;
+
+    let mut file_count = 0;
+    for (table, columns) in tables {
+        file_count += export_table(
+            &pg_client,
+            store.as_ref(),
+            &prefix,
+            table,
+            columns,
+            since,
+            until,
+        )
+        .await?;
+    }
+
+    println!("Exported {file_count} Parquet files to {output}");
+    Ok(())
+}
+
+fn open_output(output: &str) -> anyhow::Result<(Box<dyn ObjectStore>, Path)> {
+    if output.starts_with("s3://") {
+        let url = Url::parse(output)?;
+        let store = AmazonS3Builder::from_env().with_url(output).build()?;
+        Ok((Box::new(store), Path::from(url.path().trim_matches('/'))))
+    } else {
+        fs::create_dir_all(output)?;
+        Ok((
+            Box::new(LocalFileSystem::new_with_prefix(output)?),
+            Path::default(),
+        ))
+    }
+}
+
+/// Streams the rows of `table` in timestamp order, writing one file per day.
+/// Returns the number of files written.
+async fn export_table(
+    pg_client: &Client,
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    table: &str,
+    columns: &[&str],
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> anyhow::Result<usize> {
+    let data_types: BTreeMap<String, String> = pg_client
+        .query(
+            "SELECT column_name, data_type FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+            &[&table],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    let kinds = columns
+        .iter()
+        .map(|column| {
+            data_types
+                .get(*column)
+                .map_or(ColumnKind::Text, |data_type| ColumnKind::from_pg(data_type))
+        })
+        .collect::<Vec<_>>();
+    let schema = Arc::new(Schema::new(
+        columns
+            .iter()
+            .zip(&kinds)
+            .map(|(column, kind)| Field::new(*column, kind.arrow_type(), true))
+            .collect::<Vec<_>>(),
+    ));
+    let ts_index = columns
+        .iter()
+        .position(|column| *column == "ts")
+        .expect("Event tables have a ts column");
+
+    let select = columns
+        .iter()
+        .zip(&kinds)
+        .map(|(column, kind)| kind.select(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query = format!(
+        "SELECT {select} FROM {table} WHERE ($1::DATE IS NULL OR ts >= $1) AND ($2::DATE IS NULL OR ts < $2) ORDER BY ts, log_id"
+    );
+    let params: [&(dyn ToSql + Sync); 2] = [&since, &until];
+    let rows = pg_client.query_raw(&query, params).await?;
+    pin_mut!(rows);
+
+    let mut partition: Option<Partition> = None;
+    let mut file_count = 0;
+    while let Some(row) = rows.try_next().await? {
+        let date = row
+            .get::<_, Option<NaiveDateTime>>(ts_index)
+            .map(|ts| ts.date());
+        if partition
+            .as_ref()
+            .is_some_and(|current| current.date != date)
+        {
+            partition
+                .take()
+                .expect("Checked above")
+                .upload(store, prefix, table)
+                .await?;
+            file_count += 1;
+        }
+        if partition.is_none() {
+            partition = Some(Partition::new(date, schema.clone(), &kinds)?);
+        }
+        partition.as_mut().expect("Set above").append(&row)?;
+    }
+    if let Some(last) = partition {
+        last.upload(store, prefix, table).await?;
+        file_count += 1;
+    }
+    Ok(file_count)
+}
+
+/// How a Postgres column is stored in Parquet.
+#[derive(Debug, Clone, Copy)]
+enum ColumnKind {
+    Int32,
+    Int64,
+    Float64,
+    Boolean,
+    Timestamp,
+    Text,
+}
+
+impl ColumnKind {
+    fn from_pg(data_type: &str) -> Self {
+        match data_type {
+            "integer" => ColumnKind::Int32,
+            "bigint" => ColumnKind::Int64,
+            "double precision" => ColumnKind::Float64,
+            "boolean" => ColumnKind::Boolean,
+            "timestamp without time zone" => ColumnKind::Timestamp,
+            // Text, JSONB payloads and BYTEA are exported as their text form
+            _ => ColumnKind::Text,
+        }
+    }
+
+    fn arrow_type(self) -> DataType {
+        match self {
+            ColumnKind::Int32 => DataType::Int32,
+            ColumnKind::Int64 => DataType::Int64,
+            ColumnKind::Float64 => DataType::Float64,
+            ColumnKind::Boolean => DataType::Boolean,
+            // `TIMESTAMP` columns hold UTC
+            ColumnKind::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            ColumnKind::Text => DataType::Utf8,
+        }
+    }
+
+    fn select(self, column: &str) -> String {
+        match self {
+            ColumnKind::Text => format!("{column}::TEXT AS {column}"),
+            _ => column.to_string(),
+        }
+    }
+}
+
+enum ColumnBuilder {
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnKind) -> Self {
+        match kind {
+            ColumnKind::Int32 => ColumnBuilder::Int32(Int32Builder::new()),
+            ColumnKind::Int64 => ColumnBuilder::Int64(Int64Builder::new()),
+            ColumnKind::Float64 => ColumnBuilder::Float64(Float64Builder::new()),
+            ColumnKind::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            ColumnKind::Timestamp => {
+                ColumnBuilder::Timestamp(TimestampMicrosecondBuilder::new().with_timezone("UTC"))
+            }
+            ColumnKind::Text => ColumnBuilder::Text(StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, row: &Row, index: usize) {
+        match self {
+            ColumnBuilder::Int32(builder) => {
+                builder.append_option(row.get::<_, Option<i32>>(index))
+            }
+            ColumnBuilder::Int64(builder) => {
+                builder.append_option(row.get::<_, Option<i64>>(index))
+            }
+            ColumnBuilder::Float64(builder) => {
+                builder.append_option(row.get::<_, Option<f64>>(index))
+            }
+            ColumnBuilder::Boolean(builder) => {
+                builder.append_option(row.get::<_, Option<bool>>(index))
+            }
+            ColumnBuilder::Timestamp(builder) => builder.append_option(
+                row.get::<_, Option<NaiveDateTime>>(index)
+                    .map(|ts| ts.and_utc().timestamp_micros()),
+            ),
+            ColumnBuilder::Text(builder) => {
+                builder.append_option(row.get::<_, Option<String>>(index))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int32(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Boolean(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Timestamp(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Text(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// The Parquet file of one table and day, buffered until it is complete.
+struct Partition {
+    date: Option<NaiveDate>,
+    schema: Arc<Schema>,
+    builders: Vec<ColumnBuilder>,
+    buffered_rows: usize,
+    writer: ArrowWriter<Vec<u8>>,
+}
+
+impl Partition {
+    fn new(
+        date: Option<NaiveDate>,
+        schema: Arc<Schema>,
+        kinds: &[ColumnKind],
+    ) -> anyhow::Result<Self> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        Ok(Self {
+            date,
+            writer: ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))?,
+            schema,
+            builders: kinds.iter().copied().map(ColumnBuilder::new).collect(),
+            buffered_rows: 0,
+        })
+    }
+
+    fn append(&mut self, row: &Row) -> anyhow::Result<()> {
+        for (index, builder) in self.builders.iter_mut().enumerate() {
+            builder.append(row, index);
+        }
+        self.buffered_rows += 1;
+        if self.buffered_rows == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> anyhow::Result<()> {
+        let columns = self
+            .builders
+            .iter_mut()
+            .map(ColumnBuilder::finish)
+            .collect();
+        self.writer
+            .write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.buffered_rows = 0;
+        Ok(())
+    }
+
+    async fn upload(
+        mut self,
+        store: &dyn ObjectStore,
+        prefix: &Path,
+        table: &str,
+    ) -> anyhow::Result<()> {
+        if self.buffered_rows > 0 {
+            self.write_batch()?;
+        }
+        let data = self.writer.into_inner()?;
+        let date = self
+            .date
+            .map_or_else(|| NULL_PARTITION.to_string(), |date| date.to_string());
+        let path = prefix
+            .child(table)
+            .child(format!("date={date}"))
+            .child("part-0.parquet");
+        let bytes = data.len();
+        store.put(&path, PutPayload::from(data)).await?;
+        info!(%path, bytes, "Wrote Parquet file");
+        Ok(())
+    }
+}
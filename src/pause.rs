@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use fedimint_core::{anyhow, config::FederationId};
+use tokio_postgres::Client;
+use tracing::info;
+
+/// Stops ingesting `federation_id` until it is resumed, e.g. while its events
+/// are corrupt or under investigation. The flag lives in
+/// `paused_federations`, so it holds across runs and replicas without a
+/// config change. Pausing again only updates the reason.
+pub(crate) async fn pause(
+    pg_client: &Client,
+    federation_id: FederationId,
+    reason: Option<String>,
+) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO paused_federations (federation_id, reason, paused_at) VALUES ($1, $2, $3) ON CONFLICT (federation_id) DO UPDATE SET reason = EXCLUDED.reason",
+            &[&federation_id.to_string(), &reason, &Utc::now().naive_utc()],
+        )
+        .await?;
+    info!(%federation_id, ?reason, "Paused federation");
+    println!("Paused {federation_id}, its events are fetched again once resumed");
+    Ok(())
+}
+
+/// Ingests `federation_id` again from the next run on. Nothing is lost while
+/// paused: the cursor stayed put, so the next run catches up.
+pub(crate) async fn resume(pg_client: &Client, federation_id: FederationId) -> anyhow::Result<()> {
+    let resumed = pg_client
+        .execute(
+            "DELETE FROM paused_federations WHERE federation_id = $1",
+            &[&federation_id.to_string()],
+        )
+        .await?;
+    anyhow::ensure!(resumed > 0, "Federation {federation_id} isn't paused");
+    info!(%federation_id, "Resumed federation");
+    println!("Resumed {federation_id}");
+    Ok(())
+}
+
+/// The paused federations with the reason given when pausing them.
+pub(crate) async fn paused(
+    pg_client: &Client,
+) -> anyhow::Result<BTreeMap<FederationId, Option<String>>> {
+    pg_client
+        .query("SELECT federation_id, reason FROM paused_federations", &[])
+        .await?
+        .iter()
+        .map(|row| Ok((row.get::<_, String>(0).parse()?, row.get(1))))
+        .collect()
+}
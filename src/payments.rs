@@ -0,0 +1,262 @@
+/// Flattens the typed LNv1/LNv2 event tables into one row per terminal
+/// payment event (succeeded or failed), joined with the matching started
+/// event for the amount, fee and start time.
+///
+/// The started event is looked up in the same gateway epoch first. A payment
+/// that was in flight while the gateway was restored has its started event in
+/// the previous epoch, so as a fallback the started event with the same
+/// contract id, payment hash or payment image from any epoch within one day
+/// before the terminal event is used.
+///
+/// Meant to be used as a CTE: `WITH payments AS (PAYMENTS_QUERY) SELECT ...`.
+///
+/// Columns: `federation_id, federation_name, gateway_epoch, log_id, ts,
+/// protocol, direction, outcome, payment_hash, amount_msat, fee_msat,
+/// started_ts, error`.
+pub(crate) const PAYMENTS_QUERY: &str = "
+    SELECT s.federation_id, s.federation_name, s.gateway_epoch, s.log_id, s.ts,
+        'lnv1' AS protocol, 'outgoing' AS direction, 'succeeded' AS outcome,
+        s.payment_hash, s.contract_amount AS amount_msat,
+        s.contract_amount - st.invoice_amount AS fee_msat,
+        st.ts AS started_ts, NULL AS error
+    FROM lnv1_outgoing_payment_succeeded s
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv1_outgoing_payment_started st
+        WHERE st.contract_id = s.contract_id AND st.federation_id = s.federation_id
+            AND (st.gateway_epoch = s.gateway_epoch OR st.ts BETWEEN s.ts - INTERVAL '1 day' AND s.ts)
+        ORDER BY st.gateway_epoch = s.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT f.federation_id, f.federation_name, f.gateway_epoch, f.log_id, f.ts,
+        'lnv1', 'outgoing', 'failed',
+        f.payment_hash, f.contract_amount,
+        0,
+        st.ts, f.error_reason
+    FROM lnv1_outgoing_payment_failed f
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv1_outgoing_payment_started st
+        WHERE st.contract_id = f.contract_id AND st.federation_id = f.federation_id
+            AND (st.gateway_epoch = f.gateway_epoch OR st.ts BETWEEN f.ts - INTERVAL '1 day' AND f.ts)
+        ORDER BY st.gateway_epoch = f.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT s.federation_id, s.federation_name, s.gateway_epoch, s.log_id, s.ts,
+        'lnv1', 'incoming', 'succeeded',
+        s.payment_hash, st.invoice_amount,
+        st.invoice_amount - st.contract_amount,
+        st.ts, NULL
+    FROM lnv1_incoming_payment_succeeded s
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv1_incoming_payment_started st
+        WHERE st.payment_hash = s.payment_hash AND st.federation_id = s.federation_id
+            AND (st.gateway_epoch = s.gateway_epoch OR st.ts BETWEEN s.ts - INTERVAL '1 day' AND s.ts)
+        ORDER BY st.gateway_epoch = s.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT f.federation_id, f.federation_name, f.gateway_epoch, f.log_id, f.ts,
+        'lnv1', 'incoming', 'failed',
+        f.payment_hash, st.invoice_amount,
+        0,
+        st.ts, f.error_reason
+    FROM lnv1_incoming_payment_failed f
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv1_incoming_payment_started st
+        WHERE st.payment_hash = f.payment_hash AND st.federation_id = f.federation_id
+            AND (st.gateway_epoch = f.gateway_epoch OR st.ts BETWEEN f.ts - INTERVAL '1 day' AND f.ts)
+        ORDER BY st.gateway_epoch = f.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT s.federation_id, s.federation_name, s.gateway_epoch, s.log_id, s.ts,
+        'lnv2', 'outgoing', 'succeeded',
+        s.payment_image, st.amount,
+        st.amount - st.invoice_amount,
+        st.operation_start, NULL
+    FROM lnv2_outgoing_payment_succeeded s
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv2_outgoing_payment_started st
+        WHERE st.payment_image = s.payment_image AND st.federation_id = s.federation_id
+            AND (st.gateway_epoch = s.gateway_epoch OR st.ts BETWEEN s.ts - INTERVAL '1 day' AND s.ts)
+        ORDER BY st.gateway_epoch = s.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT f.federation_id, f.federation_name, f.gateway_epoch, f.log_id, f.ts,
+        'lnv2', 'outgoing', 'failed',
+        f.payment_image, st.amount,
+        0,
+        st.operation_start, f.error
+    FROM lnv2_outgoing_payment_failed f
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv2_outgoing_payment_started st
+        WHERE st.payment_image = f.payment_image AND st.federation_id = f.federation_id
+            AND (st.gateway_epoch = f.gateway_epoch OR st.ts BETWEEN f.ts - INTERVAL '1 day' AND f.ts)
+        ORDER BY st.gateway_epoch = f.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT s.federation_id, s.federation_name, s.gateway_epoch, s.log_id, s.ts,
+        'lnv2', 'incoming', 'succeeded',
+        s.payment_image, st.invoice_amount,
+        st.invoice_amount - st.amount,
+        st.operation_start, NULL
+    FROM lnv2_incoming_payment_succeeded s
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv2_incoming_payment_started st
+        WHERE st.payment_image = s.payment_image AND st.federation_id = s.federation_id
+            AND (st.gateway_epoch = s.gateway_epoch OR st.ts BETWEEN s.ts - INTERVAL '1 day' AND s.ts)
+        ORDER BY st.gateway_epoch = s.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+    UNION ALL
+    SELECT f.federation_id, f.federation_name, f.gateway_epoch, f.log_id, f.ts,
+        'lnv2', 'incoming', 'failed',
+        f.payment_image, st.invoice_amount,
+        0,
+        st.operation_start, f.error
+    FROM lnv2_incoming_payment_failed f
+    LEFT JOIN LATERAL (
+        SELECT * FROM lnv2_incoming_payment_started st
+        WHERE st.payment_image = f.payment_image AND st.federation_id = f.federation_id
+            AND (st.gateway_epoch = f.gateway_epoch OR st.ts BETWEEN f.ts - INTERVAL '1 day' AND f.ts)
+        ORDER BY st.gateway_epoch = f.gateway_epoch DESC, st.ts DESC
+        LIMIT 1
+    ) st ON TRUE
+";
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use tokio_postgres::Client;
+
+    use super::PAYMENTS_QUERY;
+    use crate::test_db;
+
+    const FEDERATION: &str = "'fed', 'Federation'";
+
+    async fn payments(pg_client: &Client) -> Vec<(i32, i64, Option<i64>, Option<NaiveDateTime>)> {
+        pg_client
+            .query(
+                &format!(
+                    "WITH payments AS ({PAYMENTS_QUERY}) SELECT gateway_epoch, amount_msat, fee_msat, started_ts FROM payments ORDER BY ts"
+                ),
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+            .collect()
+    }
+
+    fn ts(s: &str) -> Option<NaiveDateTime> {
+        Some(NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap())
+    }
+
+    async fn lnv1_outgoing_started(
+        pg_client: &Client,
+        epoch: i32,
+        log_id: i64,
+        ts: &str,
+        contract_id: &str,
+        invoice_amount: i64,
+    ) {
+        pg_client
+            .execute(
+                &format!(
+                    "INSERT INTO lnv1_outgoing_payment_started (gateway_epoch, log_id, ts, federation_id, federation_name, contract_id, invoice_amount, operation_id) VALUES ({epoch}, {log_id}, '{ts}', {FEDERATION}, '{contract_id}', {invoice_amount}, 'op')"
+                ),
+                &[],
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn lnv1_outgoing_succeeded(
+        pg_client: &Client,
+        epoch: i32,
+        log_id: i64,
+        ts: &str,
+        contract_id: &str,
+        contract_amount: i64,
+    ) {
+        pg_client
+            .execute(
+                &format!(
+                    "INSERT INTO lnv1_outgoing_payment_succeeded (gateway_epoch, log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, preimage) VALUES ({epoch}, {log_id}, '{ts}', {FEDERATION}, '{contract_id}', {contract_amount}, 'gk', 'hash', 0, 'uk', 'preimage')"
+                ),
+                &[],
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn payment_in_flight_during_restore_keeps_its_started_event() {
+        let pg_client = test_db::connect().await;
+        // The log restarts at 0 in the new epoch, so the log ids overlap
+        lnv1_outgoing_started(&pg_client, 0, 7, "2024-05-01 10:00:00", "c1", 1000).await;
+        lnv1_outgoing_succeeded(&pg_client, 1, 0, "2024-05-01 10:05:00", "c1", 1010).await;
+
+        assert_eq!(
+            payments(&pg_client).await,
+            vec![(1, 1010, Some(10), ts("2024-05-01 10:00:00"))]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn started_event_of_the_same_epoch_wins() {
+        let pg_client = test_db::connect().await;
+        lnv1_outgoing_started(&pg_client, 0, 3, "2024-05-01 10:04:00", "c1", 900).await;
+        lnv1_outgoing_started(&pg_client, 1, 3, "2024-05-01 09:00:00", "c1", 1000).await;
+        lnv1_outgoing_succeeded(&pg_client, 1, 4, "2024-05-01 10:05:00", "c1", 1010).await;
+
+        assert_eq!(
+            payments(&pg_client).await,
+            vec![(1, 1010, Some(10), ts("2024-05-01 09:00:00"))]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn started_event_of_another_epoch_older_than_a_day_is_ignored() {
+        let pg_client = test_db::connect().await;
+        lnv1_outgoing_started(&pg_client, 0, 7, "2024-04-29 10:00:00", "c1", 1000).await;
+        lnv1_outgoing_succeeded(&pg_client, 1, 0, "2024-05-01 10:05:00", "c1", 1010).await;
+
+        assert_eq!(payments(&pg_client).await, vec![(1, 1010, None, None)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn started_event_of_a_later_epoch_is_ignored() {
+        let pg_client = test_db::connect().await;
+        lnv1_outgoing_succeeded(&pg_client, 0, 8, "2024-05-01 10:05:00", "c1", 1010).await;
+        lnv1_outgoing_started(&pg_client, 1, 0, "2024-05-01 10:06:00", "c1", 1000).await;
+
+        assert_eq!(payments(&pg_client).await, vec![(0, 1010, None, None)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn lnv2_incoming_payment_across_epochs() {
+        let pg_client = test_db::connect().await;
+        pg_client
+            .batch_execute(&format!(
+                "INSERT INTO lnv2_incoming_payment_started (gateway_epoch, log_id, ts, federation_id, federation_name, amount, claim_pk, ephemeral_pk, expiration, payment_image, refund_pk, invoice_amount, operation_start) VALUES (2, 11, '2024-05-01 10:00:00', {FEDERATION}, 990, 'cpk', 'epk', 0, 'image', 'rpk', 1000, '2024-05-01 09:59:00');
+                INSERT INTO lnv2_incoming_payment_succeeded (gateway_epoch, log_id, ts, federation_id, federation_name, payment_image) VALUES (3, 0, '2024-05-01 10:01:00', {FEDERATION}, 'image');"
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            payments(&pg_client).await,
+            vec![(3, 1000, Some(10), ts("2024-05-01 09:59:00"))]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::TelegramClient;
+
+/// How often progress is logged while catching up.
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Tracks how far a run got through the new events of a federation. The
+/// payment log is fetched in one go before processing, so the number of
+/// events still to go is known upfront and the ETA follows from the rate so
+/// far. This matters on a first run against a gateway with a long history,
+/// which can take hours.
+pub(crate) struct BackfillProgress {
+    federation_name: String,
+    total: u64,
+    processed: u64,
+    /// Log ids of the newest and oldest new event.
+    log_id_range: (i64, i64),
+    started: Instant,
+    last_logged: Instant,
+    telegram: Option<(TelegramClient, Duration)>,
+    last_sent: Instant,
+}
+
+impl BackfillProgress {
+    pub fn new(
+        federation_name: String,
+        total: u64,
+        log_id_range: (i64, i64),
+        telegram: Option<(TelegramClient, Duration)>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            federation_name,
+            total,
+            processed: 0,
+            log_id_range,
+            started: now,
+            last_logged: now,
+            telegram,
+            last_sent: now,
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.processed as f64 * 100.0 / self.total as f64
+    }
+
+    /// Remaining time at the rate of events processed so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.processed == 0 {
+            return None;
+        }
+        let per_event = self.started.elapsed().as_secs_f64() / self.processed as f64;
+        Some(Duration::from_secs_f64(
+            per_event * (self.total - self.processed) as f64,
+        ))
+    }
+
+    /// Counts `log_id` as processed and reports progress if it's due.
+    pub async fn record(&mut self, log_id: i64) {
+        self.processed += 1;
+
+        if self.last_logged.elapsed() >= LOG_INTERVAL {
+            self.last_logged = Instant::now();
+            info!(
+                federation_name = self.federation_name,
+                processed = self.processed,
+                total = self.total,
+                log_id,
+                newest_log_id = self.log_id_range.0,
+                oldest_log_id = self.log_id_range.1,
+                eta_secs = self.eta().map(|eta| eta.as_secs()),
+                "Backfill {:.0}% complete",
+                self.percent()
+            );
+        }
+
+        if let Some((telegram_client, interval)) = &self.telegram
+            && self.last_sent.elapsed() >= *interval
+        {
+            self.last_sent = Instant::now();
+            telegram_client
+                .send_telegram_message(self.to_string())
+                .await;
+        }
+    }
+}
+
+impl fmt::Display for BackfillProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Backfill of {} {:.0}% complete ({}/{} events)",
+            self.federation_name,
+            self.percent(),
+            self.processed,
+            self.total
+        )?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", about {} min remaining", eta.as_secs().div_ceil(60))?;
+        }
+        Ok(())
+    }
+}
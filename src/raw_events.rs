@@ -0,0 +1,301 @@
+use std::ops::RangeInclusive;
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use deadpool_postgres::Pool;
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::PersistedLogEntry;
+use serde_json::Value;
+use tokio_postgres::{Client, Row};
+
+use crate::{GatewayEpoch, parse_log_id};
+
+/// How payloads are stored in `raw_events`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ArchiveEncoding {
+    /// In the `payload` JSONB column, readable with SQL
+    #[default]
+    Json,
+    /// CBOR compressed with zstd in the `payload_cbor_zstd` column, for
+    /// gateways with many events. `raw-events-to-json` converts them back
+    CborZstd,
+}
+
+/// Stores `entry` verbatim in `raw_events`, whatever its module and kind and
+/// whether or not it parses. The typed tables can be rebuilt from it after a
+/// schema change or a parser fix. `ts` is `None` for a timestamp out of
+/// range.
+pub(crate) async fn archive(
+    pg_client: &Client,
+    ts: Option<NaiveDateTime>,
+    federation_id: FederationId,
+    federation_name: &str,
+    gateway_epoch: GatewayEpoch,
+    entry: &PersistedLogEntry,
+    encoding: ArchiveEncoding,
+) -> anyhow::Result<()> {
+    let (payload, payload_cbor_zstd) = match encoding {
+        ArchiveEncoding::Json => (Some(payload_json(entry)), None),
+        ArchiveEncoding::CborZstd => (None, Some(encode_cbor_zstd(&payload_json(entry))?)),
+    };
+    pg_client
+        .execute(
+            "INSERT INTO raw_events (log_id, ts, federation_id, federation_name, gateway_epoch, module, kind, payload, payload_cbor_zstd) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO NOTHING",
+            &[
+                &parse_log_id(&entry.id())?,
+                &ts,
+                &federation_id.to_string(),
+                &federation_name,
+                &i32::from(gateway_epoch),
+                &entry.module.as_ref().map(|(module, _)| module.as_str()),
+                &entry.kind.to_string(),
+                &payload,
+                &payload_cbor_zstd,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Holes in the archived log ids of a federation, oldest first. A
+/// federation's event log is numbered without gaps, so a hole means its
+/// events were never ingested, unless it lies within a range recorded with
+/// [`record_checked_gap`]. Nothing below the first archived event is checked,
+/// those events were ingested before the archive existed.
+pub(crate) async fn gaps(
+    pg_client: &Client,
+    federation_id: FederationId,
+    gateway_epoch: GatewayEpoch,
+) -> anyhow::Result<Vec<RangeInclusive<i64>>> {
+    Ok(pg_client
+        .query(
+            "SELECT previous + 1, log_id - 1 FROM (SELECT log_id, LAG(log_id) OVER (ORDER BY log_id) AS previous FROM raw_events WHERE federation_id = $1 AND gateway_epoch = $2) ids WHERE log_id - previous > 1 AND NOT EXISTS (SELECT 1 FROM checked_gaps WHERE federation_id = $1 AND gateway_epoch = $2 AND first_log_id <= previous + 1 AND last_log_id >= log_id - 1) ORDER BY log_id",
+            &[&federation_id.to_string(), &i32::from(gateway_epoch)],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0)..=row.get(1))
+        .collect())
+}
+
+/// Records that the gateway was asked for the events of `gap`, so the ids it
+/// didn't return aren't reported as holes again. The payment log leaves out
+/// events of other kinds, and their ids stay missing for good.
+pub(crate) async fn record_checked_gap(
+    pg_client: &Client,
+    federation_id: FederationId,
+    gateway_epoch: GatewayEpoch,
+    gap: &RangeInclusive<i64>,
+    now: NaiveDateTime,
+) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO checked_gaps (federation_id, gateway_epoch, first_log_id, last_log_id, checked_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (federation_id, gateway_epoch, first_log_id) DO UPDATE SET last_log_id = GREATEST(checked_gaps.last_log_id, EXCLUDED.last_log_id), checked_at = EXCLUDED.checked_at",
+            &[
+                &federation_id.to_string(),
+                &i32::from(gateway_epoch),
+                gap.start(),
+                gap.end(),
+                &now,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// The payload as JSON. A payload that isn't valid JSON is kept as a JSON
+/// string, so storing it can't fail.
+pub(crate) fn payload_json(entry: &PersistedLogEntry) -> Value {
+    serde_json::from_slice(&entry.payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&entry.payload).into_owned()))
+}
+
+fn encode_cbor_zstd(payload: &Value) -> anyhow::Result<Vec<u8>> {
+    let mut cbor = Vec::new();
+    ciborium::into_writer(payload, &mut cbor)?;
+    Ok(zstd::encode_all(cbor.as_slice(), 0)?)
+}
+
+fn decode_cbor_zstd(payload: &[u8]) -> anyhow::Result<Value> {
+    Ok(ciborium::from_reader(
+        zstd::decode_all(payload)?.as_slice(),
+    )?)
+}
+
+/// Reads the payload of a `raw_events` row from the JSON column `idx`, or
+/// from the compact column `compact_idx` if the JSON one is `NULL`.
+pub(crate) fn read_payload(row: &Row, idx: usize, compact_idx: usize) -> anyhow::Result<Value> {
+    if let Some(payload) = row.get::<_, Option<Value>>(idx) {
+        return Ok(payload);
+    }
+    let compact: Vec<u8> = row
+        .get::<_, Option<Vec<u8>>>(compact_idx)
+        .ok_or_else(|| anyhow::anyhow!("Archived event has no payload"))?;
+    decode_cbor_zstd(&compact)
+}
+
+/// Stores the payloads archived with [`ArchiveEncoding::CborZstd`] as JSON
+/// again, e.g. to look into them with SQL or before going back to JSON.
+/// Converts them in batches that are committed one by one, so it can be
+/// stopped and run again. Returns the number of converted events.
+pub(crate) async fn convert_to_json(
+    pool: &Pool,
+    federation_id: Option<FederationId>,
+) -> anyhow::Result<u64> {
+    const BATCH_SIZE: i64 = 1000;
+    let federation_id = federation_id.map(|federation_id| federation_id.to_string());
+    let mut pg_client = pool.get().await?;
+    let mut converted = 0;
+    loop {
+        let transaction = pg_client.transaction().await?;
+        let rows = transaction
+            .query(
+                "SELECT log_id, federation_id, gateway_epoch, payload_cbor_zstd FROM raw_events WHERE payload IS NULL AND ($1::TEXT IS NULL OR federation_id = $1) LIMIT $2 FOR UPDATE",
+                &[&federation_id, &BATCH_SIZE],
+            )
+            .await?;
+        for row in &rows {
+            let payload = decode_cbor_zstd(row.get(3))?;
+            transaction
+                .execute(
+                    "UPDATE raw_events SET payload = $4, payload_cbor_zstd = NULL WHERE log_id = $1 AND federation_id = $2 AND gateway_epoch = $3",
+                    &[
+                        &row.get::<_, i64>(0),
+                        &row.get::<_, String>(1),
+                        &row.get::<_, i32>(2),
+                        &payload,
+                    ],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        converted += rows.len() as u64;
+        if (rows.len() as i64) < BATCH_SIZE {
+            return Ok(converted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use fedimint_core::config::FederationId;
+    use fedimint_eventlog::PersistedLogEntry;
+    use serde_json::json;
+    use tokio_postgres::Client;
+
+    use super::{ArchiveEncoding, archive, convert_to_json, gaps, record_checked_gap};
+    use crate::{GatewayEpoch, test_db};
+
+    async fn archive_ids(pg_client: &Client, epoch: GatewayEpoch, log_ids: &[i64]) {
+        for log_id in log_ids {
+            let entry: PersistedLogEntry = serde_json::from_value(json!({
+                "id": log_id,
+                "kind": "payment-receive",
+                "module": ["mint", 0],
+                "ts_usecs": 0,
+                "payload": {},
+            }))
+            .unwrap();
+            archive(
+                pg_client,
+                None,
+                FederationId::dummy(),
+                "Federation",
+                epoch,
+                &entry,
+                ArchiveEncoding::Json,
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn checked_gaps_are_not_reported_again() {
+        let pg_client = test_db::connect().await;
+        let epoch = "0".parse::<GatewayEpoch>().unwrap();
+        let now = NaiveDateTime::default();
+        archive_ids(&pg_client, epoch, &[1, 2, 5, 9]).await;
+        assert_eq!(
+            gaps(&pg_client, FederationId::dummy(), epoch)
+                .await
+                .unwrap(),
+            vec![3..=4, 6..=8]
+        );
+
+        record_checked_gap(&pg_client, FederationId::dummy(), epoch, &(3..=4), now)
+            .await
+            .unwrap();
+        assert_eq!(
+            gaps(&pg_client, FederationId::dummy(), epoch)
+                .await
+                .unwrap(),
+            vec![6..=8]
+        );
+
+        // Partly filled, what's left of it was checked with the rest
+        archive_ids(&pg_client, epoch, &[7]).await;
+        record_checked_gap(&pg_client, FederationId::dummy(), epoch, &(6..=8), now)
+            .await
+            .unwrap();
+        assert!(
+            gaps(&pg_client, FederationId::dummy(), epoch)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // Only for the epoch it was checked in
+        let other_epoch = "1".parse::<GatewayEpoch>().unwrap();
+        archive_ids(&pg_client, other_epoch, &[1, 5]).await;
+        assert_eq!(
+            gaps(&pg_client, FederationId::dummy(), other_epoch)
+                .await
+                .unwrap(),
+            vec![2..=4]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn compact_payloads_convert_back_to_json() {
+        let pool = test_db::pool().await;
+        let pg_client = pool.get().await.unwrap();
+        let payload = json!({"amount": 1000, "preimage": "ab".repeat(32), "fee": null});
+        let entry: PersistedLogEntry = serde_json::from_value(json!({
+            "id": 1,
+            "kind": "payment-receive",
+            "module": ["mint", 0],
+            "ts_usecs": 0,
+            "payload": payload,
+        }))
+        .unwrap();
+        archive(
+            &pg_client,
+            None,
+            FederationId::dummy(),
+            "Federation",
+            "0".parse().unwrap(),
+            &entry,
+            ArchiveEncoding::CborZstd,
+        )
+        .await
+        .unwrap();
+        let stored = pg_client
+            .query_one("SELECT payload, payload_cbor_zstd FROM raw_events", &[])
+            .await
+            .unwrap();
+        assert_eq!(super::read_payload(&stored, 0, 1).unwrap(), payload);
+        assert!(stored.get::<_, Option<serde_json::Value>>(0).is_none());
+
+        assert_eq!(convert_to_json(&pool, None).await.unwrap(), 1);
+        let converted = pg_client
+            .query_one("SELECT payload, payload_cbor_zstd FROM raw_events", &[])
+            .await
+            .unwrap();
+        assert_eq!(converted.get::<_, serde_json::Value>(0), payload);
+        assert!(converted.get::<_, Option<Vec<u8>>>(1).is_none());
+    }
+}
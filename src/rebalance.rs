@@ -0,0 +1,158 @@
+use std::{fmt, str::FromStr};
+
+use chrono::Utc;
+use clap::Args;
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{msat::Msat, payments::PAYMENTS_QUERY, time_window::TimeWindow};
+
+/// The start of an operation id, hex encoded like in the event tables.
+#[derive(Debug, Clone)]
+pub(crate) struct OperationIdPrefix(String);
+
+impl FromStr for OperationIdPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The prefix ends up in the query text, see `Rebalances::condition`
+        anyhow::ensure!(
+            !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit()),
+            "Invalid operation id prefix, expected hex: {s}"
+        );
+        Ok(Self(s.to_ascii_lowercase()))
+    }
+}
+
+impl fmt::Display for OperationIdPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub(crate) struct RebalanceOpts {
+    /// Operation id prefix of the operator's own payments that move liquidity
+    /// between federations (repeatable). Payments tagged with
+    /// `tag-rebalance` count as rebalances as well
+    #[arg(
+        long = "rebalance-operation-prefix",
+        env = "REBALANCE_OPERATION_PREFIXES",
+        value_delimiter = ','
+    )]
+    pub operation_id_prefixes: Vec<OperationIdPrefix>,
+}
+
+impl RebalanceOpts {
+    /// SQL condition on the `payment_hash` of a `PAYMENTS_QUERY` row, true for
+    /// rebalances. Both legs of a rebalance share the payment hash, so
+    /// matching the operation id of either one tags the payment in both
+    /// federations. Only LNv1 events carry an operation id, LNv2 rebalances
+    /// have to be tagged.
+    pub fn condition(&self) -> String {
+        let mut rebalances = vec!["SELECT payment_hash FROM rebalance_payments".to_string()];
+        if !self.operation_id_prefixes.is_empty() {
+            let patterns = self
+                .operation_id_prefixes
+                .iter()
+                .map(|prefix| format!("'{prefix}%'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let matches = format!("st.operation_id LIKE ANY(ARRAY[{patterns}])");
+            for terminal in [
+                "lnv1_outgoing_payment_succeeded",
+                "lnv1_outgoing_payment_failed",
+            ] {
+                rebalances.push(format!(
+                    "SELECT t.payment_hash FROM {terminal} t JOIN lnv1_outgoing_payment_started st ON st.contract_id = t.contract_id AND st.federation_id = t.federation_id WHERE {matches}"
+                ));
+            }
+            rebalances.push(format!(
+                "SELECT st.payment_hash FROM lnv1_incoming_payment_started st WHERE {matches}"
+            ));
+        }
+        format!("payment_hash IN ({})", rebalances.join(" UNION "))
+    }
+}
+
+/// Marks the payment with `payment_hash` (the payment image for LNv2) as a
+/// rebalance, for payments that can't be recognised by their operation id.
+/// Tagging again only updates the note.
+pub(crate) async fn tag(
+    pg_client: &Client,
+    payment_hash: String,
+    note: Option<String>,
+) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO rebalance_payments (payment_hash, note, tagged_at) VALUES ($1, $2, $3) ON CONFLICT (payment_hash) DO UPDATE SET note = EXCLUDED.note",
+            &[&payment_hash, &note, &Utc::now().naive_utc()],
+        )
+        .await?;
+    info!(payment_hash, ?note, "Tagged rebalance");
+    println!("Tagged {payment_hash} as rebalance");
+    Ok(())
+}
+
+/// The operator's rebalances within a window and what they cost, next to the
+/// fees earned from everyone else. The fees of a rebalance are paid by the
+/// operator to their own gateway, so they are a cost rather than revenue.
+#[derive(Debug)]
+pub(crate) struct RebalanceCosts {
+    count: i64,
+    volume: Msat,
+    cost: Msat,
+    external_fees: Msat,
+}
+
+impl RebalanceCosts {
+    pub async fn query(
+        pg_client: &Client,
+        window: TimeWindow,
+        rebalances: &RebalanceOpts,
+    ) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let row = pg_client
+            .query_one(
+                &format!(
+                    "
+                    WITH payments AS ({PAYMENTS_QUERY})
+                    SELECT
+                        COUNT(*) FILTER (WHERE rebalance),
+                        COALESCE(SUM(amount_msat) FILTER (WHERE rebalance AND direction = 'outgoing'), 0)::TEXT,
+                        COALESCE(SUM(fee_msat) FILTER (WHERE rebalance), 0)::TEXT,
+                        COALESCE(SUM(fee_msat) FILTER (WHERE NOT rebalance), 0)::TEXT
+                    FROM (SELECT *, {} AS rebalance FROM payments) p
+                    WHERE outcome = 'succeeded' AND ts >= $1 AND ts < $2
+                    ",
+                    rebalances.condition()
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        Ok(Self {
+            count: row.get(0),
+            volume: row.get(1),
+            cost: row.get(2),
+            external_fees: row.get(3),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl fmt::Display for RebalanceCosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "===========REBALANCING===========")?;
+        writeln!(
+            f,
+            "Rebalance Payments: {}, Volume: {}, Cost: {}",
+            self.count, self.volume, self.cost
+        )?;
+        writeln!(f, "Fees Excluding Rebalances: {}", self.external_fees)?;
+        writeln!(f)
+    }
+}
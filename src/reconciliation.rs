@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::payments::PAYMENTS_QUERY;
+
+/// A payment record exported from outside the gateway, e.g. the forwarding
+/// history of the Lightning node.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReconciliationRecord {
+    pub ts: DateTime<Utc>,
+    pub payment_hash: String,
+    pub amount_msat: i64,
+    #[serde(default)]
+    pub fee_msat: Option<i64>,
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+/// Loads the CSV into `reconciliation_records` and matches it against the
+/// warehouse.
+pub(crate) async fn import(pool: &Pool, csv: &Path, source: String) -> anyhow::Result<()> {
+    let records = csv::Reader::from_path(csv)?
+        .deserialize()
+        .collect::<Result<Vec<ReconciliationRecord>, _>>()?;
+    info!(records = records.len(), csv = %csv.display(), "Read reconciliation records");
+    reconcile(pool, &records, source).await
+}
+
+/// Stores `records` in `reconciliation_records` and pairs every record with a
+/// succeeded payment in the warehouse by payment hash. The amount may either
+/// include the gateway's fee or not, since the Lightning node only sees the
+/// invoice amount. Records without a
+/// matching payment, and payments in the same time range without a matching
+/// record, are reported as unmatched.
+pub(crate) async fn reconcile(
+    pool: &Pool,
+    records: &[ReconciliationRecord],
+    source: String,
+) -> anyhow::Result<()> {
+    let imported_at = Utc::now().naive_utc();
+
+    let mut pg_client = pool.get().await?;
+    let transaction = pg_client.transaction().await?;
+    for record in records {
+        transaction.execute("INSERT INTO reconciliation_records (imported_at, source, external_id, ts, payment_hash, amount_msat, fee_msat, status) VALUES ($1, $2, $3, $4, $5, $6, $7, 'unmatched')",
+        &[&imported_at, &source, &record.external_id, &record.ts.naive_utc(), &record.payment_hash, &record.amount_msat, &record.fee_msat]).await?;
+    }
+
+    let matched = transaction
+        .execute(
+            &format!(
+                "
+                WITH payments AS ({PAYMENTS_QUERY})
+                UPDATE reconciliation_records r
+                SET status = CASE WHEN r.amount_msat IN (p.amount_msat, p.amount_msat - p.fee_msat) THEN 'matched' ELSE 'amount_mismatch' END,
+                    matched_federation_id = p.federation_id,
+                    matched_log_id = p.log_id,
+                    matched_gateway_epoch = p.gateway_epoch
+                FROM payments p
+                WHERE p.outcome = 'succeeded' AND p.payment_hash = r.payment_hash AND r.imported_at = $1
+                "
+            ),
+            &[&imported_at],
+        )
+        .await?;
+    transaction.commit().await?;
+    info!(records = records.len(), matched, %source, "Imported reconciliation records");
+
+    let unmatched_records = pg_client
+        .query(
+            "SELECT ts, payment_hash, amount_msat, status FROM reconciliation_records WHERE imported_at = $1 AND status <> 'matched' ORDER BY ts",
+            &[&imported_at],
+        )
+        .await?;
+    for row in &unmatched_records {
+        let ts: chrono::NaiveDateTime = row.get(0);
+        let payment_hash: String = row.get(1);
+        let amount_msat: i64 = row.get(2);
+        let status: String = row.get(3);
+        warn!(%ts, %payment_hash, %amount_msat, %status, "External record not matched in warehouse");
+    }
+
+    let unmatched_payments = pg_client
+        .query(
+            &format!(
+                "
+                WITH payments AS ({PAYMENTS_QUERY})
+                SELECT p.federation_name, p.ts, p.payment_hash, p.amount_msat
+                FROM payments p
+                WHERE p.outcome = 'succeeded'
+                    AND p.ts >= (SELECT MIN(ts) FROM reconciliation_records WHERE imported_at = $1)
+                    AND p.ts <= (SELECT MAX(ts) FROM reconciliation_records WHERE imported_at = $1)
+                    AND NOT EXISTS (SELECT 1 FROM reconciliation_records r WHERE r.payment_hash = p.payment_hash)
+                ORDER BY p.ts
+                "
+            ),
+            &[&imported_at],
+        )
+        .await?;
+    for row in &unmatched_payments {
+        let federation_name: String = row.get(0);
+        let ts: chrono::NaiveDateTime = row.get(1);
+        let payment_hash: String = row.get(2);
+        let amount_msat: Option<i64> = row.get(3);
+        warn!(%federation_name, %ts, %payment_hash, ?amount_msat, "Warehouse payment not matched in external records");
+    }
+
+    println!(
+        "Imported {} records: {} matched, {} unmatched records, {} unmatched warehouse payments",
+        records.len(),
+        records.len() - unmatched_records.len(),
+        unmatched_records.len(),
+        unmatched_payments.len()
+    );
+
+    Ok(())
+}
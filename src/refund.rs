@@ -0,0 +1,191 @@
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::EventLogId;
+use serde::{Deserialize, de};
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::{dead_letter, outgoing::LNv2PaymentImage, parse_log_id};
+
+#[derive(Debug, Clone)]
+pub(crate) struct LNv1ContractCancelled {
+    contract_id: String,
+    contract_amount: Option<i64>,
+    reason: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for LNv1ContractCancelled {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+
+        let contract_id = value["contract_id"]
+            .as_str()
+            .ok_or_else(|| de::Error::missing_field("contract_id"))?
+            .to_string();
+        let contract_amount = value["contract_amount"].as_i64();
+        let reason = value["reason"].as_str().map(|s| s.to_string());
+
+        Ok(Self {
+            contract_id,
+            contract_amount,
+            reason,
+        })
+    }
+}
+
+impl LNv1ContractCancelled {
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        log_id: &EventLogId,
+        timestamp: u64,
+        federation_id: &FederationId,
+        federation_name: String,
+        gateway_epoch: i32,
+    ) -> anyhow::Result<()> {
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_contract_cancelled (log_id, ts, federation_id, federation_name, gateway_epoch, contract_id, contract_amount, reason) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.contract_id, &self.contract_amount, &self.reason]).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LNv1RefundClaimed {
+    contract_id: String,
+    amount: i64,
+}
+
+impl<'de> Deserialize<'de> for LNv1RefundClaimed {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+
+        let contract_id = value["contract_id"]
+            .as_str()
+            .ok_or_else(|| de::Error::missing_field("contract_id"))?
+            .to_string();
+        let amount = value["amount"]
+            .as_i64()
+            .ok_or_else(|| de::Error::missing_field("amount"))?;
+
+        Ok(Self {
+            contract_id,
+            amount,
+        })
+    }
+}
+
+impl LNv1RefundClaimed {
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        log_id: &EventLogId,
+        timestamp: u64,
+        federation_id: &FederationId,
+        federation_name: String,
+        gateway_epoch: i32,
+    ) -> anyhow::Result<()> {
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv1_refund_claimed (log_id, ts, federation_id, federation_name, gateway_epoch, contract_id, amount) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.contract_id, &self.amount]).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LNv2ContractCancelled {
+    payment_image: LNv2PaymentImage,
+    amount: Option<i64>,
+    reason: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for LNv2ContractCancelled {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+
+        let payment_image: LNv2PaymentImage =
+            serde_json::from_value(value["payment_image"].clone())
+                .map_err(|e| de::Error::custom(e.to_string()))?;
+        let amount = value["amount"].as_i64();
+        let reason = value["reason"].as_str().map(|s| s.to_string());
+
+        Ok(Self {
+            payment_image,
+            amount,
+            reason,
+        })
+    }
+}
+
+impl LNv2ContractCancelled {
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        log_id: &EventLogId,
+        timestamp: u64,
+        federation_id: &FederationId,
+        federation_name: String,
+        gateway_epoch: i32,
+    ) -> anyhow::Result<()> {
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_contract_cancelled (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, amount, reason) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash, &self.amount, &self.reason]).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LNv2RefundClaimed {
+    payment_image: LNv2PaymentImage,
+    amount: i64,
+}
+
+impl<'de> Deserialize<'de> for LNv2RefundClaimed {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+
+        let payment_image: LNv2PaymentImage =
+            serde_json::from_value(value["payment_image"].clone())
+                .map_err(|e| de::Error::custom(e.to_string()))?;
+        let amount = value["amount"]
+            .as_i64()
+            .ok_or_else(|| de::Error::missing_field("amount"))?;
+
+        Ok(Self {
+            payment_image,
+            amount,
+        })
+    }
+}
+
+impl LNv2RefundClaimed {
+    pub async fn insert(
+        &self,
+        pg_client: &Client,
+        log_id: &EventLogId,
+        timestamp: u64,
+        federation_id: &FederationId,
+        federation_name: String,
+        gateway_epoch: i32,
+    ) -> anyhow::Result<()> {
+        let log_id = parse_log_id(log_id)?;
+        let timestamp = dead_letter::timestamp(timestamp as i64)?;
+        pg_client.execute("INSERT INTO lnv2_refund_claimed (log_id, ts, federation_id, federation_name, gateway_epoch, payment_image, amount) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+        &[&log_id, &timestamp, &federation_id.to_string(), &federation_name, &gateway_epoch, &self.payment_image.hash, &self.amount]).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::PersistedLogEntry;
+use serde_json::json;
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{
+    GatewayEpoch, federation_event_processor::FederationEventProcessor, raw_events, schema,
+};
+
+/// Events read from `raw_events` at a time.
+const PAGE_SIZE: i64 = 1000;
+
+/// Rebuilds the event tables from `raw_events`, from `from_log_id` on, by
+/// running the current parsers over the archived payloads. Rows at or above
+/// `from_log_id` are deleted and parsed again, one federation per
+/// transaction, so a parser fix or a new column doesn't require pulling the
+/// payment log from the gateway again. Events rejected as back-dated stay
+/// out, peg-outs keep their market fee rate, and nothing is sent to the
+/// webhook.
+pub(crate) async fn replay(
+    pool: &Pool,
+    gateway_epoch: GatewayEpoch,
+    from_log_id: i64,
+    federation_id: Option<FederationId>,
+) -> anyhow::Result<()> {
+    let pg_client = pool.get().await?;
+    let federations = pg_client
+        .query(
+            "SELECT federation_id, MAX(federation_name) FROM raw_events WHERE gateway_epoch = $1 AND log_id >= $2 AND ($3::TEXT IS NULL OR federation_id = $3) GROUP BY federation_id ORDER BY federation_id",
+            &[
+                &i32::from(gateway_epoch),
+                &from_log_id,
+                &federation_id.map(|federation_id| federation_id.to_string()),
+            ],
+        )
+        .await?;
+    if federations.is_empty() {
+        println!("No archived events from log id {from_log_id} in gateway epoch {gateway_epoch}");
+        return Ok(());
+    }
+
+    for row in &federations {
+        let federation_id: String = row.get(0);
+        let federation_name: String = row.get(1);
+        let mut processor = FederationEventProcessor::replaying(
+            federation_id.parse()?,
+            federation_name.clone(),
+            pool.clone(),
+            gateway_epoch,
+        );
+        pg_client.batch_execute("BEGIN").await?;
+        let res = replay_federation(
+            &pg_client,
+            &mut processor,
+            &federation_id,
+            gateway_epoch,
+            from_log_id,
+        )
+        .await;
+        let (replayed, dead_lettered) = match res {
+            Ok(counts) => {
+                pg_client.batch_execute("COMMIT").await?;
+                counts
+            }
+            Err(err) => {
+                pg_client.batch_execute("ROLLBACK").await?;
+                return Err(err);
+            }
+        };
+        info!(%federation_name, replayed, dead_lettered, "Replayed raw events");
+        println!(
+            "{federation_name}: replayed {replayed} events, {dead_lettered} of them could not be parsed"
+        );
+    }
+    Ok(())
+}
+
+/// Returns the number of replayed events and how many of them ended up in
+/// `dead_letter_events`.
+async fn replay_federation(
+    pg_client: &Client,
+    processor: &mut FederationEventProcessor,
+    federation_id: &str,
+    gateway_epoch: GatewayEpoch,
+    from_log_id: i64,
+) -> anyhow::Result<(u64, i64)> {
+    let gateway_epoch = i32::from(gateway_epoch);
+    // Market fee rates come from mempool's recent blocks only, so they
+    // can't be looked up again for older peg-outs
+    pg_client
+        .execute(
+            "CREATE TEMP TABLE replayed_fee_rates ON COMMIT DROP AS SELECT log_id, market_fee_rate FROM gateway_ledger WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id >= $3 AND market_fee_rate IS NOT NULL",
+            &[&federation_id, &gateway_epoch, &from_log_id],
+        )
+        .await?;
+    // Events with a timestamp out of range are archived without one and
+    // can't be rebuilt, so their dead letters are kept. The others are
+    // dead-lettered again if they still don't parse, and otherwise no longer
+    // hold back the ingestion watermark.
+    for table in schema::event_tables()
+        .map(|(table, _)| table)
+        .chain(["dead_letter_events"])
+    {
+        pg_client
+            .execute(
+                &format!(
+                    "DELETE FROM {table} WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id >= $3 AND ts IS NOT NULL"
+                ),
+                &[&federation_id, &gateway_epoch, &from_log_id],
+            )
+            .await?;
+    }
+
+    let mut replayed = 0;
+    let mut after = from_log_id - 1;
+    loop {
+        let rows = pg_client
+            .query(
+                "SELECT log_id, ts, module, kind, payload, payload_cbor_zstd FROM raw_events r WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id > $3 AND ts IS NOT NULL AND NOT EXISTS (SELECT 1 FROM back_dated_events b WHERE b.event_log_id = r.log_id AND b.federation_id = r.federation_id AND b.gateway_epoch = r.gateway_epoch AND NOT b.accepted) ORDER BY log_id LIMIT $4",
+                &[&federation_id, &gateway_epoch, &after, &PAGE_SIZE],
+            )
+            .await?;
+        for row in &rows {
+            let log_id: i64 = row.get(0);
+            let ts: NaiveDateTime = row.get(1);
+            let module: Option<String> = row.get(2);
+            let kind: String = row.get(3);
+            let payload = raw_events::read_payload(row, 4, 5)?;
+            // The module instance id isn't archived, and no parser uses it
+            let entry: PersistedLogEntry = serde_json::from_value(json!({
+                "id": log_id,
+                "kind": kind,
+                "module": module.map(|module| json!([module, 0])),
+                "ts_usecs": ts.and_utc().timestamp_micros(),
+                "payload": payload,
+            }))?;
+            processor.store_entry(pg_client, &entry).await?;
+            after = log_id;
+            replayed += 1;
+        }
+        if (rows.len() as i64) < PAGE_SIZE {
+            break;
+        }
+    }
+
+    pg_client
+        .execute(
+            "UPDATE gateway_ledger g SET market_fee_rate = f.market_fee_rate FROM replayed_fee_rates f WHERE g.federation_id = $1 AND g.gateway_epoch = $2 AND g.log_id = f.log_id",
+            &[&federation_id, &gateway_epoch],
+        )
+        .await?;
+    let dead_lettered: i64 = pg_client
+        .query_one(
+            "SELECT COUNT(*) FROM dead_letter_events WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id >= $3 AND ts IS NOT NULL",
+            &[&federation_id, &gateway_epoch, &from_log_id],
+        )
+        .await?
+        .get(0);
+    Ok((replayed, dead_lettered))
+}
@@ -0,0 +1,319 @@
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::ValueEnum;
+use deadpool_postgres::Pool;
+use fedimint_core::{anyhow, bitcoin};
+use tokio_postgres::Client;
+
+use crate::{
+    leaderboard::WeeklyLeaderboard,
+    mempool::PegOutSummary,
+    msat::Msat,
+    noise::StatsNoise,
+    payments::PAYMENTS_QUERY,
+    rebalance::{RebalanceCosts, RebalanceOpts},
+    time_window::TimeWindow,
+};
+
+/// Parses `--as-of` as an RFC 3339 timestamp or a date. A date means the end
+/// of that day (UTC), so `2024-12-31` includes all of December 31st.
+pub(crate) fn parse_as_of(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(as_of) = DateTime::parse_from_rfc3339(s) {
+        return Ok(as_of.to_utc());
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("Expected a date (YYYY-MM-DD) or an RFC 3339 timestamp, got {s}"))?;
+    Ok(date
+        .succ_opt()
+        .ok_or_else(|| format!("Date out of range: {s}"))?
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is a valid time")
+        .and_utc())
+}
+
+/// How much a report built from the event tables shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ReportDetail {
+    /// Payment and failure totals and the fees, in two lines
+    Terse,
+    /// Payment counts per federation, without fees or amounts
+    Redacted,
+    /// Latencies, fees, payment counts per federation, peg-outs, rebalances
+    /// and the weekly leaderboard
+    Full,
+}
+
+/// Rebuilds the daily report for the 24 hours before `as_of` from the event
+/// tables alone, ignoring every event timestamped at or after `as_of`.
+/// Balances and liquidity are only known to the gateway at the time of a run
+/// and are left out. Unlike the gateway's own summary, the fees don't include
+/// the operator's rebalances, which are listed separately.
+pub(crate) async fn report(
+    pool: &Pool,
+    as_of: DateTime<Utc>,
+    rebalances: &RebalanceOpts,
+) -> anyhow::Result<()> {
+    let pg_client = pool.get().await?;
+    let window = TimeWindow::trailing(as_of, Duration::days(1));
+    let mut message = format!("Report as of {}\n\n", as_of.to_rfc3339());
+    message += "===========24 HOUR SUMMARY===========\n";
+    message += &build(&pg_client, window, rebalances, ReportDetail::Full).await?;
+    println!("{message}");
+    Ok(())
+}
+
+/// The report of `window` from the event tables, see [`report`].
+pub(crate) async fn build(
+    pg_client: &Client,
+    window: TimeWindow,
+    rebalances: &RebalanceOpts,
+    detail: ReportDetail,
+) -> anyhow::Result<String> {
+    let (start, end) = window.naive_bounds();
+    let mut message = String::new();
+    if detail == ReportDetail::Terse {
+        let row = pg_client
+            .query_one(
+                &format!(
+                    "
+                    WITH payments AS ({PAYMENTS_QUERY})
+                    SELECT
+                        COUNT(*) FILTER (WHERE outcome = 'succeeded'),
+                        COUNT(*) FILTER (WHERE outcome = 'failed'),
+                        COALESCE(SUM(fee_msat) FILTER (WHERE outcome = 'succeeded'), 0)::TEXT
+                    FROM payments
+                    WHERE ts >= $1 AND ts < $2 AND NOT ({})
+                    ",
+                    rebalances.condition()
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        let succeeded: i64 = row.get(0);
+        let failed: i64 = row.get(1);
+        let fees: Msat = row.get(2);
+        message += format!("Payments - Succeeded: {succeeded}, Failed: {failed}\n").as_str();
+        message += format!("Fees: {fees}\n").as_str();
+        return Ok(message);
+    }
+
+    if detail == ReportDetail::Full {
+        for direction in ["outgoing", "incoming"] {
+            let row = pg_client
+                .query_one(
+                    &format!(
+                        "
+                        WITH payments AS ({PAYMENTS_QUERY})
+                        SELECT
+                            COALESCE(AVG(EXTRACT(EPOCH FROM ts - started_ts) * 1000), 0)::FLOAT8,
+                            COALESCE(PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM ts - started_ts) * 1000), 0)::FLOAT8,
+                            COALESCE(SUM(fee_msat), 0)::TEXT
+                        FROM payments
+                        WHERE direction = $1 AND outcome = 'succeeded' AND ts >= $2 AND ts < $3
+                            AND NOT ({})
+                        ",
+                        rebalances.condition()
+                    ),
+                    &[&direction, &start, &end],
+                )
+                .await?;
+            let average_latency: f64 = row.get(0);
+            let median_latency: f64 = row.get(1);
+            let fees: Msat = row.get(2);
+            let label = if direction == "outgoing" {
+                "Outgoing"
+            } else {
+                "Incoming"
+            };
+            message += format!("{label} Average Latency: {}ms\n", average_latency as u64).as_str();
+            message += format!("{label} Median Latency: {}ms\n", median_latency as u64).as_str();
+            message += format!("{label} Fees: {fees}\n").as_str();
+        }
+        message += "\n";
+    }
+
+    let mut activities = FederationActivity::query(pg_client, window)
+        .await?
+        .into_values()
+        .collect::<Vec<_>>();
+    activities.sort_by(|a, b| a.federation_name.cmp(&b.federation_name));
+    for activity in activities {
+        message += format!("{activity}\n").as_str();
+    }
+    if detail == ReportDetail::Redacted {
+        return Ok(message);
+    }
+
+    let peg_outs = PegOutSummary::query(pg_client, window).await?;
+    if !peg_outs.is_empty() {
+        message += format!("{peg_outs}").as_str();
+    }
+
+    let rebalance_costs = RebalanceCosts::query(pg_client, window, rebalances).await?;
+    if !rebalance_costs.is_empty() {
+        message += format!("{rebalance_costs}").as_str();
+    }
+
+    let leaderboard = WeeklyLeaderboard::query(pg_client, window.end, rebalances).await?;
+    message += format!("{leaderboard}").as_str();
+    Ok(message)
+}
+
+/// A federation's payments and refunds in a window, counted from the event
+/// tables, so a summary covers the whole window whichever run sends it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FederationActivity {
+    pub federation_name: String,
+    /// Shown when set, it is only known to the gateway at the time of a run
+    pub balance: Option<bitcoin::Amount>,
+    pub outgoing_succeeded: u64,
+    pub outgoing_failed: u64,
+    pub incoming_succeeded: u64,
+    pub incoming_failed: u64,
+    pub refunds: u64,
+}
+
+impl FederationActivity {
+    /// The federations with payments in `window`, by federation id.
+    pub async fn query(
+        pg_client: &Client,
+        window: TimeWindow,
+    ) -> anyhow::Result<BTreeMap<String, Self>> {
+        let (start, end) = window.naive_bounds();
+        let rows = pg_client
+            .query(
+                &format!(
+                    "
+                    WITH payments AS ({PAYMENTS_QUERY}),
+                    refunds AS (
+                        SELECT federation_id, ts FROM lnv1_refund_claimed
+                        UNION ALL
+                        SELECT federation_id, ts FROM lnv2_refund_claimed
+                    )
+                    SELECT
+                        federation_id,
+                        MAX(federation_name),
+                        COUNT(*) FILTER (WHERE direction = 'outgoing' AND outcome = 'succeeded'),
+                        COUNT(*) FILTER (WHERE direction = 'outgoing' AND outcome = 'failed'),
+                        COUNT(*) FILTER (WHERE direction = 'incoming' AND outcome = 'succeeded'),
+                        COUNT(*) FILTER (WHERE direction = 'incoming' AND outcome = 'failed'),
+                        (SELECT COUNT(*) FROM refunds r WHERE r.federation_id = p.federation_id AND r.ts >= $1 AND r.ts < $2)
+                    FROM payments p
+                    WHERE ts >= $1 AND ts < $2
+                    GROUP BY federation_id
+                    "
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let count = |idx| row.get::<_, i64>(idx) as u64;
+                (
+                    row.get(0),
+                    Self {
+                        federation_name: row.get(1),
+                        balance: None,
+                        outgoing_succeeded: count(2),
+                        outgoing_failed: count(3),
+                        incoming_succeeded: count(4),
+                        incoming_failed: count(5),
+                        refunds: count(6),
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Number of payments that completed or failed.
+    pub fn payment_count(&self) -> u64 {
+        self.outgoing_succeeded + self.incoming_succeeded + self.failure_count()
+    }
+
+    pub fn failure_count(&self) -> u64 {
+        self.outgoing_failed + self.incoming_failed
+    }
+
+    /// The counts without the balance, blurred with `noise`, for chats
+    /// outside the gateway operator.
+    pub fn redacted(&self, noise: StatsNoise) -> String {
+        format!(
+            "Federation: {}\n\
+            Outgoing Payments - Succeeded: {}, Failed: {}\n\
+            Incoming Payments - Succeeded: {}, Failed: {}\n\
+            Refunds Issued: {}\n",
+            self.federation_name,
+            noise.apply(self.outgoing_succeeded),
+            noise.apply(self.outgoing_failed),
+            noise.apply(self.incoming_succeeded),
+            noise.apply(self.incoming_failed),
+            noise.apply(self.refunds),
+        )
+    }
+}
+
+impl fmt::Display for FederationActivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Federation: {}", self.federation_name)?;
+        if let Some(balance) = self.balance {
+            writeln!(f, "Balance: {balance}")?;
+        }
+        writeln!(
+            f,
+            "Outgoing Payments - Succeeded: {}, Failed: {}",
+            self.outgoing_succeeded, self.outgoing_failed
+        )?;
+        writeln!(
+            f,
+            "Incoming Payments - Succeeded: {}, Failed: {}",
+            self.incoming_succeeded, self.incoming_failed
+        )?;
+        writeln!(f, "Refunds Issued: {}", self.refunds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+
+    use super::FederationActivity;
+    use crate::{test_db, time_window::TimeWindow};
+
+    /// A daemon's run that sends the summary may have ingested none of the
+    /// window's payments itself.
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn activity_covers_the_whole_window() {
+        let pg_client = test_db::connect().await;
+        for (log_id, ts, contract_id) in [
+            (1, "2024-05-01 01:00:00", "c1"),
+            (2, "2024-05-01 23:00:00", "c2"),
+            (3, "2024-05-02 01:00:00", "c3"),
+        ] {
+            pg_client
+                .execute(
+                    &format!(
+                        "INSERT INTO lnv1_outgoing_payment_succeeded (gateway_epoch, log_id, ts, federation_id, federation_name, contract_id, contract_amount, gateway_key, payment_hash, timelock, user_key, preimage) VALUES (0, {log_id}, '{ts}', 'fed', 'Federation', '{contract_id}', 1000, 'gk', 'hash', 0, 'uk', 'preimage')"
+                    ),
+                    &[],
+                )
+                .await
+                .unwrap();
+        }
+
+        let window = TimeWindow::trailing(
+            DateTime::parse_from_rfc3339("2024-05-02T00:00:00Z")
+                .unwrap()
+                .into(),
+            Duration::days(1),
+        );
+        let activities = FederationActivity::query(&pg_client, window).await.unwrap();
+        let activity = &activities["fed"];
+        assert_eq!(activity.federation_name, "Federation");
+        assert_eq!(activity.outgoing_succeeded, 2);
+        assert_eq!(activity.payment_count(), 2);
+        assert_eq!(activity.failure_count(), 0);
+    }
+}
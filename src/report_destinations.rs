@@ -0,0 +1,212 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::info;
+
+use crate::{
+    TelegramClient,
+    rebalance::RebalanceOpts,
+    report::{self, ReportDetail},
+    time_window::TimeWindow,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ReportPeriod {
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl ReportPeriod {
+    fn length(self) -> Duration {
+        match self {
+            ReportPeriod::Hourly => Duration::hours(1),
+            ReportPeriod::Daily => Duration::days(1),
+            ReportPeriod::Weekly => Duration::weeks(1),
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ReportPeriod::Hourly => "HOURLY REPORT",
+            ReportPeriod::Daily => "DAILY REPORT",
+            ReportPeriod::Weekly => "WEEKLY REPORT",
+        }
+    }
+}
+
+/// A chat that gets its own report, built from the event tables, once per
+/// period (`<chat_id>:<hourly|daily|weekly>[:<terse|redacted|full>]`), e.g. a
+/// terse hourly report to an ops channel next to the daily summary.
+#[derive(Debug, Clone)]
+pub(crate) struct ReportDestination {
+    pub chat_id: String,
+    period: ReportPeriod,
+    detail: ReportDetail,
+}
+
+impl FromStr for ReportDestination {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(chat_id), Some(period), detail, None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("Expected <chat_id>:<period>[:<detail>], got {s}");
+        };
+        Ok(Self {
+            chat_id: chat_id.to_string(),
+            period: ReportPeriod::from_str(period, true)
+                .map_err(|err| anyhow::anyhow!("Invalid report period in {s}: {err}"))?,
+            detail: detail
+                .map(|detail| ReportDetail::from_str(detail, true))
+                .transpose()
+                .map_err(|err| anyhow::anyhow!("Invalid report detail in {s}: {err}"))?
+                .unwrap_or(ReportDetail::Full),
+        })
+    }
+}
+
+impl fmt::Display for ReportDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:?}:{:?}", self.chat_id, self.period, self.detail)
+    }
+}
+
+impl ReportDestination {
+    /// Reports from the end of the last report, so consecutive reports
+    /// neither overlap nor leave gaps even if a run was missed, or one
+    /// period back for the first report.
+    fn window(&self, last_sent: Option<NaiveDateTime>, now: DateTime<Utc>) -> TimeWindow {
+        match last_sent {
+            Some(last_sent) => TimeWindow::trailing(now, now - last_sent.and_utc()),
+            None => TimeWindow::trailing(now, self.period.length()),
+        }
+    }
+
+    /// Whether the period has passed since the last report. Runs scheduled
+    /// once per period never start at exactly the same second, so a tenth of
+    /// the period early still counts.
+    fn is_due(&self, last_sent: Option<NaiveDateTime>, now: DateTime<Utc>) -> bool {
+        last_sent.is_none_or(|last_sent| now - last_sent.and_utc() >= self.period.length() * 9 / 10)
+    }
+}
+
+/// Key of the main summary in `report_deliveries`. Destinations always
+/// contain a `:`, so it can't clash with one.
+pub(crate) const DAILY_SUMMARY: &str = "daily-summary";
+
+/// When the report under `key` was sent last.
+async fn last_sent(pg_client: &Client, key: &str) -> anyhow::Result<Option<NaiveDateTime>> {
+    Ok(pg_client
+        .query_opt(
+            "SELECT sent_at FROM report_deliveries WHERE destination = $1",
+            &[&key],
+        )
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// Records that the report under `key` was sent at `now`.
+pub(crate) async fn record_sent(
+    pg_client: &Client,
+    key: &str,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO report_deliveries (destination, sent_at) VALUES ($1, $2) ON CONFLICT (destination) DO UPDATE SET sent_at = EXCLUDED.sent_at",
+            &[&key, &now.naive_utc()],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Whether the report under `key` was already sent on the UTC day of `now`.
+/// Tied to the day rather than to 24 hours since the last one, so a report
+/// sent by the first run of a day doesn't creep earlier day by day.
+pub(crate) async fn sent_today(
+    pg_client: &Client,
+    key: &str,
+    now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+    Ok(last_sent(pg_client, key)
+        .await?
+        .is_some_and(|last_sent| last_sent.date() == now.date_naive()))
+}
+
+/// Sends the report of every destination whose period has passed, and
+/// records when, in `report_deliveries`, unless `dry_run`.
+pub(crate) async fn send_due(
+    pg_client: &Client,
+    telegram_client: &TelegramClient,
+    destinations: &[ReportDestination],
+    rebalances: &RebalanceOpts,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    for destination in destinations {
+        let key = destination.to_string();
+        let last_sent = last_sent(pg_client, &key).await?;
+        if !destination.is_due(last_sent, now) {
+            continue;
+        }
+
+        let window = destination.window(last_sent, now);
+        let mut message = format!(
+            "==========={}===========\n{window}\n",
+            destination.period.title()
+        );
+        message += &report::build(pg_client, window, rebalances, destination.detail).await?;
+        telegram_client
+            .send_telegram_message_to(&destination.chat_id, message)
+            .await;
+        info!(destination = %key, "Sent report");
+
+        if !dry_run {
+            record_sent(pg_client, &key, now).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::{DAILY_SUMMARY, record_sent, sent_today};
+    use crate::test_db;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn daily_summary_is_due_once_per_utc_day() {
+        let pg_client = test_db::connect().await;
+        let now = at("2024-05-01T23:50:00Z");
+        assert!(!sent_today(&pg_client, DAILY_SUMMARY, now).await.unwrap());
+
+        record_sent(&pg_client, DAILY_SUMMARY, now).await.unwrap();
+        assert!(
+            sent_today(&pg_client, DAILY_SUMMARY, at("2024-05-01T23:59:00Z"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !sent_today(&pg_client, DAILY_SUMMARY, at("2024-05-02T00:05:00Z"))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !sent_today(&pg_client, "chat:Daily:Full", now)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+
+use crate::time_window::TimeWindow;
+
+/// Payments are not shown individually beyond this many.
+const MAX_LISTED: usize = 10;
+
+/// Started events per payment hash (payment image for LNv2). LNv1 outgoing
+/// started events only carry the contract id, the hash is taken from the
+/// terminal event of the same contract.
+const ATTEMPTS_QUERY: &str = "
+    SELECT st.federation_name, 'outgoing' AS direction, COALESCE(s.payment_hash, f.payment_hash) AS payment_hash, st.ts
+    FROM lnv1_outgoing_payment_started st
+    LEFT JOIN lnv1_outgoing_payment_succeeded s ON s.contract_id = st.contract_id AND s.federation_id = st.federation_id
+    LEFT JOIN lnv1_outgoing_payment_failed f ON f.contract_id = st.contract_id AND f.federation_id = st.federation_id
+    UNION ALL
+    SELECT federation_name, 'incoming', payment_hash, ts FROM lnv1_incoming_payment_started
+    UNION ALL
+    SELECT federation_name, 'outgoing', payment_image, ts FROM lnv2_outgoing_payment_started
+    UNION ALL
+    SELECT federation_name, 'incoming', payment_image, ts FROM lnv2_incoming_payment_started
+";
+
+#[derive(Debug, Clone)]
+struct RetriedPayment {
+    federation_name: String,
+    direction: String,
+    payment_hash: String,
+    attempts: i64,
+}
+
+/// Payments that were started more than once within a window. Clients that
+/// retry the same invoice in a loop inflate the failure counts and load the
+/// gateway, so payments with at least `min_attempts` attempts are reported
+/// as retry storms.
+pub(crate) struct RetryStorms {
+    /// Started events beyond the first one, over all payments.
+    retries: i64,
+    storms: Vec<RetriedPayment>,
+}
+
+impl RetryStorms {
+    pub async fn query(
+        pg_client: &Client,
+        window: TimeWindow,
+        min_attempts: i64,
+    ) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let rows = pg_client
+            .query(
+                &format!(
+                    "
+                    WITH attempts AS ({ATTEMPTS_QUERY})
+                    SELECT federation_name, direction, payment_hash, COUNT(*) AS attempts
+                    FROM attempts
+                    WHERE payment_hash IS NOT NULL AND ts >= $1 AND ts < $2
+                    GROUP BY federation_name, direction, payment_hash
+                    HAVING COUNT(*) > 1
+                    ORDER BY attempts DESC
+                    "
+                ),
+                &[&start, &end],
+            )
+            .await?;
+        let retried = rows
+            .iter()
+            .map(|row| RetriedPayment {
+                federation_name: row.get(0),
+                direction: row.get(1),
+                payment_hash: row.get(2),
+                attempts: row.get(3),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            retries: retried.iter().map(|payment| payment.attempts - 1).sum(),
+            storms: retried
+                .into_iter()
+                .filter(|payment| payment.attempts >= min_attempts)
+                .collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storms.is_empty()
+    }
+}
+
+impl fmt::Display for RetryStorms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "===========RETRY STORMS===========")?;
+        writeln!(
+            f,
+            "Payments: {}, Retries Across All Payments: {}",
+            self.storms.len(),
+            self.retries
+        )?;
+        for payment in self.storms.iter().take(MAX_LISTED) {
+            writeln!(
+                f,
+                "{}: {} {} - {} attempts",
+                payment.federation_name, payment.direction, payment.payment_hash, payment.attempts
+            )?;
+        }
+        writeln!(f)
+    }
+}
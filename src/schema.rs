@@ -0,0 +1,579 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+use tracing::info;
+
+/// Every table this binary reads or writes, with the columns it uses. Keep
+/// this in sync with the migrations.
+pub(crate) const EXPECTED_SCHEMA: &[(&str, &[&str])] = &[
+    (
+        "back_dated_events",
+        &[
+            "event_log_id",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "event_ts",
+            "newest_ts",
+            "accepted",
+            "recorded_at",
+        ],
+    ),
+    (
+        "checked_gaps",
+        &[
+            "federation_id",
+            "gateway_epoch",
+            "first_log_id",
+            "last_log_id",
+            "checked_at",
+        ],
+    ),
+    (
+        "computed_columns",
+        &["table_name", "column_name", "expression", "created_at"],
+    ),
+    (
+        "dead_letter_events",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "module",
+            "kind",
+            "payload",
+            "error",
+        ],
+    ),
+    (
+        "etl_cursor",
+        &[
+            "federation_id",
+            "gateway_epoch",
+            "last_log_id",
+            "updated_at",
+        ],
+    ),
+    (
+        "etl_runs",
+        &[
+            "id",
+            "started_at",
+            "finished_at",
+            "gateway_epoch",
+            "status",
+            "error",
+            "config_hash",
+            "config",
+            "ingest_stats",
+            "backfill_checkpoints",
+        ],
+    ),
+    (
+        "federation_config_changes",
+        &[
+            "ts",
+            "federation_id",
+            "federation_name",
+            "field",
+            "old_value",
+            "new_value",
+        ],
+    ),
+    (
+        "federation_config_snapshots",
+        &[
+            "ts",
+            "federation_id",
+            "federation_name",
+            "lightning_base_msat",
+            "lightning_ppm",
+            "transaction_base_msat",
+            "transaction_ppm",
+            "tos_url",
+            "modules",
+        ],
+    ),
+    ("gateway_epochs", &["gateway_epoch", "created_at", "reason"]),
+    (
+        "gateway_ledger",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "module",
+            "kind",
+            "operation_id",
+            "direction",
+            "amount_msat",
+            "fee_msat",
+            "market_fee_rate",
+        ],
+    ),
+    (
+        "gateway_summary_snapshots",
+        &[
+            "etl_run_id",
+            "ts",
+            "gateway_epoch",
+            "window_start",
+            "window_end",
+            "direction",
+            "average_latency_ms",
+            "median_latency_ms",
+            "total_fees_msat",
+            "total_success",
+            "total_failure",
+        ],
+    ),
+    (
+        "gateway_uptime",
+        &["ts", "reachable", "latency_ms", "error"],
+    ),
+    (
+        "ingestion_watermarks",
+        &["federation_id", "gateway_epoch", "watermark", "updated_at"],
+    ),
+    (
+        "lnv1_complete_lightning_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "payment_hash",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_contract_cancelled",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "contract_id",
+            "contract_amount",
+            "reason",
+        ],
+    ),
+    (
+        "lnv1_incoming_payment_failed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "payment_hash",
+            "error_reason",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_incoming_payment_started",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "contract_id",
+            "contract_amount",
+            "invoice_amount",
+            "operation_id",
+            "payment_hash",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_incoming_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "payment_hash",
+            "preimage",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_outgoing_payment_failed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "contract_id",
+            "contract_amount",
+            "gateway_key",
+            "payment_hash",
+            "timelock",
+            "user_key",
+            "error_reason",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_outgoing_payment_started",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "contract_id",
+            "invoice_amount",
+            "operation_id",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_outgoing_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "contract_id",
+            "contract_amount",
+            "gateway_key",
+            "payment_hash",
+            "timelock",
+            "user_key",
+            "preimage",
+            "gateway_epoch",
+        ],
+    ),
+    (
+        "lnv1_refund_claimed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "contract_id",
+            "amount",
+        ],
+    ),
+    (
+        "lnv2_complete_lightning_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+        ],
+    ),
+    (
+        "lnv2_contract_cancelled",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+            "amount",
+            "reason",
+        ],
+    ),
+    (
+        "lnv2_incoming_payment_failed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+            "error",
+        ],
+    ),
+    (
+        "lnv2_incoming_payment_started",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "amount",
+            "claim_pk",
+            "ephemeral_pk",
+            "expiration",
+            "payment_image",
+            "refund_pk",
+            "invoice_amount",
+            "operation_start",
+        ],
+    ),
+    (
+        "lnv2_incoming_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+        ],
+    ),
+    (
+        "lnv2_outgoing_payment_failed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+            "error",
+        ],
+    ),
+    (
+        "lnv2_outgoing_payment_started",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "invoice_amount",
+            "max_delay",
+            "min_contract_amount",
+            "operation_start",
+            "amount",
+            "claim_pk",
+            "ephemeral_pk",
+            "expiration",
+            "payment_image",
+            "refund_pk",
+        ],
+    ),
+    (
+        "lnv2_outgoing_payment_succeeded",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+            "target_federation",
+        ],
+    ),
+    (
+        "lnv2_refund_claimed",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_image",
+            "amount",
+        ],
+    ),
+    (
+        "node_payments",
+        &[
+            "node",
+            "direction",
+            "payment_hash",
+            "ts",
+            "amount_msat",
+            "fee_msat",
+            "status",
+            "collected_at",
+        ],
+    ),
+    (
+        "onchain_transactions",
+        &[
+            "federation_id",
+            "txid",
+            "federation_name",
+            "gateway_epoch",
+            "direction",
+            "operation_id",
+            "first_seen",
+            "block_height",
+            "confirmations",
+            "last_checked",
+        ],
+    ),
+    ("output_sequences", &["output", "last_sequence"]),
+    (
+        "paused_federations",
+        &["federation_id", "reason", "paused_at"],
+    ),
+    (
+        "payment_latency_splits",
+        &[
+            "terminal_log_id",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "payment_hash",
+            "started_ts",
+            "lightning_started_ts",
+            "completed_ts",
+            "gateway_ms",
+            "lightning_ms",
+        ],
+    ),
+    (
+        "raw_events",
+        &[
+            "log_id",
+            "ts",
+            "federation_id",
+            "federation_name",
+            "gateway_epoch",
+            "module",
+            "kind",
+            "payload",
+            "payload_cbor_zstd",
+        ],
+    ),
+    ("rebalance_payments", &["payment_hash", "note", "tagged_at"]),
+    (
+        "reconciliation_records",
+        &[
+            "id",
+            "imported_at",
+            "source",
+            "external_id",
+            "ts",
+            "payment_hash",
+            "amount_msat",
+            "fee_msat",
+            "status",
+            "matched_federation_id",
+            "matched_log_id",
+            "matched_gateway_epoch",
+        ],
+    ),
+    ("report_deliveries", &["destination", "sent_at"]),
+    (
+        "selftest",
+        &[
+            "id",
+            "ts",
+            "etl_run_id",
+            "federation_id",
+            "passed",
+            "duration_ms",
+            "error",
+        ],
+    ),
+    (
+        "slo_evaluations",
+        &[
+            "ts",
+            "slo",
+            "window_start",
+            "window_end",
+            "total",
+            "bad",
+            "burn_rate",
+            "budget_remaining",
+        ],
+    ),
+    ("table_sizes", &["ts", "table_name", "total_bytes"]),
+    (
+        "telegram_messages",
+        &["id", "ts", "chat_id", "preview", "sent", "digested_at"],
+    ),
+    (
+        "webhook_dead_letters",
+        &[
+            "id",
+            "created_at",
+            "gateway_epoch",
+            "federation_id",
+            "log_id",
+            "event",
+            "event_zstd",
+            "attempts",
+            "error",
+            "delivered_at",
+        ],
+    ),
+];
+
+/// Tables keyed by `log_id` that aren't event tables: the archive has a copy
+/// of every event, and webhook dead letters reference one.
+const NON_EVENT_TABLES: &[&str] = &["raw_events", "webhook_dead_letters"];
+
+/// Tables with one row per ingested event, keyed by `log_id`.
+pub(crate) fn event_tables() -> impl Iterator<Item = (&'static str, &'static [&'static str])> {
+    EXPECTED_SCHEMA
+        .iter()
+        .copied()
+        .filter(|(table, columns)| columns.contains(&"log_id") && !NON_EVENT_TABLES.contains(table))
+}
+
+/// Compares the tables and columns this binary uses with the database before
+/// anything is written, so an outdated schema (or an outdated binary) fails
+/// fast instead of halfway through a run.
+///
+/// Missing tables or columns mean the migrations haven't been applied, e.g.
+/// because of `--migrate off`. Unknown columns are only a problem if they
+/// are `NOT NULL` without a default, since inserts from this binary would
+/// leave them empty.
+pub(crate) async fn check_schema(pg_client: &Client) -> anyhow::Result<()> {
+    let rows = pg_client
+        .query(
+            "SELECT table_name, column_name, is_nullable = 'NO' AND column_default IS NULL FROM information_schema.columns WHERE table_schema = current_schema()",
+            &[],
+        )
+        .await?;
+    let mut actual: BTreeMap<String, BTreeMap<String, bool>> = BTreeMap::new();
+    for row in &rows {
+        actual
+            .entry(row.get(0))
+            .or_default()
+            .insert(row.get(1), row.get(2));
+    }
+
+    let mut problems = Vec::new();
+    for (table, columns) in EXPECTED_SCHEMA {
+        let Some(actual_columns) = actual.get(*table) else {
+            problems.push(format!("table {table} is missing"));
+            continue;
+        };
+        let expected = columns.iter().copied().collect::<BTreeSet<_>>();
+        for column in &expected {
+            if !actual_columns.contains_key(*column) {
+                problems.push(format!("column {table}.{column} is missing"));
+            }
+        }
+        for (column, required) in actual_columns {
+            if *required && !expected.contains(column.as_str()) {
+                problems.push(format!(
+                    "column {table}.{column} is NOT NULL without a default and unknown to this version"
+                ));
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        problems.is_empty(),
+        "Database schema is incompatible with this version: {}. Apply the migrations with init-db, or upgrade this binary if the database is newer.",
+        problems.join(", ")
+    );
+    info!(
+        tables = EXPECTED_SCHEMA.len(),
+        "Database schema is compatible"
+    );
+    Ok(())
+}
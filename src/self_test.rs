@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use deadpool_postgres::Pool;
+use fedimint_core::anyhow;
+use tracing::{error, info};
+
+use crate::{EtlRun, FederationEventProcessor};
+
+/// Runs the processor's self-test before the real ingestion and records the
+/// outcome in `selftest`. Reading the row back checks that committed writes
+/// arrive as well, since the synthetic event itself is rolled back. A dry run
+/// only runs the rolled back part. A failed self-test fails the run before
+/// anything is ingested.
+pub(crate) async fn run(
+    pool: &Pool,
+    etl_run: &EtlRun,
+    processor: &mut FederationEventProcessor,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let result = processor.self_test().await;
+    let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+    if !etl_run.is_dry_run() {
+        record(pool, etl_run, processor, &result, duration_ms).await?;
+    }
+
+    match result {
+        Ok(()) => {
+            info!(duration_ms, "Self-test passed");
+            Ok(())
+        }
+        Err(err) => {
+            error!("Self-test failed: {err:#}");
+            Err(err.context("Self-test failed"))
+        }
+    }
+}
+
+async fn record(
+    pool: &Pool,
+    etl_run: &EtlRun,
+    processor: &FederationEventProcessor,
+    result: &anyhow::Result<()>,
+    duration_ms: f64,
+) -> anyhow::Result<()> {
+    let error = result.as_ref().err().map(|err| format!("{err:#}"));
+    let pg_client = pool.get().await?;
+    let row = pg_client
+        .query_one(
+            "INSERT INTO selftest (ts, etl_run_id, federation_id, passed, duration_ms, error) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            &[
+                &etl_run.clock().now().naive_utc(),
+                &etl_run.id(),
+                &processor.federation_id().to_string(),
+                &result.is_ok(),
+                &duration_ms,
+                &error,
+            ],
+        )
+        .await?;
+    let id: i64 = row.get(0);
+    anyhow::ensure!(
+        pg_client
+            .query_opt("SELECT 1 FROM selftest WHERE id = $1", &[&id])
+            .await?
+            .is_some(),
+        "Self-test result did not arrive in selftest"
+    );
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::EventLogId;
+use tokio_postgres::Client;
+
+use crate::{
+    GatewayLedgerEntry, LNv1CompleteLightningPaymentSucceeded, LNv1IncomingPaymentFailed,
+    LNv1IncomingPaymentStarted, LNv1IncomingPaymentSucceeded, LNv1OutgoingPaymentFailed,
+    LNv1OutgoingPaymentStarted, LNv1OutgoingPaymentSucceeded,
+    incoming::{
+        LNv2CompleteLightningPaymentSucceeded, LNv2IncomingPaymentFailed,
+        LNv2IncomingPaymentStarted, LNv2IncomingPaymentSucceeded,
+    },
+    onchain::OnchainTransaction,
+    outgoing::{
+        LNv2OutgoingPaymentFailed, LNv2OutgoingPaymentStarted, LNv2OutgoingPaymentSucceeded,
+    },
+    refund::{LNv1ContractCancelled, LNv1RefundClaimed, LNv2ContractCancelled, LNv2RefundClaimed},
+};
+
+/// The payment log entry an event was parsed from, stored with every event.
+#[derive(Debug, Clone)]
+pub(crate) struct EventOrigin {
+    pub log_id: EventLogId,
+    /// Microseconds since the epoch, as in the payment log.
+    pub timestamp: u64,
+    pub federation_id: FederationId,
+    pub federation_name: String,
+    pub gateway_epoch: i32,
+}
+
+/// An event parsed into the type of its module and kind.
+#[derive(Debug, Clone)]
+pub(crate) enum TypedEvent {
+    LNv1OutgoingPaymentStarted(LNv1OutgoingPaymentStarted),
+    LNv1OutgoingPaymentSucceeded(LNv1OutgoingPaymentSucceeded),
+    LNv1OutgoingPaymentFailed(LNv1OutgoingPaymentFailed),
+    LNv1IncomingPaymentStarted(LNv1IncomingPaymentStarted),
+    LNv1IncomingPaymentSucceeded(LNv1IncomingPaymentSucceeded),
+    LNv1IncomingPaymentFailed(LNv1IncomingPaymentFailed),
+    LNv1CompleteLightningPaymentSucceeded(LNv1CompleteLightningPaymentSucceeded),
+    LNv1ContractCancelled(LNv1ContractCancelled),
+    LNv1RefundClaimed(LNv1RefundClaimed),
+    LNv2OutgoingPaymentStarted(LNv2OutgoingPaymentStarted),
+    LNv2OutgoingPaymentSucceeded(LNv2OutgoingPaymentSucceeded),
+    LNv2OutgoingPaymentFailed(LNv2OutgoingPaymentFailed),
+    LNv2IncomingPaymentStarted(LNv2IncomingPaymentStarted),
+    LNv2IncomingPaymentSucceeded(LNv2IncomingPaymentSucceeded),
+    LNv2IncomingPaymentFailed(LNv2IncomingPaymentFailed),
+    LNv2CompleteLightningPaymentSucceeded(LNv2CompleteLightningPaymentSucceeded),
+    LNv2ContractCancelled(LNv2ContractCancelled),
+    LNv2RefundClaimed(LNv2RefundClaimed),
+    LedgerEntry(GatewayLedgerEntry),
+    OnchainTransaction(OnchainTransaction),
+}
+
+/// Where parsed events are written. The processor only talks to a sink, so
+/// another backend, or a fanout to several, is a new implementation rather
+/// than a change to every event type.
+pub(crate) trait EventSink {
+    async fn write_event(&mut self, origin: &EventOrigin, event: &TypedEvent)
+    -> anyhow::Result<()>;
+
+    /// Writes several events of the same log entry, e.g. a peg-out's ledger
+    /// entry and its on-chain transaction.
+    async fn write_batch(
+        &mut self,
+        origin: &EventOrigin,
+        events: &[TypedEvent],
+    ) -> anyhow::Result<()> {
+        for event in events {
+            self.write_event(origin, event).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever the sink buffered. Called after every stored log
+    /// entry, before the caller commits its cursor.
+    async fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+/// Writes each event to the table of its kind, within whatever transaction
+/// is open on `pg_client`.
+pub(crate) struct PostgresSink<'a> {
+    pg_client: &'a Client,
+}
+
+impl<'a> PostgresSink<'a> {
+    pub fn new(pg_client: &'a Client) -> Self {
+        Self { pg_client }
+    }
+}
+
+impl EventSink for PostgresSink<'_> {
+    async fn write_event(
+        &mut self,
+        origin: &EventOrigin,
+        event: &TypedEvent,
+    ) -> anyhow::Result<()> {
+        let EventOrigin {
+            log_id,
+            timestamp,
+            federation_id,
+            federation_name,
+            gateway_epoch,
+        } = origin;
+        let (pg_client, timestamp, federation_name, gateway_epoch) = (
+            self.pg_client,
+            *timestamp,
+            federation_name.clone(),
+            *gateway_epoch,
+        );
+        match event {
+            TypedEvent::LNv1OutgoingPaymentStarted(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1OutgoingPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1OutgoingPaymentFailed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1IncomingPaymentStarted(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1IncomingPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1IncomingPaymentFailed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1CompleteLightningPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1ContractCancelled(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv1RefundClaimed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2OutgoingPaymentStarted(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2OutgoingPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2OutgoingPaymentFailed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2IncomingPaymentStarted(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2IncomingPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2IncomingPaymentFailed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2CompleteLightningPaymentSucceeded(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2ContractCancelled(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LNv2RefundClaimed(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            TypedEvent::LedgerEntry(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        log_id,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+            // Keyed by txid, the log id of the first event that saw it isn't
+            // kept
+            TypedEvent::OnchainTransaction(event) => {
+                event
+                    .insert(
+                        pg_client,
+                        timestamp,
+                        federation_id,
+                        federation_name,
+                        gateway_epoch,
+                    )
+                    .await
+            }
+        }
+    }
+
+    /// Everything is written immediately, the caller's transaction decides
+    /// when it becomes visible.
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
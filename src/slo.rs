@@ -0,0 +1,187 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, Utc};
+use fedimint_core::anyhow;
+use tokio_postgres::{Client, types::ToSql};
+
+use crate::{payments::PAYMENTS_QUERY, time_window::TimeWindow};
+
+/// Error budgets are accounted over this many days.
+const BUDGET_PERIOD_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SloKind {
+    /// At least `target` percent of payments succeed.
+    Success { target: f64 },
+    /// At least `percentile` percent of succeeded payments complete within
+    /// `threshold_ms`.
+    Latency { percentile: f64, threshold_ms: f64 },
+}
+
+/// A service level objective for incoming or outgoing payments, given as
+/// `<direction>-success=<percent>` (e.g. `incoming-success=99`) or
+/// `<direction>-p<percentile>-latency-ms=<ms>` (e.g.
+/// `outgoing-p95-latency-ms=5000`).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Slo {
+    name: String,
+    direction: &'static str,
+    kind: SloKind,
+}
+
+impl FromStr for Slo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected <slo>=<value>, got {s}"))?;
+        let (direction, objective) = if let Some(objective) = name.strip_prefix("incoming-") {
+            ("incoming", objective)
+        } else if let Some(objective) = name.strip_prefix("outgoing-") {
+            ("outgoing", objective)
+        } else {
+            anyhow::bail!("SLO {name} must start with incoming- or outgoing-");
+        };
+
+        let value = value.parse::<f64>()?;
+        let kind = if objective == "success" {
+            anyhow::ensure!(
+                0.0 < value && value < 100.0,
+                "Success target must be between 0 and 100, got {value}"
+            );
+            SloKind::Success { target: value }
+        } else if let Some(percentile) = objective
+            .strip_prefix('p')
+            .and_then(|objective| objective.strip_suffix("-latency-ms"))
+        {
+            let percentile = percentile.parse::<f64>()?;
+            anyhow::ensure!(
+                0.0 < percentile && percentile < 100.0,
+                "Latency percentile must be between 0 and 100, got {percentile}"
+            );
+            SloKind::Latency {
+                percentile,
+                threshold_ms: value,
+            }
+        } else {
+            anyhow::bail!("Unknown SLO {name}");
+        };
+
+        Ok(Self {
+            name: s.to_string(),
+            direction,
+            kind,
+        })
+    }
+}
+
+impl fmt::Display for Slo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Slo {
+    /// Fraction of payments that may be bad without violating the objective.
+    fn allowed_bad_fraction(&self) -> f64 {
+        match self.kind {
+            SloKind::Success { target } => 1.0 - target / 100.0,
+            SloKind::Latency { percentile, .. } => 1.0 - percentile / 100.0,
+        }
+    }
+
+    /// Counts all and bad payments within `window`.
+    async fn count(&self, pg_client: &Client, window: TimeWindow) -> anyhow::Result<(i64, i64)> {
+        let (start, end) = window.naive_bounds();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&self.direction, &start, &end];
+        let (filter, bad) = match &self.kind {
+            SloKind::Success { .. } => ("TRUE", "outcome = 'failed'"),
+            SloKind::Latency { threshold_ms, .. } => {
+                params.push(threshold_ms);
+                (
+                    "outcome = 'succeeded' AND started_ts IS NOT NULL",
+                    "EXTRACT(EPOCH FROM ts - started_ts) * 1000 > $4::FLOAT8",
+                )
+            }
+        };
+        let query = format!(
+            "
+            WITH payments AS ({PAYMENTS_QUERY})
+            SELECT COUNT(*), COUNT(*) FILTER (WHERE {bad})
+            FROM payments
+            WHERE direction = $1 AND ts >= $2 AND ts < $3 AND {filter}
+            "
+        );
+        let row = pg_client.query_one(&query, &params).await?;
+        Ok((row.get(0), row.get(1)))
+    }
+
+    /// Computes the burn rate over the day ending at `now` and the share of
+    /// the error budget left over the budget period, and stores both in
+    /// `slo_evaluations` unless `dry_run`.
+    pub async fn evaluate(
+        &self,
+        pg_client: &Client,
+        now: DateTime<Utc>,
+        dry_run: bool,
+    ) -> anyhow::Result<SloEvaluation> {
+        let window = TimeWindow::trailing(now, Duration::days(1));
+        let (total, bad) = self.count(pg_client, window).await?;
+        let (period_total, period_bad) = self
+            .count(
+                pg_client,
+                window.extended_to(Duration::days(BUDGET_PERIOD_DAYS)),
+            )
+            .await?;
+
+        let allowed = self.allowed_bad_fraction();
+        let burn_rate = if total == 0 {
+            0.0
+        } else {
+            bad as f64 / total as f64 / allowed
+        };
+        let budget_remaining = if period_total == 0 {
+            1.0
+        } else {
+            1.0 - period_bad as f64 / period_total as f64 / allowed
+        };
+
+        if !dry_run {
+            pg_client.execute("INSERT INTO slo_evaluations (ts, slo, window_start, window_end, total, bad, burn_rate, budget_remaining) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[&now.naive_utc(), &self.name, &window.start.naive_utc(), &window.end.naive_utc(), &total, &bad, &burn_rate, &budget_remaining]).await?;
+        }
+
+        Ok(SloEvaluation {
+            name: self.name.clone(),
+            total,
+            bad,
+            burn_rate,
+            budget_remaining,
+        })
+    }
+}
+
+/// The state of one SLO at the end of a run.
+#[derive(Debug, Clone)]
+pub(crate) struct SloEvaluation {
+    name: String,
+    total: i64,
+    bad: i64,
+    pub burn_rate: f64,
+    budget_remaining: f64,
+}
+
+impl fmt::Display for SloEvaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} bad, Burn Rate: {:.1}x, Budget Left ({BUDGET_PERIOD_DAYS}d): {:.0}%",
+            self.name,
+            self.bad,
+            self.total,
+            self.burn_rate,
+            self.budget_remaining * 100.0
+        )
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::{NaiveDateTime, Utc};
+use fedimint_core::{anyhow, config::FederationId};
+use fedimint_eventlog::PersistedLogEntry;
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, parse_log_id, raw_events};
+
+/// Stores `entry` as-is in `staging.gateway_events`, a landing table for dbt
+/// style transformations. Unlike the typed tables it takes events of every
+/// module and kind, with the payload as JSONB, plus `_loaded_at` and
+/// `_etl_run_id` for incremental models.
+pub(crate) async fn land(
+    pg_client: &Client,
+    ts: NaiveDateTime,
+    federation_id: FederationId,
+    federation_name: &str,
+    gateway_epoch: GatewayEpoch,
+    etl_run_id: Option<i64>,
+    entry: &PersistedLogEntry,
+) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO staging.gateway_events (federation_id, federation_name, gateway_epoch, log_id, ts, module, kind, payload, _loaded_at, _etl_run_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT DO NOTHING",
+            &[
+                &federation_id.to_string(),
+                &federation_name,
+                &i32::from(gateway_epoch),
+                &parse_log_id(&entry.id())?,
+                &ts,
+                &entry.module.as_ref().map(|(module, _)| module.as_str()),
+                &entry.kind.to_string(),
+                &raw_events::payload_json(entry),
+                &Utc::now().naive_utc(),
+                &etl_run_id,
+            ],
+        )
+        .await?;
+    Ok(())
+}
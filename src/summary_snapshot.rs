@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+use fedimint_core::anyhow;
+use fedimint_gateway_common::{PaymentStats, PaymentSummaryResponse};
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, etl_run::EtlRun, time_window::TimeWindow};
+
+/// Stores the gateway's own `payment_summary` of every run in
+/// `gateway_summary_snapshots`, one row per direction. It's recorded before
+/// ingesting, so there's a gateway-side time series even for periods in which
+/// the event tables stayed empty.
+pub(crate) async fn record(
+    pg_client: &Client,
+    etl_run: &EtlRun,
+    gateway_epoch: GatewayEpoch,
+    now: DateTime<Utc>,
+    window: TimeWindow,
+    summary: &PaymentSummaryResponse,
+) -> anyhow::Result<()> {
+    let (window_start, window_end) = window.naive_bounds();
+    for (direction, stats) in [
+        ("outgoing", &summary.outgoing),
+        ("incoming", &summary.incoming),
+    ] {
+        let PaymentStats {
+            average_latency,
+            median_latency,
+            total_fees,
+            total_success,
+            total_failure,
+        } = stats;
+        pg_client
+            .execute(
+                "INSERT INTO gateway_summary_snapshots (etl_run_id, ts, gateway_epoch, window_start, window_end, direction, average_latency_ms, median_latency_ms, total_fees_msat, total_success, total_failure) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                &[
+                    &etl_run.id(),
+                    &now.naive_utc(),
+                    &i32::from(gateway_epoch),
+                    &window_start,
+                    &window_end,
+                    &direction,
+                    &average_latency.map(|latency| latency.as_secs_f64() * 1000.0),
+                    &median_latency.map(|latency| latency.as_secs_f64() * 1000.0),
+                    &(total_fees.msats as i64),
+                    &(*total_success as i64),
+                    &(*total_failure as i64),
+                ],
+            )
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::{
+    fmt::{self, Write},
+    num::NonZeroU64,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use fedimint_core::anyhow;
+use tokio_postgres::Client;
+
+/// Tables listed in the weekly report, the metrics have all of them.
+const REPORTED_TABLES: usize = 5;
+
+const SECS_PER_DAY: f64 = 86_400.0;
+
+/// Records the on-disk size of every table, indexes and TOAST included, in
+/// `table_sizes`. Growth is derived from these snapshots, since Postgres only
+/// knows the current size.
+pub(crate) async fn record(pg_client: &Client, now: DateTime<Utc>) -> anyhow::Result<()> {
+    pg_client
+        .execute(
+            "INSERT INTO table_sizes (ts, table_name, total_bytes) SELECT $1, CASE WHEN schemaname = 'public' THEN relname ELSE schemaname || '.' || relname END, pg_total_relation_size(relid) FROM pg_stat_user_tables",
+            &[&now.naive_utc()],
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct TableSize {
+    table_name: String,
+    total_bytes: i64,
+    /// `None` until there are snapshots from two different runs.
+    bytes_per_day: Option<f64>,
+}
+
+/// Size of each table and how fast it grew over the past week, with a
+/// projection of when the database outgrows its storage.
+#[derive(Debug)]
+pub(crate) struct TableGrowth {
+    database_bytes: i64,
+    tables: Vec<TableSize>,
+    capacity_bytes: Option<u64>,
+}
+
+impl TableGrowth {
+    pub async fn query(
+        pg_client: &Client,
+        now: DateTime<Utc>,
+        capacity_gib: Option<NonZeroU64>,
+    ) -> anyhow::Result<Self> {
+        let database_bytes: i64 = pg_client
+            .query_one("SELECT pg_database_size(current_database())", &[])
+            .await?
+            .get(0);
+        // Compares the latest snapshot of each table with the oldest one of
+        // the past week, so a few days of history already give a rate
+        let rows = pg_client
+            .query(
+                "
+                WITH latest AS (
+                    SELECT DISTINCT ON (table_name) table_name, ts, total_bytes
+                    FROM table_sizes
+                    WHERE ts <= $2
+                    ORDER BY table_name, ts DESC
+                ), oldest AS (
+                    SELECT DISTINCT ON (table_name) table_name, ts, total_bytes
+                    FROM table_sizes
+                    WHERE ts >= $1 AND ts <= $2
+                    ORDER BY table_name, ts
+                )
+                SELECT latest.table_name, latest.total_bytes, oldest.total_bytes, EXTRACT(EPOCH FROM latest.ts - oldest.ts)::FLOAT8
+                FROM latest
+                LEFT JOIN oldest USING (table_name)
+                ORDER BY latest.total_bytes DESC, latest.table_name
+                ",
+                &[
+                    &(now - Duration::weeks(1)).naive_utc(),
+                    &now.naive_utc(),
+                ],
+            )
+            .await?;
+        let tables = rows
+            .iter()
+            .map(|row| {
+                let total_bytes: i64 = row.get(1);
+                let oldest_bytes: Option<i64> = row.get(2);
+                let elapsed_secs: Option<f64> = row.get(3);
+                let bytes_per_day = oldest_bytes
+                    .zip(elapsed_secs)
+                    .filter(|(_, elapsed_secs)| *elapsed_secs > 0.0)
+                    .map(|(oldest_bytes, elapsed_secs)| {
+                        (total_bytes - oldest_bytes) as f64 * SECS_PER_DAY / elapsed_secs
+                    });
+                TableSize {
+                    table_name: row.get(0),
+                    total_bytes,
+                    bytes_per_day,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            database_bytes,
+            tables,
+            capacity_bytes: capacity_gib.map(|gib| gib.get() << 30),
+        })
+    }
+
+    fn bytes_per_day(&self) -> Option<f64> {
+        self.tables
+            .iter()
+            .filter_map(|table| table.bytes_per_day)
+            .reduce(|total, bytes_per_day| total + bytes_per_day)
+    }
+
+    /// Days until the database fills the configured storage at the growth of
+    /// the past week. `None` without a capacity or if it doesn't grow.
+    fn days_left(&self) -> Option<f64> {
+        let capacity_bytes = self.capacity_bytes?;
+        let bytes_per_day = self.bytes_per_day().filter(|bytes| *bytes > 0.0)?;
+        Some(((capacity_bytes as f64 - self.database_bytes as f64) / bytes_per_day).max(0.0))
+    }
+
+    /// Prometheus gauges, appended to the exposition of `FederationMetrics`.
+    pub fn write_metrics(&self, f: &mut impl Write) -> fmt::Result {
+        writeln!(
+            f,
+            "# HELP etl_gateway_database_bytes Size of the warehouse database"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_database_bytes gauge")?;
+        writeln!(f, "etl_gateway_database_bytes {}", self.database_bytes)?;
+        writeln!(
+            f,
+            "# HELP etl_gateway_table_bytes Size of a table including indexes and TOAST"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_table_bytes gauge")?;
+        for table in &self.tables {
+            writeln!(
+                f,
+                "etl_gateway_table_bytes{{table=\"{}\"}} {}",
+                table.table_name, table.total_bytes
+            )?;
+        }
+        writeln!(
+            f,
+            "# HELP etl_gateway_table_growth_bytes_per_day Growth of a table over the past week"
+        )?;
+        writeln!(f, "# TYPE etl_gateway_table_growth_bytes_per_day gauge")?;
+        for table in &self.tables {
+            if let Some(bytes_per_day) = table.bytes_per_day {
+                writeln!(
+                    f,
+                    "etl_gateway_table_growth_bytes_per_day{{table=\"{}\"}} {bytes_per_day}",
+                    table.table_name
+                )?;
+            }
+        }
+        if let Some(days_left) = self.days_left() {
+            writeln!(
+                f,
+                "# HELP etl_gateway_storage_days_left Days until the database fills its storage at the current growth"
+            )?;
+            writeln!(f, "# TYPE etl_gateway_storage_days_left gauge")?;
+            writeln!(f, "etl_gateway_storage_days_left {days_left}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TableGrowth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "===========STORAGE===========")?;
+        write!(f, "Database: {}", format_bytes(self.database_bytes as f64))?;
+        if let Some(bytes_per_day) = self.bytes_per_day() {
+            write!(f, ", {}/day", format_growth(bytes_per_day))?;
+        }
+        writeln!(f)?;
+        if let Some(capacity_bytes) = self.capacity_bytes {
+            match self.days_left() {
+                Some(days_left) => writeln!(
+                    f,
+                    "Storage of {} full in ~{days_left:.0} days at the current growth",
+                    format_bytes(capacity_bytes as f64)
+                )?,
+                None => writeln!(
+                    f,
+                    "{:.1}% of {} used",
+                    self.database_bytes as f64 * 100.0 / capacity_bytes as f64,
+                    format_bytes(capacity_bytes as f64)
+                )?,
+            }
+        }
+        for table in self.tables.iter().take(REPORTED_TABLES) {
+            write!(
+                f,
+                "{}: {}",
+                table.table_name,
+                format_bytes(table.total_bytes as f64)
+            )?;
+            if let Some(bytes_per_day) = table.bytes_per_day {
+                write!(f, " ({}/day)", format_growth(bytes_per_day))?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value.abs() < 1024.0 {
+            return format!("{value:.1} {unit}");
+        }
+        value /= 1024.0;
+    }
+    format!("{value:.1} TiB")
+}
+
+fn format_growth(bytes: f64) -> String {
+    let sign = if bytes < 0.0 { "-" } else { "+" };
+    format!("{sign}{}", format_bytes(bytes.abs()))
+}
@@ -0,0 +1,56 @@
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::{Client, Config, NoTls};
+
+use crate::migrations;
+
+/// Connects to the database in `TEST_DATABASE_URL` with a schema of its own
+/// for the test, migrated to the latest version, so tests can run in
+/// parallel against one scratch database. The schema is left behind for
+/// inspection. Tests that need a database are marked
+/// `#[ignore = "needs TEST_DATABASE_URL"]` and run with
+/// `cargo test -- --ignored`, and fail when the variable isn't set.
+pub(crate) async fn connect() -> Client {
+    let config = scratch_schema().await;
+    let (pg_client, connection) = config
+        .connect(NoTls)
+        .await
+        .expect("Can connect to TEST_DATABASE_URL");
+    tokio::spawn(connection);
+    pg_client
+}
+
+/// Like [`connect`], for code that takes its connections from a pool.
+pub(crate) async fn pool() -> Pool {
+    let config = scratch_schema().await;
+    let manager = Manager::from_config(
+        config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+    Pool::builder(manager).build().expect("Can build the pool")
+}
+
+/// Creates and migrates a new schema, and returns the config of connections
+/// that use it.
+async fn scratch_schema() -> Config {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set");
+    let mut config: Config = url.parse().expect("TEST_DATABASE_URL is valid");
+    let schema = format!("etl_test_{:08x}", rand::random::<u32>());
+    config.options(format!("-c search_path={schema}"));
+
+    let (pg_client, connection) = config
+        .connect(NoTls)
+        .await
+        .expect("Can connect to TEST_DATABASE_URL");
+    tokio::spawn(connection);
+    pg_client
+        .batch_execute(&format!("CREATE SCHEMA {schema}"))
+        .await
+        .expect("Can create the test schema");
+    migrations::migrate(&pg_client)
+        .await
+        .expect("Can migrate the test schema");
+    config
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::ValueEnum;
+
+/// A half-open time range `[start, end)`, so consecutive windows neither
+/// overlap nor leave gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl TimeWindow {
+    /// The `length` before `end`.
+    pub fn trailing(end: DateTime<Utc>, length: Duration) -> Self {
+        Self {
+            start: end - length,
+            end,
+        }
+    }
+
+    /// The local calendar day before the one `now` falls in, from midnight to
+    /// midnight in the system time zone. Days with a DST change are 23 or 25
+    /// hours long.
+    pub fn previous_local_day(now: DateTime<Utc>) -> Self {
+        let today = now.with_timezone(&Local).date_naive();
+        let yesterday = today.pred_opt().expect("Date out of range");
+        Self {
+            start: local_midnight(yesterday),
+            end: local_midnight(today),
+        }
+    }
+
+    /// The window of the same length that ends where this one starts.
+    pub fn previous(&self) -> Self {
+        Self::trailing(self.start, self.end - self.start)
+    }
+
+    /// Extends the window back to `length` before its end.
+    pub fn extended_to(&self, length: Duration) -> Self {
+        Self::trailing(self.end, length)
+    }
+
+    /// Bounds for `TIMESTAMP` columns, which hold UTC.
+    pub fn naive_bounds(&self) -> (NaiveDateTime, NaiveDateTime) {
+        (self.start.naive_utc(), self.end.naive_utc())
+    }
+
+    /// Bounds in milliseconds since the unix epoch, as the gateway API expects.
+    pub fn millis_bounds(&self) -> (u64, u64) {
+        (
+            self.start.timestamp_millis().max(0) as u64,
+            self.end.timestamp_millis().max(0) as u64,
+        )
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} - {}",
+            self.start.with_timezone(&Local).format("%Y-%m-%d %H:%M %Z"),
+            self.end.with_timezone(&Local).format("%Y-%m-%d %H:%M %Z")
+        )
+    }
+}
+
+/// The first instant of `date` in the system time zone. If a DST change
+/// skips midnight, the day starts at the first local time that exists.
+fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+    (0..=24 * 60)
+        .find_map(|minutes| {
+            Local
+                .from_local_datetime(&(midnight + Duration::minutes(minutes)))
+                .earliest()
+        })
+        .expect("A day has at least one valid local time")
+        .to_utc()
+}
+
+/// Which period the daily summary covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SummaryWindow {
+    /// The 24 hours before the run
+    Trailing,
+    /// The previous calendar day in the local time zone
+    LocalDay,
+}
+
+impl SummaryWindow {
+    pub fn window(self, now: DateTime<Utc>) -> TimeWindow {
+        match self {
+            SummaryWindow::Trailing => TimeWindow::trailing(now, Duration::days(1)),
+            SummaryWindow::LocalDay => TimeWindow::previous_local_day(now),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use fedimint_core::{Amount, BitcoinHash, anyhow, bitcoin, core::OperationId};
+use fedimint_eventlog::Event;
+use fedimint_mint_client::event as mint;
+use fedimint_wallet_client::events as wallet;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{
+    events::EVENT_REGISTRY,
+    ledger::GatewayLedgerEntry,
+    onchain::OnchainTransaction,
+    output::{OutputFormat, Rows},
+};
+
+/// Fields of the ingested mint and wallet events that end up in a table. Keep
+/// this in sync with `GatewayLedgerEntry::parse` and `OnchainTransaction::parse`.
+const CAPTURED_FIELDS: &[(&str, &str, &[&str])] = &[
+    ("mint", "payment-send", &["operation_id", "amount"]),
+    ("mint", "payment-receive", &["operation_id", "amount"]),
+    ("mint", "oob-notes-spent", &["spent_amount"]),
+    ("mint", "oob-notes-reissued", &["amount"]),
+    ("wallet", "payment-send", &["operation_id", "amount", "fee"]),
+    (
+        "wallet",
+        "payment-receive",
+        &["operation_id", "amount", "txid"],
+    ),
+    ("wallet", "deposit-confirmed", &["txid"]),
+    ("wallet", "withdraw-request", &["txid"]),
+    ("wallet", "payment-send-status", &["operation_id", "status"]),
+];
+
+/// The Lightning events are defined by the gateway itself, in
+/// `fedimint-gateway-server`, which this binary doesn't depend on.
+const UNCHECKED_MODULES: &[&str] = &["ln", "lnv2"];
+
+/// An upstream event kind, with the JSON of a sample value if the struct can
+/// be built here.
+struct UpstreamEvent {
+    module: String,
+    kind: String,
+    sample: Option<Value>,
+}
+
+impl UpstreamEvent {
+    fn kind<E: Event>() -> Self {
+        Self {
+            module: E::MODULE.expect("Module events have a module").to_string(),
+            kind: E::KIND.to_string(),
+            sample: None,
+        }
+    }
+
+    fn sample<E: Event + Serialize>(event: E) -> anyhow::Result<Self> {
+        Ok(Self {
+            sample: Some(serde_json::to_value(event)?),
+            ..Self::kind::<E>()
+        })
+    }
+}
+
+fn upstream_events() -> anyhow::Result<Vec<UpstreamEvent>> {
+    let operation_id = OperationId([1; 32]);
+    let txid = bitcoin::Txid::all_zeros();
+    Ok(vec![
+        UpstreamEvent::kind::<mint::NoteCreated>(),
+        UpstreamEvent::kind::<mint::NoteSpent>(),
+        UpstreamEvent::sample(mint::OOBNotesSpent {
+            requested_amount: Amount::from_msats(1_000),
+            spent_amount: Amount::from_msats(1_000),
+            timeout: Duration::from_secs(60),
+            include_invite: false,
+        })?,
+        UpstreamEvent::sample(mint::OOBNotesReissued {
+            amount: Amount::from_msats(1_000),
+        })?,
+        UpstreamEvent::sample(mint::SendPaymentEvent {
+            operation_id,
+            amount: Amount::from_msats(1_000),
+            oob_notes: String::new(),
+        })?,
+        UpstreamEvent::sample(mint::ReceivePaymentEvent {
+            operation_id,
+            amount: Amount::from_msats(1_000),
+        })?,
+        UpstreamEvent::sample(mint::ReceivePaymentUpdateEvent {
+            operation_id,
+            status: mint::ReceivePaymentStatus::Success,
+        })?,
+        UpstreamEvent::sample(wallet::WithdrawRequest { txid })?,
+        UpstreamEvent::sample(wallet::DepositConfirmed {
+            txid,
+            out_idx: 0,
+            amount: Amount::from_msats(1_000),
+        })?,
+        UpstreamEvent::sample(wallet::SendPaymentEvent {
+            operation_id,
+            amount: bitcoin::Amount::from_sat(1),
+            fee: bitcoin::Amount::from_sat(1),
+        })?,
+        UpstreamEvent::sample(wallet::SendPaymentStatusEvent {
+            operation_id,
+            status: wallet::SendPaymentStatus::Success(txid),
+        })?,
+        UpstreamEvent::sample(wallet::ReceivePaymentEvent {
+            operation_id,
+            amount: Amount::from_msats(1_000),
+            txid,
+        })?,
+    ])
+}
+
+/// Runs our parsers against the JSON the upstream event structs serialize to.
+/// Broken parsers, captured fields that disappeared and ingested kinds that
+/// are no longer emitted fail the check. Upstream fields and kinds we don't
+/// capture are only reported, unless `strict` is set.
+pub(crate) fn check_upstream(strict: bool, format: OutputFormat) -> anyhow::Result<()> {
+    let upstream = upstream_events()?;
+    let mut breaking = Vec::new();
+    let mut uncaptured = Vec::new();
+
+    for event in &upstream {
+        let ingested = EVENT_REGISTRY
+            .iter()
+            .filter(|registration| {
+                registration.module == event.module && registration.kind == event.kind
+            })
+            .collect::<Vec<_>>();
+        if ingested.is_empty() {
+            uncaptured.push(format!("{} {}: not ingested", event.module, event.kind));
+            continue;
+        }
+        let Some(sample) = &event.sample else {
+            breaking.push(format!(
+                "{} {}: ingested but no sample to check against",
+                event.module, event.kind
+            ));
+            continue;
+        };
+
+        for registration in ingested {
+            let parsed = match registration.table {
+                "gateway_ledger" => GatewayLedgerEntry::parse(&event.module, &event.kind, sample)
+                    .map(|entry| entry.is_some())
+                    .unwrap_or(false),
+                "onchain_transactions" => OnchainTransaction::parse(&event.kind, sample).is_some(),
+                table => anyhow::bail!("No parser known for table {table}"),
+            };
+            if !parsed {
+                breaking.push(format!(
+                    "{} {}: {} can't parse {sample}",
+                    event.module, event.kind, registration.table
+                ));
+            }
+        }
+
+        let captured = CAPTURED_FIELDS
+            .iter()
+            .find(|(module, kind, _)| *module == event.module && *kind == event.kind)
+            .map(|(_, _, fields)| *fields)
+            .unwrap_or_default();
+        let fields = sample
+            .as_object()
+            .map(|object| object.keys().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for field in captured {
+            if !fields.contains(field) {
+                breaking.push(format!(
+                    "{} {}: field {field} is gone",
+                    event.module, event.kind
+                ));
+            }
+        }
+        for field in fields {
+            if !captured.contains(&field) {
+                uncaptured.push(format!(
+                    "{} {}: field {field} is not captured",
+                    event.module, event.kind
+                ));
+            }
+        }
+    }
+
+    for registration in EVENT_REGISTRY {
+        if UNCHECKED_MODULES.contains(&registration.module) {
+            continue;
+        }
+        if !upstream
+            .iter()
+            .any(|event| event.module == registration.module && event.kind == registration.kind)
+        {
+            breaking.push(format!(
+                "{} {}: no longer emitted upstream",
+                registration.module, registration.kind
+            ));
+        }
+    }
+
+    let mut rows = Rows::new(&["status", "finding"]);
+    for line in &breaking {
+        rows.push(vec![json!("breaking"), json!(line)]);
+    }
+    for line in &uncaptured {
+        rows.push(vec![json!("uncaptured"), json!(line)]);
+    }
+    rows.print(format)?;
+    if matches!(format, OutputFormat::Table) {
+        println!(
+            "Not checked: {} (defined in fedimint-gateway-server)",
+            UNCHECKED_MODULES.join(", ")
+        );
+    }
+
+    if !breaking.is_empty() {
+        anyhow::bail!("Found {} breaking upstream changes", breaking.len());
+    }
+    if strict && !uncaptured.is_empty() {
+        anyhow::bail!(
+            "Found {} uncaptured upstream fields or kinds",
+            uncaptured.len()
+        );
+    }
+    Ok(())
+}
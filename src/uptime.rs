@@ -0,0 +1,82 @@
+use std::{fmt, time::Instant};
+
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use fedimint_core::{anyhow, util::SafeUrl};
+use fedimint_gateway_client::get_info;
+use fedimint_ln_common::client::GatewayApi;
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+use crate::time_window::TimeWindow;
+
+/// Calls `get_info` once and records whether the gateway answered in
+/// `gateway_uptime`. Meant to be scheduled every minute, independent of the
+/// ETL runs. An unreachable gateway is recorded, not returned as an error.
+pub(crate) async fn probe(
+    pool: &Pool,
+    client: &GatewayApi,
+    gateway_addr: &SafeUrl,
+) -> anyhow::Result<()> {
+    let ts = Utc::now().naive_utc();
+    let start = Instant::now();
+    let result = get_info(client, gateway_addr).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let error = match &result {
+        Ok(_) => {
+            info!(latency_ms, "Gateway is reachable");
+            None
+        }
+        Err(err) => {
+            warn!(%err, "Gateway is unreachable");
+            Some(err.to_string())
+        }
+    };
+
+    pool.get()
+        .await?
+        .execute(
+            "INSERT INTO gateway_uptime (ts, reachable, latency_ms, error) VALUES ($1, $2, $3, $4)",
+            &[&ts, &error.is_none(), &latency_ms, &error],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Share of probes within a window that reached the gateway.
+pub(crate) struct GatewayUptime {
+    probes: i64,
+    reachable: i64,
+}
+
+impl GatewayUptime {
+    pub async fn query(pg_client: &Client, window: TimeWindow) -> anyhow::Result<Self> {
+        let (start, end) = window.naive_bounds();
+        let row = pg_client
+            .query_one(
+                "SELECT COUNT(*), COUNT(*) FILTER (WHERE reachable) FROM gateway_uptime WHERE ts >= $1 AND ts < $2",
+                &[&start, &end],
+            )
+            .await?;
+        Ok(Self {
+            probes: row.get(0),
+            reachable: row.get(1),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes == 0
+    }
+}
+
+impl fmt::Display for GatewayUptime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Gateway Uptime: {:.2}% ({} of {} probes)",
+            self.reachable as f64 * 100.0 / self.probes as f64,
+            self.reachable,
+            self.probes
+        )
+    }
+}
@@ -0,0 +1,165 @@
+use fedimint_core::{anyhow, config::FederationId};
+use tokio_postgres::Client;
+
+use crate::{GatewayEpoch, clock::Clock, raw_events};
+
+/// The time up to which every event of a federation is in the event tables,
+/// for downstream jobs that should only run once the data up to a given time
+/// is complete. It is the newest timestamp in `raw_events`, held back to the
+/// event before the first hole that isn't repaired and before the first
+/// dead letter, until the hole is repaired or the dead letter deleted. An
+/// idle federation's watermark stays at its last event.
+pub(crate) struct IngestionWatermark;
+
+impl IngestionWatermark {
+    /// Moves the watermark forward to what the archived events, holes and
+    /// dead letters allow, never back. Within a transaction it is only
+    /// visible once the events up to it are.
+    pub async fn advance(
+        pg_client: &Client,
+        federation_id: FederationId,
+        gateway_epoch: GatewayEpoch,
+        clock: &Clock,
+    ) -> anyhow::Result<()> {
+        let first_gap = raw_events::gaps(pg_client, federation_id, gateway_epoch)
+            .await?
+            .first()
+            .map(|gap| *gap.start());
+        let first_dead_letter: Option<i64> = pg_client
+            .query_one(
+                "SELECT MIN(log_id) FROM dead_letter_events WHERE federation_id = $1 AND gateway_epoch = $2",
+                &[&federation_id.to_string(), &i32::from(gateway_epoch)],
+            )
+            .await?
+            .get(0);
+        let incomplete_from = [first_gap, first_dead_letter]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(i64::MAX);
+
+        pg_client
+            .execute(
+                "INSERT INTO ingestion_watermarks (federation_id, gateway_epoch, watermark, updated_at) SELECT $1, $2, MAX(ts), $4 FROM raw_events WHERE federation_id = $1 AND gateway_epoch = $2 AND log_id < $3 HAVING MAX(ts) IS NOT NULL ON CONFLICT (federation_id, gateway_epoch) DO UPDATE SET watermark = GREATEST(ingestion_watermarks.watermark, EXCLUDED.watermark), updated_at = EXCLUDED.updated_at",
+                &[
+                    &federation_id.to_string(),
+                    &i32::from(gateway_epoch),
+                    &incomplete_from,
+                    &clock.now().naive_utc(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDateTime};
+    use fedimint_core::config::FederationId;
+    use fedimint_eventlog::PersistedLogEntry;
+    use serde_json::json;
+    use tokio_postgres::Client;
+
+    use super::IngestionWatermark;
+    use crate::{
+        GatewayEpoch,
+        clock::Clock,
+        dead_letter::{self, ParseError},
+        raw_events,
+    };
+
+    /// Archives an event logged `log_id` seconds after the epoch.
+    async fn archive(pg_client: &Client, epoch: GatewayEpoch, log_id: i64) -> PersistedLogEntry {
+        let entry: PersistedLogEntry = serde_json::from_value(json!({
+            "id": log_id,
+            "kind": "payment-receive",
+            "module": ["mint", 0],
+            "ts_usecs": log_id * 1_000_000,
+            "payload": {},
+        }))
+        .unwrap();
+        raw_events::archive(
+            pg_client,
+            DateTime::from_timestamp(log_id, 0).map(|ts| ts.naive_utc()),
+            FederationId::dummy(),
+            "Federation",
+            epoch,
+            &entry,
+            raw_events::ArchiveEncoding::Json,
+        )
+        .await
+        .unwrap();
+        entry
+    }
+
+    async fn watermark(pg_client: &Client, epoch: GatewayEpoch) -> Option<i64> {
+        IngestionWatermark::advance(
+            pg_client,
+            FederationId::dummy(),
+            epoch,
+            &Clock::frozen(DateTime::default()),
+        )
+        .await
+        .unwrap();
+        pg_client
+            .query_opt(
+                "SELECT watermark FROM ingestion_watermarks WHERE gateway_epoch = $1",
+                &[&i32::from(epoch)],
+            )
+            .await
+            .unwrap()
+            .map(|row| row.get::<_, NaiveDateTime>(0).and_utc().timestamp())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn stops_before_holes_and_dead_letters() {
+        let pg_client = crate::test_db::connect().await;
+        let epoch = "0".parse::<GatewayEpoch>().unwrap();
+        assert_eq!(watermark(&pg_client, epoch).await, None);
+
+        for log_id in [1, 2, 3] {
+            archive(&pg_client, epoch, log_id).await;
+        }
+        assert_eq!(watermark(&pg_client, epoch).await, Some(3));
+
+        // A hole at 6 holds it at the event before the hole
+        for log_id in [4, 5, 7, 8] {
+            archive(&pg_client, epoch, log_id).await;
+        }
+        assert_eq!(watermark(&pg_client, epoch).await, Some(5));
+
+        raw_events::record_checked_gap(
+            &pg_client,
+            FederationId::dummy(),
+            epoch,
+            &(6..=6),
+            NaiveDateTime::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(watermark(&pg_client, epoch).await, Some(8));
+
+        let entry = archive(&pg_client, epoch, 9).await;
+        archive(&pg_client, epoch, 10).await;
+        dead_letter::insert(
+            &pg_client,
+            &entry,
+            &FederationId::dummy(),
+            "Federation".to_string(),
+            epoch,
+            "payment-receive",
+            &ParseError::new("invalid payload"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(watermark(&pg_client, epoch).await, Some(8));
+
+        pg_client
+            .execute("DELETE FROM dead_letter_events", &[])
+            .await
+            .unwrap();
+        assert_eq!(watermark(&pg_client, epoch).await, Some(10));
+    }
+}
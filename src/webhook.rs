@@ -0,0 +1,328 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use fedimint_core::{
+    anyhow,
+    bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256},
+};
+use serde_json::Value;
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+use crate::{
+    alerts::{Alert, Alerter},
+    compression::{self, ColumnClass},
+};
+
+/// Attempts per delivery before the event is moved to `webhook_dead_letters`.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Deliveries in a row that have to fail permanently to open the circuit.
+const TRIP_AFTER_FAILURES: u32 = 3;
+
+/// Name of the webhook's sequence in `output_sequences`.
+const WEBHOOK_OUTPUT: &str = "webhook";
+
+/// How often a single delivery is attempted while the circuit is open.
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stops posting to an endpoint that keeps failing, so a broken integration
+/// doesn't spend the full retry backoff on every event of a run. While open,
+/// events go straight to the dead letters, and one delivery per
+/// [`PROBE_INTERVAL`] is let through to detect recovery.
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    last_probe: Option<Instant>,
+    /// Events sent to the dead letters without an attempt while open.
+    buffered: u64,
+}
+
+enum CircuitState {
+    Closed,
+    Probe,
+    Open,
+}
+
+/// Posts individual payment events to an HTTP endpoint. Every request carries
+/// an `X-Signature-256: sha256=<hex>` header with the HMAC-SHA256 of the body
+/// under the shared secret, so the receiver can verify where it came from.
+/// Every event has a `sequence` number, see [`next_sequence`].
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookClient {
+    url: String,
+    secret: String,
+    compress_dead_letters: bool,
+    breaker: Arc<Mutex<CircuitBreaker>>,
+    alerter: Option<Alerter>,
+    client: reqwest::Client,
+}
+
+impl WebhookClient {
+    pub fn new(url: String, secret: String) -> Self {
+        Self {
+            url,
+            secret,
+            compress_dead_letters: false,
+            breaker: Arc::new(Mutex::new(CircuitBreaker::default())),
+            alerter: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Alerts when the circuit opens.
+    pub fn with_alerter(mut self, alerter: Alerter) -> Self {
+        self.alerter = Some(alerter);
+        self
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.breaker
+            .lock()
+            .expect("Lock poisoned")
+            .opened_at
+            .is_some()
+    }
+
+    /// Number of events that went to the dead letters without an attempt
+    /// because the circuit was open.
+    pub fn buffered(&self) -> u64 {
+        self.breaker.lock().expect("Lock poisoned").buffered
+    }
+
+    fn circuit_state(&self) -> CircuitState {
+        let mut breaker = self.breaker.lock().expect("Lock poisoned");
+        if breaker.opened_at.is_none() {
+            return CircuitState::Closed;
+        }
+        if breaker
+            .last_probe
+            .is_none_or(|last_probe| last_probe.elapsed() >= PROBE_INTERVAL)
+        {
+            breaker.last_probe = Some(Instant::now());
+            return CircuitState::Probe;
+        }
+        breaker.buffered += 1;
+        CircuitState::Open
+    }
+
+    fn record_success(&self) {
+        let opened_at = {
+            let mut breaker = self.breaker.lock().expect("Lock poisoned");
+            breaker.consecutive_failures = 0;
+            breaker.last_probe = None;
+            breaker.opened_at.take()
+        };
+        if let Some(opened_at) = opened_at {
+            info!(
+                open_secs = opened_at.elapsed().as_secs(),
+                "Webhook endpoint recovered, closing circuit"
+            );
+        }
+    }
+
+    async fn record_failure(&self) {
+        let tripped = {
+            let mut breaker = self.breaker.lock().expect("Lock poisoned");
+            breaker.consecutive_failures += 1;
+            let tripped =
+                breaker.opened_at.is_none() && breaker.consecutive_failures >= TRIP_AFTER_FAILURES;
+            if tripped {
+                breaker.opened_at = Some(Instant::now());
+            }
+            tripped
+        };
+        if tripped {
+            warn!(
+                failures = TRIP_AFTER_FAILURES,
+                "Webhook endpoint keeps failing, opening circuit"
+            );
+            if let Some(alerter) = &self.alerter {
+                alerter
+                    .send(Alert::firing(
+                        "WebhookCircuitOpen",
+                        format!(
+                            "Webhook deliveries paused after {TRIP_AFTER_FAILURES} failed deliveries in a row, events are kept as dead letters"
+                        ),
+                    ))
+                    .await;
+            }
+        }
+    }
+
+    /// Stores dead letters compressed if `compressed` contains
+    /// [`ColumnClass::RawJson`].
+    pub fn with_compression(mut self, compressed: &[ColumnClass]) -> Self {
+        self.compress_dead_letters = compressed.contains(&ColumnClass::RawJson);
+        self
+    }
+
+    fn signature(&self, body: &[u8]) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(self.secret.as_bytes());
+        engine.input(body);
+        format!("sha256={}", Hmac::<sha256::Hash>::from_engine(engine))
+    }
+
+    async fn post(&self, body: &[u8]) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Signature-256", self.signature(body))
+            .body(body.to_vec())
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Posts `body` with exponential backoff between attempts and returns the
+    /// last error if all attempts failed.
+    async fn post_with_retries(&self, body: &[u8]) -> Result<(), String> {
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            match self.post(body).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < MAX_ATTEMPTS => {
+                    warn!(attempt, %err, "Webhook delivery failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+    }
+
+    /// Delivers one event. Events that can't be delivered are stored in
+    /// `webhook_dead_letters` instead of failing the run, and can be sent
+    /// again with `webhook-redrive`, with the same sequence number.
+    pub async fn deliver(
+        &self,
+        pg_client: &Client,
+        gateway_epoch: i32,
+        federation_id: &str,
+        log_id: i64,
+        mut event: Value,
+    ) -> anyhow::Result<()> {
+        event["sequence"] = next_sequence(pg_client, WEBHOOK_OUTPUT).await?.into();
+        let body = serde_json::to_vec(&event)?;
+        let (result, attempts) = match self.circuit_state() {
+            CircuitState::Closed => (self.post_with_retries(&body).await, MAX_ATTEMPTS),
+            CircuitState::Probe => (self.post(&body).await.map_err(|err| err.to_string()), 1),
+            CircuitState::Open => (Err("Circuit open".to_string()), 0),
+        };
+        let error = match result {
+            Ok(()) => {
+                self.record_success();
+                return Ok(());
+            }
+            Err(error) if attempts == 0 => error,
+            Err(error) => {
+                warn!(%error, log_id, "Webhook delivery failed permanently");
+                self.record_failure().await;
+                error
+            }
+        };
+
+        let (event, event_zstd) = if self.compress_dead_letters {
+            (None, Some(compression::compress_json(&event)?))
+        } else {
+            (Some(event), None)
+        };
+        pg_client.execute("INSERT INTO webhook_dead_letters (created_at, gateway_epoch, federation_id, log_id, event, event_zstd, attempts, error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        &[&Utc::now().naive_utc(), &gateway_epoch, &federation_id, &log_id, &event, &event_zstd, &(attempts as i32), &error]).await?;
+        Ok(())
+    }
+}
+
+/// Numbers the messages of `output` 1, 2, 3, ... in the order they are sent,
+/// across federations and gateway epochs, so consumers can detect a missed
+/// message independently of the log ids. The number is taken in the
+/// transaction that stores the event, so an ingest that is rolled back gives
+/// its numbers to the events that are stored instead: a consumer can see a
+/// number twice, but never a gap. Concurrent transactions that deliver to the same output wait for each
+/// other's commit.
+async fn next_sequence(pg_client: &Client, output: &str) -> anyhow::Result<i64> {
+    Ok(pg_client
+        .query_one(
+            "INSERT INTO output_sequences (output, last_sequence) VALUES ($1, 1) ON CONFLICT (output) DO UPDATE SET last_sequence = output_sequences.last_sequence + 1 RETURNING last_sequence",
+            &[&output],
+        )
+        .await?
+        .get(0))
+}
+
+/// Sends all dead letters that haven't been delivered yet again, oldest
+/// first. Delivered ones are kept with `delivered_at` set.
+pub(crate) async fn redrive(pool: &Pool, webhook: &WebhookClient) -> anyhow::Result<()> {
+    let pg_client = pool.get().await?;
+    let rows = pg_client
+        .query(
+            "SELECT id, event, event_zstd FROM webhook_dead_letters WHERE delivered_at IS NULL ORDER BY id",
+            &[],
+        )
+        .await?;
+
+    let mut delivered = 0;
+    for row in &rows {
+        let id: i64 = row.get(0);
+        let event = compression::read_json(row, 1, 2)?;
+        match webhook
+            .post_with_retries(&serde_json::to_vec(&event)?)
+            .await
+        {
+            Ok(()) => {
+                pg_client
+                    .execute(
+                        "UPDATE webhook_dead_letters SET delivered_at = $2 WHERE id = $1",
+                        &[&id, &Utc::now().naive_utc()],
+                    )
+                    .await?;
+                delivered += 1;
+            }
+            Err(error) => {
+                pg_client
+                    .execute(
+                        "UPDATE webhook_dead_letters SET attempts = attempts + $2, error = $3 WHERE id = $1",
+                        &[&id, &(MAX_ATTEMPTS as i32), &error],
+                    )
+                    .await?;
+            }
+        }
+    }
+
+    info!(
+        delivered,
+        failed = rows.len() - delivered,
+        "Redrove webhook dead letters"
+    );
+    println!("Delivered {delivered} of {} dead letters", rows.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_sequence;
+    use crate::test_db;
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sequence_has_no_gaps_after_a_rollback() {
+        let pg_client = test_db::connect().await;
+        assert_eq!(next_sequence(&pg_client, "webhook").await.unwrap(), 1);
+
+        pg_client.batch_execute("BEGIN").await.unwrap();
+        assert_eq!(next_sequence(&pg_client, "webhook").await.unwrap(), 2);
+        pg_client.batch_execute("ROLLBACK").await.unwrap();
+
+        assert_eq!(next_sequence(&pg_client, "webhook").await.unwrap(), 2);
+        assert_eq!(next_sequence(&pg_client, "other").await.unwrap(), 1);
+    }
+}